@@ -0,0 +1,151 @@
+use broadcast_node::{BroadcastNodeTrait, Message, MessageBody, Node, NodeId, NodeTrait};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+const BROADCASTS: u32 = 1_000;
+
+// Every node is a neighbour of every other one, the worst case for fanout
+fn node_with_topology(cluster_size: usize) -> Node<u32> {
+    let ids: Vec<NodeId> = (0..cluster_size)
+        .map(|i| NodeId::new(format!("n{i}")))
+        .collect();
+    let topology: HashMap<NodeId, Vec<NodeId>> = ids
+        .iter()
+        .map(|id| {
+            let neighbours = ids.iter().filter(|n| *n != id).cloned().collect();
+            (id.clone(), neighbours)
+        })
+        .collect();
+
+    Node::<u32>::new()
+        .with_membership(ids[0].clone(), ids.clone())
+        .with_topology(topology)
+}
+
+fn broadcast(value: u32) -> Message {
+    Message {
+        src: "c1".into(),
+        dest: "n0".into(),
+        body: MessageBody::broadcast {
+            message: value,
+            msg_id: value,
+        },
+    }
+}
+
+// Fresh values only, so each call pays for the store insert, the fanout peer
+// selection and both outbox inserts on top of the ack
+fn bench_handle_broadcast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_broadcast_message");
+    group.throughput(Throughput::Elements(BROADCASTS as u64));
+    for cluster_size in [5, 25, 100] {
+        let node = node_with_topology(cluster_size);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(cluster_size),
+            &node,
+            |b, node| {
+                b.iter_batched(
+                    || (node.clone(), mpsc::channel()),
+                    |(mut node, (tx, rx))| {
+                        for value in 0..BROADCASTS {
+                            node.handle_broadcast_message(broadcast(value), tx.clone())
+                                .unwrap();
+                        }
+                        (node, rx)
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+// The same values as bench_handle_broadcast in a single bulk_broadcast, one
+// reply and one fanout pass instead of one per value
+fn bench_bulk_broadcast(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_bulk_broadcast_message");
+    group.throughput(Throughput::Elements(BROADCASTS as u64));
+    for cluster_size in [5, 25, 100] {
+        let node = node_with_topology(cluster_size);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(cluster_size),
+            &node,
+            |b, node| {
+                b.iter_batched(
+                    || (node.clone(), mpsc::channel()),
+                    |(mut node, (tx, rx))| {
+                        let bulk = Message {
+                            src: "c1".into(),
+                            dest: "n0".into(),
+                            body: MessageBody::bulk_broadcast {
+                                msg_id: 1,
+                                messages: (0..BROADCASTS).collect(),
+                            },
+                        };
+                        node.handle_bulk_broadcast_message(bulk, tx).unwrap();
+                        (node, rx)
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+// Every peer has a deep retry outbox spread over many in-flight batches, and
+// each gossip_ok acknowledges one of them. Per-ack cost should stay flat as
+// the outbox grows.
+fn bench_gossip_ok(c: &mut Criterion) {
+    const PEERS: usize = 25;
+    let mut group = c.benchmark_group("handle_gossip_ok_message");
+    for batches_per_peer in [10, 100, 1_000] {
+        let mut node = node_with_topology(PEERS);
+        for b in 0..batches_per_peer {
+            for value in (b * 10)..(b * 10 + 10) {
+                node.handle_broadcast_message(broadcast(value as u32), mpsc::channel().0)
+                    .unwrap();
+            }
+            node.fanout_messages(mpsc::channel().0).unwrap();
+        }
+        let acks: Vec<Message> = node
+            .in_flight_batches()
+            .map(|(peer, msg_id)| Message {
+                src: peer.clone(),
+                dest: "n0".into(),
+                body: MessageBody::gossip_ok {
+                    in_reply_to: Some(msg_id),
+                },
+            })
+            .collect();
+        group.throughput(Throughput::Elements(acks.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(batches_per_peer),
+            &(node, acks),
+            |b, (node, acks)| {
+                b.iter_batched(
+                    || (node.clone(), acks.clone()),
+                    |(mut node, acks)| {
+                        let (tx, _rx) = mpsc::channel();
+                        for ack in acks {
+                            node.handle_gossip_ok_message(ack, tx.clone()).unwrap();
+                        }
+                        node
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_handle_broadcast,
+    bench_bulk_broadcast,
+    bench_gossip_ok
+);
+criterion_main!(benches);
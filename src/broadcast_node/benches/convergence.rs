@@ -0,0 +1,111 @@
+use broadcast_node::{Config, GossipMode, Host, Message, MessageBody, NodeId};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::hint::black_box;
+use std::sync::mpsc;
+
+const NODES: usize = 16;
+const MAX_ROUNDS: usize = 200;
+
+struct Outcome {
+    rounds: usize,
+    messages: u64,
+}
+
+fn message(src: &str, dest: &NodeId, body: MessageBody) -> Message {
+    Message {
+        src: src.into(),
+        dest: dest.clone(),
+        body,
+    }
+}
+
+// Two halves under a partition each receive their own broadcasts, then the
+// partition heals and we count sync rounds until every store matches.
+fn converge_after_heal(config: Config) -> Outcome {
+    let ids: Vec<NodeId> = (0..NODES).map(|i| NodeId::new(format!("n{i}"))).collect();
+    let (tx, _rx) = mpsc::channel();
+    let mut host = Host::<u32>::new(config);
+    for id in &ids {
+        let init = MessageBody::init {
+            msg_id: 0,
+            node_id: id.clone(),
+            node_ids: ids.clone(),
+        };
+        host.route(message("c0", id, init), tx.clone()).unwrap();
+    }
+
+    let (left, right) = ids.split_at(NODES / 2);
+    host.partition(&[left, right]);
+    for (value, id) in ids.iter().enumerate() {
+        let broadcast = MessageBody::broadcast {
+            message: value as u32,
+            msg_id: value as u32,
+        };
+        host.route(message("c1", id, broadcast), tx.clone())
+            .unwrap();
+    }
+    host.sync_round(tx.clone()).unwrap();
+
+    host.heal();
+    let before = host.delivered();
+    let mut rounds = 0;
+    while !host.converged() && rounds < MAX_ROUNDS {
+        host.sync_round(tx.clone()).unwrap();
+        rounds += 1;
+    }
+    Outcome {
+        rounds,
+        messages: host.delivered() - before,
+    }
+}
+
+// Peer choice is random, so report the average over a few runs
+fn report(label: &str, config: &Config) {
+    let runs = 20;
+    let outcomes: Vec<Outcome> = (0..runs)
+        .map(|_| converge_after_heal(config.clone()))
+        .collect();
+    let rounds = outcomes.iter().map(|o| o.rounds).sum::<usize>() as f64 / runs as f64;
+    let messages = outcomes.iter().map(|o| o.messages).sum::<u64>() as f64 / runs as f64;
+    println!("{NODES} nodes, {label}: {rounds:.1} rounds, {messages:.0} messages to converge");
+}
+
+fn bench_convergence(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sync_convergence_after_heal");
+    for sync_peers in [1, 2, 3, 4] {
+        let config = Config {
+            sync_peers,
+            ..Config::default()
+        };
+        report(&format!("sync_peers={sync_peers}"), &config);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(sync_peers),
+            &config,
+            |b, config| b.iter(|| black_box(converge_after_heal(config.clone()))),
+        );
+    }
+    group.finish();
+}
+
+// Same partition and heal with each GossipMode. Every mode sends one sync
+// and one sync_ok per peer contacted, so message counts differ only through
+// the rounds taken.
+fn bench_gossip_mode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sync_convergence_by_gossip_mode");
+    for mode in [GossipMode::Push, GossipMode::Pull, GossipMode::PushPull] {
+        let config = Config {
+            gossip_mode: mode,
+            ..Config::default()
+        };
+        report(&format!("{mode:?}"), &config);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{mode:?}")),
+            &config,
+            |b, config| b.iter(|| black_box(converge_after_heal(config.clone()))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_convergence, bench_gossip_mode);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+use broadcast_node::{
+    BroadcastNodeTrait, Message, MessageBody, Node, NodeTrait, Store, ValueStore,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::mpsc;
+
+// Each value reaches a node once from a client and once more from every
+// neighbour that gossips it on, so most inserts under fanout are duplicates
+const NEIGHBOURS: u32 = 4;
+const SIZES: [u32; 3] = [1_000, 10_000, 100_000];
+
+// Values scattered over the u32 range in arrival order, each delivered
+// NEIGHBOURS + 1 times with the repeats trailing the first sighting
+fn deliveries(values: u32) -> Vec<u32> {
+    let scatter = |v: u32| v.wrapping_mul(2_654_435_761);
+    (0..=NEIGHBOURS)
+        .flat_map(|round| (0..values).map(move |v| scatter((v + round * 7) % values)))
+        .collect()
+}
+
+fn filled<S: Store<u32>>(mut store: S, values: u32) -> S {
+    for value in deliveries(values) {
+        store.insert(value);
+    }
+    store
+}
+
+fn bench_store<S>(c: &mut Criterion, name: &str, empty: fn() -> S)
+where
+    S: Store<u32> + Clone,
+{
+    let mut group = c.benchmark_group(format!("store_insert/{name}"));
+    for values in SIZES {
+        let arrivals = deliveries(values);
+        group.throughput(Throughput::Elements(arrivals.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(values),
+            &arrivals,
+            |b, arrivals| {
+                b.iter_batched(
+                    empty,
+                    |mut store| {
+                        for value in arrivals {
+                            store.insert(*value);
+                        }
+                        store
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group(format!("store_read_all/{name}"));
+    for values in SIZES {
+        let store = filled(empty(), values);
+        group.bench_with_input(BenchmarkId::from_parameter(values), &store, |b, store| {
+            b.iter(|| store.read_all())
+        });
+    }
+    group.finish();
+}
+
+// A client read end to end, on a node holding `values`. Only ValueStore backs
+// a Node, the read_all groups above show what the other stores would cost.
+fn bench_handle_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handle_read_message");
+    for values in SIZES {
+        let mut node = Node::<u32>::new()
+            .with_membership("n1", vec!["n1".into(), "n2".into()])
+            .with_store(deliveries(values));
+        let (tx, rx) = mpsc::channel();
+        group.throughput(Throughput::Elements(values as u64));
+        group.bench_function(BenchmarkId::from_parameter(values), |b| {
+            b.iter(|| {
+                let read = Message {
+                    src: "c1".into(),
+                    dest: "n1".into(),
+                    body: MessageBody::read {
+                        msg_id: 1,
+                        quorum: false,
+                        offset: None,
+                        limit: None,
+                        from: None,
+                    },
+                };
+                node.handle_read_message(read, tx.clone()).unwrap();
+                rx.try_recv().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_stores(c: &mut Criterion) {
+    bench_store(c, "ValueStore", ValueStore::<u32>::new);
+    bench_store(c, "HashSet", HashSet::<u32>::new);
+    bench_store(c, "BTreeSet", BTreeSet::<u32>::new);
+    #[cfg(feature = "bitmap-sync")]
+    bench_store(c, "Bitmap", broadcast_node::bitmap::Bitmap::<u32>::new);
+}
+
+criterion_group!(benches, bench_stores, bench_handle_read);
+criterion_main!(benches);
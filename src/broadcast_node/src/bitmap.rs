@@ -0,0 +1,538 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::store::Store;
+
+// A set of u32s laid out like a roaring bitmap: values are grouped by their
+// high 16 bits, and each group (a container) stores its low 16 bits either as
+// a sorted array or, once it holds more than ARRAY_MAX values, as a 65536 bit
+// bitmap. Dense value sets shrink to about a bit per value, and difference
+// and union work a container at a time, word by word between two bitmaps.
+// With the `bitmap-sync` feature it is the set behind ValueStore, and a sync
+// payload travels as its containers, base64 in the JSON.
+//
+// The `roaring` crate isn't available to this build, so this is a small
+// implementation of the same idea and not roaring's serialization format.
+// Only nodes built with the feature can read the payload.
+//
+// On the wire, per container: key u16, kind u8 (0 array, 1 bitmap), len-1
+// u16, then len u16s or 8192 bitmap bytes, all little endian.
+const ARRAY_MAX: usize = 4096;
+const WORDS: usize = 65536 / 64;
+const BITMAP_BYTES: usize = 65536 / 8;
+
+// Holds `Data` as the u32 it converts to, so ValueStore can keep one for
+// whatever value type a node uses
+pub struct Bitmap<Data = u32> {
+    containers: BTreeMap<u16, Container>,
+    len: usize,
+    data: PhantomData<fn() -> Data>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Container {
+    // sorted and at most ARRAY_MAX long
+    Array(Vec<u16>),
+    // the words and how many bits are set, always more than ARRAY_MAX
+    Bits(Box<[u64; WORDS]>, usize),
+}
+
+impl Container {
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bits(_, len) => *len,
+        }
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low).is_ok(),
+            Container::Bits(words, _) => words[low as usize / 64] & 1 << (low % 64) != 0,
+        }
+    }
+
+    fn insert(&mut self, low: u16) -> bool {
+        let inserted = match self {
+            Container::Array(values) => match values.binary_search(&low) {
+                Ok(_) => false,
+                Err(at) => {
+                    values.insert(at, low);
+                    true
+                }
+            },
+            Container::Bits(words, len) => {
+                let word = &mut words[low as usize / 64];
+                let bit = 1 << (low % 64);
+                let absent = *word & bit == 0;
+                *word |= bit;
+                *len += absent as usize;
+                absent
+            }
+        };
+        self.fit();
+        inserted
+    }
+
+    fn remove(&mut self, low: u16) -> bool {
+        let removed = match self {
+            Container::Array(values) => match values.binary_search(&low) {
+                Ok(at) => {
+                    values.remove(at);
+                    true
+                }
+                Err(_) => false,
+            },
+            Container::Bits(words, len) => {
+                let word = &mut words[low as usize / 64];
+                let bit = 1 << (low % 64);
+                let present = *word & bit != 0;
+                *word &= !bit;
+                *len -= present as usize;
+                present
+            }
+        };
+        self.fit();
+        removed
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Container::Array(values) => Box::new(values.iter().copied()),
+            Container::Bits(words, _) => {
+                Box::new(words.iter().enumerate().flat_map(|(i, word)| {
+                    (0..64)
+                        .filter(move |bit| word & 1 << bit != 0)
+                        .map(move |bit| (i * 64 + bit) as u16)
+                }))
+            }
+        }
+    }
+
+    fn to_words(&self) -> Box<[u64; WORDS]> {
+        match self {
+            Container::Array(values) => {
+                let mut words = Box::new([0u64; WORDS]);
+                for low in values {
+                    words[*low as usize / 64] |= 1 << (low % 64);
+                }
+                words
+            }
+            Container::Bits(words, _) => words.clone(),
+        }
+    }
+
+    // Values in self but not in `other`, None once nothing is left
+    fn difference(&self, other: &Container) -> Option<Container> {
+        let left = match (self, other) {
+            (Container::Bits(ours, _), Container::Bits(theirs, _)) => {
+                let mut words = Box::new([0u64; WORDS]);
+                let mut len = 0;
+                for (word, (ours, theirs)) in words.iter_mut().zip(ours.iter().zip(theirs.iter())) {
+                    *word = ours & !theirs;
+                    len += word.count_ones() as usize;
+                }
+                Container::Bits(words, len)
+            }
+            (Container::Bits(ours, len), Container::Array(theirs)) => {
+                let mut words = ours.clone();
+                let mut len = *len;
+                for low in theirs {
+                    let bit = 1 << (low % 64);
+                    let word = &mut words[*low as usize / 64];
+                    len -= (*word & bit != 0) as usize;
+                    *word &= !bit;
+                }
+                Container::Bits(words, len)
+            }
+            (Container::Array(ours), theirs) => Container::Array(
+                ours.iter()
+                    .copied()
+                    .filter(|low| !theirs.contains(*low))
+                    .collect(),
+            ),
+        };
+        (left.len() > 0).then(|| left.fitted())
+    }
+
+    fn union_with(&mut self, other: &Container) {
+        let merged = match (&*self, other) {
+            (Container::Array(ours), Container::Array(theirs))
+                if ours.len() + theirs.len() <= ARRAY_MAX =>
+            {
+                let mut values = Vec::with_capacity(ours.len() + theirs.len());
+                values.extend_from_slice(ours);
+                values.extend_from_slice(theirs);
+                values.sort_unstable();
+                values.dedup();
+                Container::Array(values)
+            }
+            (ours, theirs) => {
+                let mut words = ours.to_words();
+                let mut len = 0;
+                match theirs {
+                    Container::Bits(theirs, _) => {
+                        for (word, theirs) in words.iter_mut().zip(theirs.iter()) {
+                            *word |= theirs;
+                        }
+                    }
+                    Container::Array(theirs) => {
+                        for low in theirs {
+                            words[*low as usize / 64] |= 1 << (low % 64);
+                        }
+                    }
+                }
+                for word in words.iter() {
+                    len += word.count_ones() as usize;
+                }
+                Container::Bits(words, len)
+            }
+        };
+        *self = merged.fitted();
+    }
+
+    // Switches to whichever kind suits how many values there are
+    fn fit(&mut self) {
+        if (self.len() > ARRAY_MAX) == matches!(self, Container::Bits(..)) {
+            return;
+        }
+        *self = match self {
+            Container::Array(_) => Container::Bits(self.to_words(), self.len()),
+            Container::Bits(..) => Container::Array(self.iter().collect()),
+        };
+    }
+
+    fn fitted(mut self) -> Self {
+        self.fit();
+        self
+    }
+}
+
+impl<Data> Bitmap<Data> {
+    pub fn new() -> Self {
+        Self {
+            containers: BTreeMap::new(),
+            len: 0,
+            data: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Values in self but not in `other`
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut left = Self::new();
+        for (key, ours) in &self.containers {
+            let container = match other.containers.get(key) {
+                Some(theirs) => ours.difference(theirs),
+                None => Some(ours.clone()),
+            };
+            if let Some(container) = container {
+                left.len += container.len();
+                left.containers.insert(*key, container);
+            }
+        }
+        left
+    }
+
+    pub fn union_with(&mut self, other: &Self) {
+        for (key, theirs) in &other.containers {
+            match self.containers.get_mut(key) {
+                Some(ours) => {
+                    self.len -= ours.len();
+                    ours.union_with(theirs);
+                    self.len += ours.len();
+                }
+                None => {
+                    self.len += theirs.len();
+                    self.containers.insert(*key, theirs.clone());
+                }
+            }
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::new();
+        for (key, container) in &self.containers {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.push(matches!(container, Container::Bits(..)) as u8);
+            bytes.extend_from_slice(&((container.len() - 1) as u16).to_le_bytes());
+            match container {
+                Container::Array(values) => {
+                    for low in values {
+                        bytes.extend_from_slice(&low.to_le_bytes());
+                    }
+                }
+                Container::Bits(words, _) => {
+                    for word in words.iter() {
+                        bytes.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+            }
+        }
+        base64_encode(&bytes)
+    }
+
+    // Reads the containers straight into a set, nothing is decoded value by
+    // value
+    pub fn decode(encoded: &str) -> Result<Self> {
+        let bytes = base64_decode(encoded)?;
+        let mut set = Self::new();
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let header = take(&mut rest, 5).context("truncated container header")?;
+            let key = u16::from_le_bytes([header[0], header[1]]);
+            let len = u16::from_le_bytes([header[3], header[4]]) as usize + 1;
+            let container = match header[2] {
+                0 => {
+                    let body = take(&mut rest, len * 2).context("truncated array container")?;
+                    let values: Vec<u16> = body
+                        .chunks_exact(2)
+                        .map(|low| u16::from_le_bytes([low[0], low[1]]))
+                        .collect();
+                    ensure!(
+                        values.windows(2).all(|pair| pair[0] < pair[1]),
+                        "array container {key} is not sorted"
+                    );
+                    Container::Array(values)
+                }
+                1 => {
+                    let body =
+                        take(&mut rest, BITMAP_BYTES).context("truncated bitmap container")?;
+                    let mut words = Box::new([0u64; WORDS]);
+                    for (word, chunk) in words.iter_mut().zip(body.chunks_exact(8)) {
+                        *word = u64::from_le_bytes(chunk.try_into()?);
+                    }
+                    let ones: usize = words.iter().map(|word| word.count_ones() as usize).sum();
+                    if ones != len {
+                        bail!("bitmap container claims {len} values, holds {ones}");
+                    }
+                    Container::Bits(words, len)
+                }
+                kind => bail!("unknown container kind {kind}"),
+            };
+            ensure!(
+                set.containers.insert(key, container.fitted()).is_none(),
+                "container {key} appears twice"
+            );
+            set.len += len;
+        }
+        Ok(set)
+    }
+}
+
+impl<Data: Copy + From<u32> + Into<u32>> Bitmap<Data> {
+    // Returns false if the value was already present
+    pub fn insert(&mut self, value: Data) -> bool {
+        let value: u32 = value.into();
+        let inserted = self
+            .containers
+            .entry((value >> 16) as u16)
+            .or_insert_with(|| Container::Array(Vec::new()))
+            .insert(value as u16);
+        self.len += inserted as usize;
+        inserted
+    }
+
+    pub fn remove(&mut self, value: &Data) -> bool {
+        let value: u32 = (*value).into();
+        let key = (value >> 16) as u16;
+        let Some(container) = self.containers.get_mut(&key) else {
+            return false;
+        };
+        let removed = container.remove(value as u16);
+        if container.len() == 0 {
+            self.containers.remove(&key);
+        }
+        self.len -= removed as usize;
+        removed
+    }
+
+    pub fn contains(&self, value: &Data) -> bool {
+        let value: u32 = (*value).into();
+        self.containers
+            .get(&((value >> 16) as u16))
+            .is_some_and(|container| container.contains(value as u16))
+    }
+
+    // Ascending
+    pub fn iter(&self) -> impl Iterator<Item = Data> + '_ {
+        self.containers.iter().flat_map(|(key, container)| {
+            container
+                .iter()
+                .map(move |low| Data::from((*key as u32) << 16 | low as u32))
+        })
+    }
+}
+
+impl<Data> Clone for Bitmap<Data> {
+    fn clone(&self) -> Self {
+        Self {
+            containers: self.containers.clone(),
+            len: self.len,
+            data: PhantomData,
+        }
+    }
+}
+
+impl<Data> Default for Bitmap<Data> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Data> PartialEq for Bitmap<Data> {
+    fn eq(&self, other: &Self) -> bool {
+        self.containers == other.containers
+    }
+}
+
+impl<Data> fmt::Debug for Bitmap<Data> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitmap")
+            .field("len", &self.len)
+            .field("containers", &self.containers.len())
+            .finish()
+    }
+}
+
+impl<Data: Copy + From<u32> + Into<u32>> Extend<Data> for Bitmap<Data> {
+    fn extend<I: IntoIterator<Item = Data>>(&mut self, values: I) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
+impl<Data: Copy + From<u32> + Into<u32>> FromIterator<Data> for Bitmap<Data> {
+    fn from_iter<I: IntoIterator<Item = Data>>(values: I) -> Self {
+        let mut set = Self::new();
+        set.extend(values);
+        set
+    }
+}
+
+// Sorted like a BTreeSet, so benches/store.rs can compare it with the others
+impl Store<u32> for Bitmap<u32> {
+    fn insert(&mut self, value: u32) -> bool {
+        Bitmap::insert(self, value)
+    }
+
+    fn contains(&self, value: &u32) -> bool {
+        Bitmap::contains(self, value)
+    }
+
+    fn len(&self) -> usize {
+        Bitmap::len(self)
+    }
+
+    fn read_all(&self) -> Vec<u32> {
+        self.iter().collect()
+    }
+}
+
+pub fn encode(values: impl IntoIterator<Item = u32>) -> String {
+    values.into_iter().collect::<Bitmap>().encode()
+}
+
+pub fn decode(encoded: &str) -> Result<Vec<u32>> {
+    let mut values = Vec::new();
+    decode_each(encoded, |value| values.push(value))?;
+    Ok(values)
+}
+
+// Hands each value to `on_value` in ascending order as its container is read,
+// returns how many there were. A corrupt container fails the decode after
+// the values before it were handed out.
+pub fn decode_each(encoded: &str, mut on_value: impl FnMut(u32)) -> Result<usize> {
+    let bytes = base64_decode(encoded)?;
+    let mut count = 0;
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        let header = take(&mut rest, 5).context("truncated container header")?;
+        let key = u16::from_le_bytes([header[0], header[1]]) as u32;
+        let len = u16::from_le_bytes([header[3], header[4]]) as usize + 1;
+        match header[2] {
+            0 => {
+                let body = take(&mut rest, len * 2).context("truncated array container")?;
+                for low in body.chunks_exact(2) {
+                    on_value(key << 16 | u16::from_le_bytes([low[0], low[1]]) as u32);
+                }
+            }
+            1 => {
+                let body = take(&mut rest, BITMAP_BYTES).context("truncated bitmap container")?;
+                let ones: usize = body.iter().map(|byte| byte.count_ones() as usize).sum();
+                if ones != len {
+                    bail!("bitmap container claims {len} values, holds {ones}");
+                }
+                for (byte_index, byte) in body.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (1 << bit) != 0 {
+                            on_value(key << 16 | (byte_index * 8 + bit) as u32);
+                        }
+                    }
+                }
+            }
+            kind => bail!("unknown container kind {kind}"),
+        }
+        count += len;
+    }
+    Ok(count)
+}
+
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if rest.len() < n {
+        return None;
+    }
+    let (head, tail) = rest.split_at(n);
+    *rest = tail;
+    Some(head)
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(encoded: &str) -> Result<Vec<u8>> {
+    let encoded = encoded.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in encoded.bytes() {
+        let digit = ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .with_context(|| format!("invalid base64 character {:?}", c as char))?;
+        n = n << 6 | digit as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
@@ -1,7 +1,16 @@
-use crate::{BroadcastNodeTrait, Message, MessageBody, Node};
+#[cfg(feature = "bitmap-sync")]
+use crate::bitmap;
+use crate::codec::{peek_header, stream_sync_ok};
+#[cfg(feature = "signing")]
+use crate::signing;
+use crate::{
+    liveness, membership, quorum, repair, BroadcastNodeTrait, Consistency, GossipMode, Message,
+    MessageBody, Node, NodeId, SyncPayloadStrategy, SyncPeerWeights, Task, ValueSet,
+};
 use anyhow::Result;
 use rand::seq::IndexedRandom;
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
 use std::sync::mpsc::Sender;
 
@@ -15,33 +24,28 @@ where
     Node<Data>: BroadcastNodeTrait,
 {
     if let MessageBody::broadcast { message, msg_id } = msg.body {
+        node.stats.broadcasts = node.stats.broadcasts.saturating_add(1);
         let reply = Message {
             src: msg.dest.clone(),
             dest: msg.src.clone(),
             body: MessageBody::broadcast_ok {
-                in_reply_to: msg_id,
+                in_reply_to: Some(msg_id),
                 msg_id: node.get_and_increment_msg_id(),
             },
         };
 
         // Always ack client/peer broadcast RPCs, but only fan out newly seen values.
-        if node.insert_if_absent(Data::from(message)).is_none() {
+        if node
+            .insert_if_absent(Data::from(message), &msg.src)
+            .is_none()
+        {
             reply.send(tx)?;
             return Ok(());
         }
 
-        let neighbours: Option<&Vec<String>> = node.topology.get(&node.id);
-        if let Some(neighbours) = neighbours {
-            let fanout_peers: Vec<String> = neighbours
-                .iter()
-                .filter(|n| **n != msg.src)
-                .take(2)
-                .cloned()
-                .collect();
-            for peer in fanout_peers {
-                node.add_to_outbox(crate::OutboxKind::FanoutMsg, &peer, message)?;
-                node.add_to_outbox(crate::OutboxKind::RetryMsg, &peer, message)?;
-            }
+        for peer in fanout_peers(node, &msg.src) {
+            node.add_to_outbox(crate::OutboxKind::FanoutMsg, &peer, message)?;
+            node.add_to_outbox(crate::OutboxKind::RetryMsg, &peer, message)?;
         }
 
         reply.send(tx)?;
@@ -49,6 +53,104 @@ where
     Ok(())
 }
 
+// Like a broadcast per value, but the new values are queued for fanout
+// together, so each peer gets them in one gossip batch, and there's one reply
+pub fn handle_bulk_broadcast_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::bulk_broadcast {
+        msg_id,
+        ref messages,
+    } = msg.body
+    {
+        node.stats.broadcasts = node.stats.broadcasts.saturating_add(messages.len() as u64);
+        let mut newly_seen = Vec::new();
+        for &m in messages {
+            if node.insert_if_absent(Data::from(m), &msg.src).is_some() {
+                newly_seen.push(m);
+            }
+        }
+        forward_new_values(node, &msg.src, &newly_seen)?;
+        let payload = MessageBody::bulk_broadcast_ok {
+            msg_id: node.get_and_increment_msg_id(),
+            in_reply_to: msg_id,
+        };
+        msg.into_reply(payload).send(tx)?;
+    }
+    Ok(())
+}
+
+// Live topology neighbours a value from `src` is forwarded to, plus
+// `fanout_random_peers` other nodes drawn at random so a value still spreads
+// when the topology is poorly connected. Random picks never repeat a
+// neighbour. Client ids never belong in a topology, but one that slips in is
+// skipped too. Some topologies leave nodes out, a node without an entry of
+// its own forwards to every other node instead so its values still spread.
+// Whatever that adds up to, `max_fanout` caps it with a random subset and
+// leaves the rest to sync.
+fn fanout_peers<Data>(node: &mut Node<Data>, src: &NodeId) -> Vec<NodeId>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let live = |n: &&NodeId| *n != src && **n != node.id && !n.is_client() && node.peer_is_up(n);
+    let mut peers: Vec<NodeId> = match node.topology.get(&node.id) {
+        Some(neighbours) => neighbours
+            .iter()
+            .filter(live)
+            .take(node.config.fanout)
+            .cloned()
+            .collect(),
+        None => node.node_ids.iter().filter(live).cloned().collect(),
+    };
+    if node.config.fanout_random_peers > 0 && node.topology.contains_key(&node.id) {
+        let others: Vec<NodeId> = node
+            .node_ids
+            .iter()
+            .filter(live)
+            .filter(|n| !peers.contains(n))
+            .cloned()
+            .collect();
+        let extra = others.choose_multiple(&mut node.rng, node.config.fanout_random_peers);
+        peers.extend(extra.cloned());
+    }
+    match node.config.max_fanout {
+        Some(max) if peers.len() > max.get() => peers
+            .choose_multiple(&mut node.rng, max.get())
+            .cloned()
+            .collect(),
+        _ => peers,
+    }
+}
+
+// Queues values we just learned from `src` for our fanout peers
+pub(crate) fn forward_new_values<Data>(
+    node: &mut Node<Data>,
+    src: &NodeId,
+    values: &[u32],
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if values.is_empty() {
+        return Ok(());
+    }
+    for peer in fanout_peers(node, src) {
+        for value in values {
+            node.add_to_outbox(crate::OutboxKind::FanoutMsg, &peer, *value)?;
+            node.add_to_outbox(crate::OutboxKind::RetryMsg, &peer, *value)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn handle_read_message<Data>(
     node: &mut Node<Data>,
     msg: Message,
@@ -58,19 +160,55 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
-    if let MessageBody::read { msg_id } = msg.body {
-        let messages: Vec<u32> = node.read();
+    if let MessageBody::read {
+        msg_id,
+        quorum,
+        offset,
+        limit,
+        ref from,
+    } = msg.body
+    {
+        if let Some(peer) = from.clone().filter(|peer| *peer != node.id) {
+            return quorum::start_forwarded_read(node, msg.src, msg_id, peer, offset, limit, tx);
+        }
+        // peers reading us for their own quorum or repair get the local store,
+        // as do paginated reads
+        let consistency = if quorum {
+            Consistency::Quorum
+        } else if offset.is_some() || limit.is_some() || node.node_ids.contains(&msg.src) {
+            Consistency::Local
+        } else {
+            node.config.consistency
+        };
+        match consistency {
+            Consistency::Quorum => return quorum::start_quorum_read(node, msg.src, msg_id, tx),
+            Consistency::ReadRepair => quorum::start_repair_read(node, tx.clone())?,
+            Consistency::Local => {}
+        }
+        let (messages, next_offset) = match (offset, limit) {
+            (None, None) => (node.read(), None),
+            (offset, limit) => node.read_page(offset.unwrap_or(0), limit.unwrap_or(usize::MAX)),
+        };
         let payload = MessageBody::read_ok {
             messages,
             in_reply_to: msg_id,
             msg_id: node.get_and_increment_msg_id(),
+            next_offset,
+            signature: None,
         };
+        let payload = signed(node, &msg.src, payload);
         let reply = msg.into_reply(payload);
         reply.send(tx)?;
     }
     Ok(())
 }
 
+// A new topology only affects values broadcast after it. Fanout peers are
+// picked once per value when it arrives and queued in per-peer outboxes, so
+// values already queued still go to the neighbours they were picked for.
+// Handlers take `&mut Node`, so an update can't land halfway through a
+// broadcast either. Anything that runs handlers on several threads has to
+// keep that, e.g. by putting the node behind one lock.
 pub fn handle_topology_message<Data>(
     node: &mut Node<Data>,
     msg: Message,
@@ -105,55 +243,197 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
+    if !node.config.anti_entropy {
+        return node.handle_unsupported_message(msg, tx);
+    }
+    if !is_authentic(node, &msg) {
+        anyhow::bail!("dropping sync from {} with a bad signature", msg.src);
+    }
     if let MessageBody::sync {
         msg_id,
         ref messages,
+        ref tombstones,
+        checksum,
+        seq,
+        mode,
+        ref bitmap,
+        ..
     } = msg.body
     {
-        let messages: HashSet<Data> = messages.into_iter().map(|m| Data::from(*m)).collect();
-        let i_have: HashSet<Data> = node.store.difference(&messages).cloned().collect();
-        let they_have: HashSet<Data> = messages.difference(&node.store).cloned().collect();
-
-        let i_have: Vec<u32> = i_have.into_iter().map(|m| Data::into(m)).collect();
-        //insert the data we dont have
-        for data in they_have {
-            node.store.insert(data);
+        let theirs: ValueSet<Data> = sync_set(messages, bitmap.as_deref())?;
+        node.merge_tombstones(&msg.src, tombstones);
+        if checksum.is_some() && checksum == Some(node.store_checksum()) {
+            let payload = MessageBody::sync_ok {
+                msg_id: node.get_and_increment_msg_id(),
+                in_reply_to: Some(msg_id),
+                messages: vec![],
+                tombstones: node.tombstones.snapshot(),
+                seq,
+                more: false,
+                bitmap: None,
+                signature: None,
+            };
+            let payload = signed(node, &msg.src, payload);
+            return msg.into_reply(payload).send(tx);
+        }
+        let mut i_have = if theirs.is_empty() {
+            // a pull, there is nothing to merge or diff against
+            node.read()
+        } else {
+            let (i_have, they_have) = split_difference(node.store.as_set(), &theirs);
+            //insert the data we dont have
+            if mode.pushes() {
+                for data in they_have {
+                    node.insert_if_absent(data, &msg.src);
+                }
+            }
+            i_have.into_iter().map(|m| Data::into(m)).collect()
+        };
+        // a push only wants an ack
+        if !mode.pulls() {
+            i_have.clear();
         }
-        //send back the data they dont have
+        let more = node
+            .config
+            .sync_reply_limit
+            .is_some_and(|limit| i_have.len() > limit.get());
+        if let Some(limit) = node.config.sync_reply_limit {
+            i_have.truncate(limit.get());
+        }
+        //send back the data they dont have. Once the initiator merges this
+        //sync_ok both stores are equal, so a single round reconciles a pair
+        //(or one round per page when the reply is capped)
+        let (messages, bitmap) = encode_payload(i_have);
         let payload = MessageBody::sync_ok {
             msg_id: node.get_and_increment_msg_id(),
-            in_reply_to: msg_id,
-            messages: i_have,
+            in_reply_to: Some(msg_id),
+            messages,
+            tombstones: node.tombstones.snapshot(),
+            seq,
+            more,
+            bitmap,
+            signature: None,
         };
+        let payload = signed(node, &msg.src, payload);
         let reply = msg.into_reply(payload);
         reply.send(tx)?;
     }
     Ok(())
 }
 
+// The store is a grow-only set and merging is plain set union, so applying
+// sync_ok replies in any order (or more than once) ends in the same state.
+// Replies carrying a sync seq are still only merged once, and never after a
+// newer one from the same peer, so merges that aren't idempotent stay safe.
 pub fn handle_sync_ok_message<Data>(
     node: &mut Node<Data>,
     msg: Message,
-    _tx: Sender<Message>,
+    tx: Sender<Message>,
 ) -> Result<()>
 where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
 {
+    if !is_authentic(node, &msg) {
+        anyhow::bail!("dropping sync_ok from {} with a bad signature", msg.src);
+    }
     if let MessageBody::sync_ok {
         msg_id: _,
-        in_reply_to: _,
+        in_reply_to,
         messages,
+        tombstones,
+        seq,
+        more,
+        bitmap,
+        ..
     } = msg.body
     {
+        let messages = sync_values(&messages, bitmap.as_deref())?;
+        if in_reply_to.is_none() {
+            eprintln!(
+                "sync_ok from {} without in_reply_to, merging anyway",
+                msg.src
+            );
+        }
+        if !accepts_sync_ok(node, &msg.src, seq) {
+            return Ok(());
+        }
+        node.merge_tombstones(&msg.src, &tombstones);
         //We might have received data we didn't have the the syncing node has
         //So we simply insert this new data and dont send any acknowledgement
-        for m in messages {
-            node.store.insert(Data::from(m));
+        let mut newly_seen = Vec::new();
+        for &m in messages.iter() {
+            if node.insert_if_absent(Data::from(m), &msg.src).is_some() {
+                newly_seen.push(m);
+            }
+        }
+        if node.config.fanout_on_sync {
+            forward_new_values(node, &msg.src, &newly_seen)?;
+        }
+        if more {
+            continue_sync(node, &msg.src, tx)?;
         }
     }
     Ok(())
 }
 
+// Same merge as handle_sync_ok_message, but values go into the store as they
+// are parsed so a large reply never exists as a Vec. Tombstones are merged
+// once the line is read, which also drops any value they cover.
+pub fn handle_sync_ok_line<Data>(
+    node: &mut Node<Data>,
+    line: &str,
+    tx: Sender<Message>,
+) -> Result<usize>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let header = peek_header(line)?;
+    if !accepts_sync_ok(node, &header.src, header.body.seq) {
+        return Ok(0);
+    }
+    let fanout_on_sync = node.config.fanout_on_sync;
+    let mut newly_seen = Vec::new();
+    let streamed = stream_sync_ok(line, |m| {
+        if node.insert_if_absent(Data::from(m), &header.src).is_some() && fanout_on_sync {
+            newly_seen.push(m);
+        }
+    })?;
+    node.merge_tombstones(&streamed.src, &streamed.tombstones);
+    // anything a tombstone just dropped isn't worth forwarding
+    newly_seen.retain(|m| node.store.contains(&Data::from(*m)));
+    forward_new_values(node, &streamed.src, &newly_seen)?;
+    if streamed.more {
+        continue_sync(node, &streamed.src, tx)?;
+    }
+    Ok(streamed.values)
+}
+
+// Asks `peer` for the rest of a capped sync_ok. This sync carries our whole
+// store whatever the payload strategy, so the next reply only holds values we
+// still lack and each round makes progress.
+fn continue_sync<Data>(node: &mut Node<Data>, peer: &NodeId, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let msg_id = node.get_and_increment_msg_id();
+    let seq = node.sequences.next_sync_for(peer);
+    node.stats.syncs_sent = node.stats.syncs_sent.saturating_add(1);
+    sync_request(node, peer, msg_id, seq, node.read()).send(tx)
+}
+
+fn accepts_sync_ok<Data>(node: &mut Node<Data>, peer: &NodeId, seq: Option<u64>) -> bool {
+    match seq {
+        Some(seq) if !node.sequences.accept_sync_ok(peer, seq) => {
+            eprintln!("Ignoring stale sync_ok {} from {}", seq, peer);
+            false
+        }
+        _ => true,
+    }
+}
+
 // To combat network partitions, a node calls this function to pick random
 // nodes for their messages,while it sends its own. Once we get theirs we can
 // copy values we dont have, while they can copy values from us
@@ -164,30 +444,323 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
-    let all_nodes: Vec<String> = node.node_ids.clone();
-    let mut rng = rand::rng();
-    let messages = all_nodes
-        .choose_multiple(&mut rng, 2)
-        .map(|node_id| Message {
-            src: node.id.clone(),
-            dest: node_id.to_owned(),
-            body: MessageBody::sync {
-                msg_id: node.get_and_increment_msg_id(),
-                messages: node.read(),
-            },
-        })
+    // borrowed straight from node_ids, only the chosen ids are ever copied.
+    // Never ourselves, a node alone in its cluster has nobody to sync with.
+    let all_nodes: Vec<&NodeId> = node
+        .node_ids
+        .iter()
+        .filter(|peer| **peer != node.id && !peer.is_client() && node.peer_is_up(peer))
         .collect();
+    let fanout = sync_fanout(
+        node.node_ids.len(),
+        node.config.sync_peers,
+        node.config.sync_peers_max,
+    );
+    let per_round = fanout.min(all_nodes.len());
+    let capacity = per_round * node.config.sync_rounds;
+    let mut msg_ids = HashSet::with_capacity(capacity);
+    let mut messages = Vec::with_capacity(capacity);
+    let weights: Option<PeerWeights<'_>> = match node.config.sync_peer_weights {
+        SyncPeerWeights::Uniform => None,
+        SyncPeerWeights::Distance => Some(Box::new(distance_weights(node))),
+        SyncPeerWeights::Newness => Some(Box::new(newness_weights(node, &all_nodes))),
+    };
+    // every round draws its own peers, so a peer can be picked more than once
+    let mut chosen: Vec<NodeId> = Vec::with_capacity(capacity);
+    for _ in 0..node.config.sync_rounds {
+        match &weights {
+            None => chosen.extend(
+                all_nodes
+                    .choose_multiple(&mut node.rng, fanout)
+                    .map(|id| (*id).clone()),
+            ),
+            // weights are finite and never negative, so sampling can't fail
+            Some(weights) => chosen.extend(
+                all_nodes
+                    .choose_multiple_weighted(&mut node.rng, fanout, |id| weights(id))
+                    .into_iter()
+                    .flatten()
+                    .map(|id| (*id).clone()),
+            ),
+        }
+    }
+    drop(weights);
+    for node_id in chosen {
+        let mut msg_id = node.get_and_increment_msg_id();
+        while !msg_ids.insert(msg_id) {
+            msg_id = node.get_and_increment_msg_id();
+        }
+        let seq = node.sequences.next_sync_for(&node_id);
+        let payload = sync_payload(node);
+        messages.push(sync_request(node, &node_id, msg_id, seq, payload));
+    }
     messages
 }
 
+// Draw weight of each candidate sync peer
+type PeerWeights<'a> = Box<dyn Fn(&NodeId) -> f64 + 'a>;
+
+// Sync weight of each node for SyncPeerWeights::Newness, scores are read
+// once so they don't decay between a round's draws
+fn newness_weights<Data>(node: &Node<Data>, peers: &[&NodeId]) -> impl Fn(&NodeId) -> f64 {
+    let now = node.clock.now();
+    let scores: HashMap<NodeId, f64> = peers
+        .iter()
+        .map(|peer| ((*peer).clone(), node.newness.score(peer, now)))
+        .collect();
+    move |id| 1.0 + scores.get(id).copied().unwrap_or(0.0)
+}
+
+// Sync weight of each node for SyncPeerWeights::Distance, 1/hops from us in
+// the topology found by a breadth first search
+fn distance_weights<Data>(node: &Node<Data>) -> impl Fn(&NodeId) -> f64 {
+    let mut links: HashMap<&NodeId, Vec<&NodeId>> = HashMap::new();
+    for (from, neighbours) in &node.topology {
+        for to in neighbours {
+            links.entry(from).or_default().push(to);
+            links.entry(to).or_default().push(from);
+        }
+    }
+    let mut hops: HashMap<NodeId, usize> = HashMap::from([(node.id.clone(), 0)]);
+    let mut queue = VecDeque::from([(&node.id, 0)]);
+    while let Some((id, distance)) = queue.pop_front() {
+        for next in links.get(id).into_iter().flatten() {
+            if !hops.contains_key(*next) {
+                hops.insert((*next).clone(), distance + 1);
+                queue.push_back((next, distance + 1));
+            }
+        }
+    }
+    // no shortest path is longer than the cluster minus one
+    let farthest = node.node_ids.len().max(1) as f64;
+    move |id| match hops.get(id) {
+        Some(0) => 0.0,
+        Some(&distance) => 1.0 / distance as f64,
+        None => 1.0 / farthest,
+    }
+}
+
+// Peers per sync round for a cluster of `cluster_size`: ceil(log2(n)) clamped
+// to [min, max]. If the bounds cross, min wins.
+pub(crate) fn sync_fanout(cluster_size: usize, min: usize, max: usize) -> usize {
+    let log2 = match cluster_size {
+        0 => 0,
+        n => (usize::BITS - (n - 1).leading_zeros()) as usize,
+    };
+    log2.min(max).max(min)
+}
+
+// Values only in `ours` and values only in `theirs`
+#[cfg(not(feature = "bitmap-sync"))]
+pub(crate) fn split_difference<Data>(
+    ours: &ValueSet<Data>,
+    theirs: &ValueSet<Data>,
+) -> (Vec<Data>, Vec<Data>)
+where
+    Data: Copy + Hash + Eq,
+{
+    (
+        ours.difference(theirs).copied().collect(),
+        theirs.difference(ours).copied().collect(),
+    )
+}
+
+// Container by container, see Bitmap::difference
+#[cfg(feature = "bitmap-sync")]
+pub(crate) fn split_difference<Data>(
+    ours: &ValueSet<Data>,
+    theirs: &ValueSet<Data>,
+) -> (Vec<Data>, Vec<Data>)
+where
+    Data: Copy + From<u32> + Into<u32>,
+{
+    (
+        ours.difference(theirs).iter().collect(),
+        theirs.difference(ours).iter().collect(),
+    )
+}
+
+// Order independent digest of a set of values. Each value is mixed
+// (splitmix64) before being summed so that {1, 2} and {3} don't collide the
+// way a plain sum or xor would, and the count is folded in last.
+pub(crate) fn checksum(values: impl Iterator<Item = u32>) -> u64 {
+    let (sum, count) = values.fold((0u64, 0u64), |(sum, count), value| {
+        (sum.wrapping_add(mix(value as u64)), count + 1)
+    });
+    sum ^ mix(count)
+}
+
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Values offered in a sync, see SyncPayloadStrategy for the tradeoffs. A
+// pull offers none, the responder merges nothing and the checksum alone tells
+// it whether we differ.
+pub(crate) fn sync_payload<Data>(node: &mut Node<Data>) -> Vec<u32>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if node.config.gossip_mode == GossipMode::Pull {
+        return Vec::new();
+    }
+    match node.config.sync_payload {
+        SyncPayloadStrategy::Full => node.read(),
+        SyncPayloadStrategy::Recent(n) => node
+            .store
+            .recent(n)
+            .iter()
+            .map(|data| (*data).into())
+            .collect(),
+        SyncPayloadStrategy::Sample(n) => {
+            let values = node.read();
+            values.choose_multiple(&mut node.rng, n).copied().collect()
+        }
+    }
+}
+
+// Our store (or part of it) for `peer`, it answers with whatever we lack
+pub(crate) fn sync_request<Data>(
+    node: &Node<Data>,
+    peer: &NodeId,
+    msg_id: u32,
+    seq: u64,
+    payload: Vec<u32>,
+) -> Message
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let (messages, bitmap) = encode_payload(payload);
+    let body = MessageBody::sync {
+        msg_id,
+        messages,
+        tombstones: node.tombstones.snapshot(),
+        checksum: Some(node.store_checksum()),
+        seq: Some(seq),
+        mode: node.config.gossip_mode,
+        bitmap,
+        signature: None,
+    };
+    Message {
+        src: node.id.clone(),
+        dest: peer.clone(),
+        body: signed(node, peer, body),
+    }
+}
+
+// An empty payload stays a plain pull either way
+#[cfg(feature = "bitmap-sync")]
+fn encode_payload(payload: Vec<u32>) -> (Vec<u32>, Option<String>) {
+    if payload.is_empty() {
+        return (payload, None);
+    }
+    (Vec::new(), Some(bitmap::encode(payload)))
+}
+
+#[cfg(not(feature = "bitmap-sync"))]
+fn encode_payload(payload: Vec<u32>) -> (Vec<u32>, Option<String>) {
+    (payload, None)
+}
+
+// The values a sync or sync_ok carries, however they were encoded
+pub(crate) fn sync_values<'a>(messages: &'a [u32], bitmap: Option<&str>) -> Result<Cow<'a, [u32]>> {
+    match bitmap {
+        #[cfg(feature = "bitmap-sync")]
+        Some(encoded) => bitmap::decode(encoded).map(Cow::Owned),
+        #[cfg(not(feature = "bitmap-sync"))]
+        Some(_) => Err(crate::RpcError::not_supported(
+            "bitmap sync payloads need a build with --features bitmap-sync",
+        )
+        .into()),
+        None => Ok(Cow::Borrowed(messages)),
+    }
+}
+
+// The values a sync carries, collected to diff the store against
+#[cfg(not(feature = "bitmap-sync"))]
+fn sync_set<Data>(messages: &[u32], bitmap: Option<&str>) -> Result<ValueSet<Data>>
+where
+    Data: Copy + From<u32> + Hash + Eq,
+{
+    let values = sync_values(messages, bitmap)?;
+    Ok(values.iter().map(|value| Data::from(*value)).collect())
+}
+
+// A bitmap payload is read straight into a Bitmap, no Vec in between
+#[cfg(feature = "bitmap-sync")]
+fn sync_set<Data>(messages: &[u32], bitmap: Option<&str>) -> Result<ValueSet<Data>>
+where
+    Data: Copy + From<u32> + Into<u32>,
+{
+    match bitmap {
+        Some(encoded) => bitmap::Bitmap::decode(encoded),
+        None => Ok(messages.iter().map(|value| Data::from(*value)).collect()),
+    }
+}
+
+// We never send broadcast RPCs ourselves, so there is nothing to correlate
 pub fn handle_broadcast_ok_message<Data>(
     _node: &mut Node<Data>,
-    _msg: Message,
+    msg: Message,
     _tx: Sender<Message>,
 ) -> Result<()>
 where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
 {
+    if let MessageBody::broadcast_ok {
+        in_reply_to: None, ..
+    } = msg.body
+    {
+        eprintln!("broadcast_ok from {} without in_reply_to", msg.src);
+    }
+    Ok(())
+}
+
+// Comparing the answers of every node shows the tree values actually spread
+// along, which can differ from the configured topology once retries, syncs
+// and down peers get involved.
+pub fn handle_provenance_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::provenance { msg_id } = msg.body {
+        let sources = node.provenance.clone();
+        msg.into_reply(MessageBody::provenance_ok {
+            in_reply_to: msg_id,
+            sources,
+        })
+        .send(tx)?;
+    }
+    Ok(())
+}
+
+pub fn handle_outbox_status_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::outbox_status { msg_id } = msg.body {
+        let depths = node.outbox_depths();
+        msg.into_reply(MessageBody::outbox_status_ok {
+            in_reply_to: msg_id,
+            depths,
+        })
+        .send(tx)?;
+    }
     Ok(())
 }
 
@@ -200,38 +773,34 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
+    if !is_authentic(node, &msg) {
+        anyhow::bail!("dropping gossip from {} with a bad signature", msg.src);
+    }
     let src = msg.src.clone();
-    if let MessageBody::gossip { msg_id, messages } = msg.body {
+    if let MessageBody::gossip {
+        msg_id,
+        messages,
+        seq,
+        ..
+    } = msg.body
+    {
+        if let Some(missing) = seq.and_then(|seq| node.sequences.observe(&src, seq)) {
+            repair::request_repair(node, &src, missing, tx.clone())?;
+        }
         let mut newly_seen = Vec::new();
         for message in messages {
-            if node.insert_if_absent(Data::from(message)).is_some() {
+            if node.insert_if_absent(Data::from(message), &src).is_some() {
                 newly_seen.push(message);
             }
         }
 
-        if !newly_seen.is_empty() {
-            let neighbours: Option<&Vec<String>> = node.topology.get(&node.id);
-            if let Some(neighbours) = neighbours {
-                let fanout_peers: Vec<String> = neighbours
-                    .iter()
-                    .filter(|n| **n != src)
-                    .take(2)
-                    .cloned()
-                    .collect();
-                for peer in fanout_peers {
-                    for message in &newly_seen {
-                        node.add_to_outbox(crate::OutboxKind::FanoutMsg, &peer, *message)?;
-                        node.add_to_outbox(crate::OutboxKind::RetryMsg, &peer, *message)?;
-                    }
-                }
-            }
-        }
+        forward_new_values(node, &src, &newly_seen)?;
 
         Message {
             src: node.id.clone(),
             dest: src,
             body: MessageBody::gossip_ok {
-                in_reply_to: msg_id,
+                in_reply_to: Some(msg_id),
             },
         }
         .send(tx)?;
@@ -240,6 +809,66 @@ where
     Ok(())
 }
 
+// Gossip from this node to `dest`, signed when a signing key is configured
+// and numbered when sequence_gossip is on
+pub(crate) fn gossip_body<Data>(
+    node: &mut Node<Data>,
+    dest: &NodeId,
+    msg_id: u32,
+    messages: Vec<u32>,
+    background: bool,
+) -> MessageBody {
+    let seq = node
+        .config
+        .sequence_gossip
+        .then(|| node.sequences.next_for(dest));
+    if let Some(seq) = seq {
+        node.sequences.record_sent(dest, seq, &messages);
+    }
+    let body = MessageBody::gossip {
+        msg_id,
+        messages,
+        signature: None,
+        seq,
+        background,
+    };
+    signed(node, dest, body)
+}
+
+// Signs a body for `dest` when a signing key is configured. Clients don't
+// check signatures, so what goes to them stays unsigned.
+#[cfg(feature = "signing")]
+pub(crate) fn signed<Data>(node: &Node<Data>, dest: &NodeId, mut body: MessageBody) -> MessageBody {
+    if let Some(key) = node
+        .config
+        .signing_key
+        .as_ref()
+        .filter(|_| !dest.is_client())
+    {
+        signing::sign(key.as_bytes(), &node.id, dest, &mut body);
+    }
+    body
+}
+
+#[cfg(not(feature = "signing"))]
+pub(crate) fn signed<Data>(_node: &Node<Data>, _dest: &NodeId, body: MessageBody) -> MessageBody {
+    body
+}
+
+// Without a key (or the feature) every body is accepted, signed or not
+#[cfg(feature = "signing")]
+pub(crate) fn is_authentic<Data>(node: &Node<Data>, msg: &Message) -> bool {
+    match &node.config.signing_key {
+        Some(key) => signing::verify(key.as_bytes(), &msg.src, &msg.dest, &msg.body),
+        None => true,
+    }
+}
+
+#[cfg(not(feature = "signing"))]
+pub(crate) fn is_authentic<Data>(_node: &Node<Data>, _msg: &Message) -> bool {
+    true
+}
+
 pub fn handle_gossip_ok_message<Data>(
     node: &mut Node<Data>,
     msg: Message,
@@ -248,8 +877,17 @@ pub fn handle_gossip_ok_message<Data>(
 where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
 {
-    if let MessageBody::gossip_ok { in_reply_to } = msg.body {
-        node.acknowledge_gossip_batch(in_reply_to);
+    match msg.body {
+        MessageBody::gossip_ok {
+            in_reply_to: Some(in_reply_to),
+        } => node.acknowledge_gossip_batch(&msg.src, in_reply_to),
+        MessageBody::gossip_ok { in_reply_to: None } => {
+            eprintln!(
+                "gossip_ok from {} without in_reply_to, nothing acked",
+                msg.src
+            )
+        }
+        _ => {}
     }
 
     Ok(())
@@ -261,24 +899,34 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
-    let retries: Vec<(String, HashSet<u32>)> = node
+    membership::drop_non_member_outboxes(node);
+    let now = node.clock.now();
+    let mut retries: Vec<(NodeId, HashSet<u32>)> = node
         .retry_outbox
         .iter()
         .filter(|(_, messages)| !messages.is_empty())
-        .filter(|(node_id, _)| !node.has_in_flight_gossip_for(node_id))
+        .filter(|(node_id, _)| !node.has_in_flight_gossip_for(node_id, now))
         .map(|(node_id, messages)| (node_id.clone(), messages.clone()))
         .collect();
+    if let Some(budget) = node.config.retry_budget {
+        retries = within_budget(retries, budget.get(), node.retry_cursor);
+        node.retry_cursor = node.retry_cursor.wrapping_add(1);
+    }
+    node.expire_in_flight_gossip(now);
 
     for (node_id, messages) in retries {
-        let msg_id = node.get_and_increment_msg_id();
-        node.track_gossip_batch(msg_id, node_id.clone(), messages.clone());
+        // Over-limit peers keep their retry outbox untouched until the next pass
+        if !node.rate_limiter.try_acquire(&node_id, now) {
+            continue;
+        }
+        let msg_id = node.track_gossip_batch(node_id.clone(), messages.clone());
+        let messages = messages.iter().copied().collect();
+        let body = gossip_body(node, &node_id, msg_id, messages, true);
+        node.stats.retries_sent = node.stats.retries_sent.saturating_add(1);
         Message {
             src: node.id.clone(),
             dest: node_id,
-            body: MessageBody::gossip {
-                msg_id,
-                messages: messages.iter().copied().collect(),
-            },
+            body,
         }
         .send(tx.clone())?;
     }
@@ -286,30 +934,118 @@ where
     Ok(())
 }
 
+// Cuts a retry pass down to `budget` values. Peers take one value each in
+// turn, starting from a different peer every pass, so after a long partition
+// the backlog drains over several passes and no peer is starved. Whatever is
+// left stays in the retry outbox for the next pass.
+fn within_budget(
+    mut retries: Vec<(NodeId, HashSet<u32>)>,
+    budget: usize,
+    cursor: usize,
+) -> Vec<(NodeId, HashSet<u32>)> {
+    if retries.is_empty() {
+        return retries;
+    }
+    retries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let start = cursor % retries.len();
+    retries.rotate_left(start);
+
+    let mut queues: Vec<Vec<u32>> = retries
+        .iter()
+        .map(|(_, messages)| {
+            let mut queue: Vec<u32> = messages.iter().copied().collect();
+            queue.sort_unstable_by(|a, b| b.cmp(a));
+            queue
+        })
+        .collect();
+    let mut taken: Vec<HashSet<u32>> = vec![HashSet::new(); retries.len()];
+    let mut left = budget;
+    while left > 0 {
+        let mut progressed = false;
+        for (queue, taken) in queues.iter_mut().zip(taken.iter_mut()) {
+            if left == 0 {
+                break;
+            }
+            if let Some(message) = queue.pop() {
+                taken.insert(message);
+                left -= 1;
+                progressed = true;
+            }
+        }
+        if !progressed {
+            break;
+        }
+    }
+    retries
+        .into_iter()
+        .zip(taken)
+        .filter(|(_, taken)| !taken.is_empty())
+        .map(|((node_id, _), taken)| (node_id, taken))
+        .collect()
+}
+
 //We do bulk fanouts
 pub fn fanout_messages<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
 where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
     Node<Data>: BroadcastNodeTrait,
 {
-    let pending: Vec<(String, HashSet<u32>)> = node.msg_outbox.drain().collect();
+    let pending: Vec<(NodeId, HashSet<u32>)> = node.msg_outbox.drain().collect();
+    let now = node.clock.now();
     for (node_id, messages) in pending {
         if messages.is_empty() {
             continue;
         }
+        // Defer rather than drop, the batch goes back into the outbox for a later fanout
+        if !node.rate_limiter.try_acquire(&node_id, now) {
+            node.msg_outbox.entry(node_id).or_default().extend(messages);
+            continue;
+        }
 
-        let msg_id = node.get_and_increment_msg_id();
-        node.track_gossip_batch(msg_id, node_id.clone(), messages.clone());
+        let msg_id = node.track_gossip_batch(node_id.clone(), messages.clone());
+        let messages = messages.iter().copied().collect();
+        let body = gossip_body(node, &node_id, msg_id, messages, false);
+        node.stats.gossip_sent = node.stats.gossip_sent.saturating_add(1);
         Message {
             src: node.id.clone(),
             dest: node_id,
-            body: MessageBody::gossip {
-                msg_id,
-                messages: messages.iter().copied().collect(),
-            },
+            body,
         }
         .send(tx.clone())?;
     }
 
     Ok(())
 }
+
+// Runs whichever periodic tasks are due according to the node's clock
+pub fn tick<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let now = node.clock.now();
+    // not taking the tasks while settling leaves them due the moment it ends
+    let settling = node
+        .initialized_at
+        .is_some_and(|at| now.saturating_duration_since(at) < node.config.startup_grace);
+    if node.schedule.take_due(Task::Fanout, now) {
+        node.fanout_messages(tx.clone())?;
+    }
+    if !settling && node.schedule.take_due(Task::Retry, now) {
+        node.retry_messages(tx.clone())?;
+    }
+    // a streamed sync_ok stores values outside `next`, wake its waiters before
+    // the deadline sweep answers them empty
+    node.wake_read_waiters(tx.clone())?;
+    quorum::expire_pending(node, tx.clone())?;
+    if node.schedule.take_due(Task::Heartbeat, now) {
+        liveness::heartbeat(node, tx.clone())?;
+    }
+    if !settling && node.schedule.take_due(Task::Sync, now) && node.config.anti_entropy {
+        for message in node.request_sync_with_random_peers() {
+            node.stats.syncs_sent = node.stats.syncs_sent.saturating_add(1);
+            message.send(tx.clone())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+use anyhow::{Context, Result};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use serde_path_to_error::deserialize;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::str::FromStr;
+
+use crate::{AckMap, Message, NodeId};
+
+// Returned by decode_line when a line is over the configured limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OversizedMessage {
+    pub len: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for OversizedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message of {} bytes exceeds limit of {}",
+            self.len, self.max
+        )
+    }
+}
+
+impl std::error::Error for OversizedMessage {}
+
+// Parses one line of Maelstrom input. The size check happens before serde
+// sees the line, so a peer can't make us allocate a huge `messages` array.
+pub fn decode_line(line: &str, max_bytes: usize) -> Result<Message> {
+    if line.len() > max_bytes {
+        return Err(OversizedMessage {
+            len: line.len(),
+            max: max_bytes,
+        }
+        .into());
+    }
+    JsonCodec
+        .decode(line.as_bytes())
+        .context("Failed to deserialize STDIN input from Maelstrom")
+}
+
+// Newline delimited input that never holds more than max_bytes + 1 bytes of
+// a line. The rest of a longer line is read and thrown away up to its
+// newline, and the line comes back as an OversizedMessage error with its
+// full length. A trailing \r is stripped like BufRead::lines does.
+pub struct BoundedLines<R> {
+    reader: R,
+    max_bytes: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> BoundedLines<R> {
+    pub fn new(reader: R, max_bytes: usize) -> Self {
+        Self {
+            reader,
+            max_bytes,
+            buf: Vec::new(),
+        }
+    }
+
+    // Consumes input up to and including the next newline, returns how many
+    // bytes came before it
+    fn skip_line(&mut self) -> io::Result<usize> {
+        let mut skipped = 0;
+        loop {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                return Ok(skipped);
+            }
+            match available.iter().position(|b| *b == b'\n') {
+                Some(i) => {
+                    self.reader.consume(i + 1);
+                    return Ok(skipped + i);
+                }
+                None => {
+                    let len = available.len();
+                    self.reader.consume(len);
+                    skipped += len;
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for BoundedLines<R> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buf.clear();
+        let limit = self.max_bytes as u64 + 1;
+        let read = match (&mut self.reader)
+            .take(limit)
+            .read_until(b'\n', &mut self.buf)
+        {
+            Ok(0) => return None,
+            Ok(read) => read,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let complete = self.buf.last() == Some(&b'\n');
+        if !complete && read as u64 == limit {
+            return Some(match self.skip_line() {
+                Ok(rest) => Err(OversizedMessage {
+                    len: read + rest,
+                    max: self.max_bytes,
+                }
+                .into()),
+                Err(e) => Err(e.into()),
+            });
+        }
+        if complete {
+            self.buf.pop();
+            if self.buf.last() == Some(&b'\r') {
+                self.buf.pop();
+            }
+        }
+        Some(String::from_utf8(std::mem::take(&mut self.buf)).context("input line is not UTF-8"))
+    }
+}
+
+// Byte encoding for messages. Maelstrom only speaks JSON, so JsonCodec is
+// what faces stdin/stdout. Other codecs are for channels we own end to end,
+// e.g. node to node sync.
+pub trait WireCodec {
+    fn encode(&self, msg: &Message) -> Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> Result<Message>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec {
+    fn encode(&self, msg: &Message) -> Result<Vec<u8>> {
+        serde_json::to_vec(msg).context("encoding message as JSON")
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message> {
+        let deser = &mut serde_json::Deserializer::from_slice(bytes);
+        deserialize(deser).context("decoding JSON message")
+    }
+}
+
+// MessagePack with field names kept, MessageBody is tagged by its `type`
+// field so it has to be encoded as a map rather than a positional array
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl WireCodec for MsgPackCodec {
+    fn encode(&self, msg: &Message) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(msg).context("encoding message as MessagePack")
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message> {
+        rmp_serde::from_slice(bytes).context("decoding MessagePack message")
+    }
+}
+
+// What the UDP transport encodes node to node traffic with, set by
+// DIST_PEER_CODEC. Clients only speak JSON, so messages for them stay JSON
+// whatever this says.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PeerCodec {
+    #[default]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+}
+
+impl WireCodec for PeerCodec {
+    fn encode(&self, msg: &Message) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => JsonCodec.encode(msg),
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => MsgPackCodec.encode(msg),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Message> {
+        match self {
+            Self::Json => JsonCodec.decode(bytes),
+            #[cfg(feature = "msgpack")]
+            Self::MsgPack => MsgPackCodec.decode(bytes),
+        }
+    }
+}
+
+impl PeerCodec {
+    // The codec a datagram was encoded with. A JSON message is an object
+    // ({), a MessagePack one a map (0x80..=0x8f, 0xde or 0xdf), so a node
+    // reads both whichever one it sends.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes.iter().find(|b| !b.is_ascii_whitespace())? {
+            b'{' => Some(Self::Json),
+            #[cfg(feature = "msgpack")]
+            0x80..=0x8f | 0xde | 0xdf => Some(Self::MsgPack),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPeerCodec(String);
+
+impl fmt::Display for InvalidPeerCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(feature = "msgpack") {
+            write!(f, "expected json or msgpack, got {:?}", self.0)
+        } else {
+            write!(
+                f,
+                "expected json (msgpack needs the msgpack feature), got {:?}",
+                self.0
+            )
+        }
+    }
+}
+
+impl std::error::Error for InvalidPeerCodec {}
+
+impl FromStr for PeerCodec {
+    type Err = InvalidPeerCodec;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Self::Json),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Ok(Self::MsgPack),
+            _ => Err(InvalidPeerCodec(value.to_owned())),
+        }
+    }
+}
+
+// Just enough of a message to decide how to handle it. serde skips the
+// rest of the body (including any `messages` array) without allocating.
+#[derive(Debug, Deserialize)]
+pub struct Header {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub body: BodyHeader,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BodyHeader {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub seq: Option<u64>,
+    #[serde(default)]
+    pub in_reply_to: Option<u32>,
+}
+
+pub fn peek_header(line: &str) -> Result<Header> {
+    serde_json::from_str(line).context("reading message header")
+}
+
+// Everything in a streamed sync_ok or read_ok except `messages` (or `bitmap`),
+// which went to the callback
+#[derive(Debug, Default)]
+pub struct StreamedBody {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub msg_id: u32,
+    pub in_reply_to: u32,
+    pub tombstones: AckMap,
+    // values merged from `messages`
+    pub values: usize,
+    pub more: bool,
+    pub next_offset: Option<usize>,
+}
+
+// Parses a sync_ok line, handing each value of `messages` to `on_value` as
+// it is read instead of collecting them into a Vec first.
+pub fn stream_sync_ok<F>(line: &str, on_value: F) -> Result<StreamedBody>
+where
+    F: FnMut(u32),
+{
+    stream_body(line, "sync_ok", on_value)
+}
+
+// Same as stream_sync_ok, for a read_ok
+pub fn stream_read_ok<F>(line: &str, on_value: F) -> Result<StreamedBody>
+where
+    F: FnMut(u32),
+{
+    stream_body(line, "read_ok", on_value)
+}
+
+fn stream_body<F>(line: &str, kind: &'static str, on_value: F) -> Result<StreamedBody>
+where
+    F: FnMut(u32),
+{
+    let mut deser = serde_json::Deserializer::from_str(line);
+    let streamed = EnvelopeSeed { kind, on_value }
+        .deserialize(&mut deser)
+        .with_context(|| format!("streaming {kind} payload"))?;
+    deser
+        .end()
+        .with_context(|| format!("trailing input after {kind}"))?;
+    Ok(streamed)
+}
+
+struct EnvelopeSeed<F> {
+    kind: &'static str,
+    on_value: F,
+}
+
+impl<'de, F: FnMut(u32)> DeserializeSeed<'de> for EnvelopeSeed<F> {
+    type Value = StreamedBody;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(u32)> Visitor<'de> for EnvelopeSeed<F> {
+    type Value = StreamedBody;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a message envelope")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut streamed = StreamedBody::default();
+        let mut saw_body = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "src" => streamed.src = map.next_value()?,
+                "dest" => streamed.dest = map.next_value()?,
+                "body" => {
+                    map.next_value_seed(BodySeed {
+                        kind: self.kind,
+                        on_value: &mut self.on_value,
+                        out: &mut streamed,
+                    })?;
+                    saw_body = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !saw_body {
+            return Err(de::Error::missing_field("body"));
+        }
+        Ok(streamed)
+    }
+}
+
+struct BodySeed<'a, F> {
+    kind: &'static str,
+    on_value: &'a mut F,
+    out: &'a mut StreamedBody,
+}
+
+impl<'de, F: FnMut(u32)> DeserializeSeed<'de> for BodySeed<'_, F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(u32)> Visitor<'de> for BodySeed<'_, F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a {} body", self.kind)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "type" => {
+                    let kind: String = map.next_value()?;
+                    if kind != self.kind {
+                        return Err(de::Error::invalid_value(
+                            de::Unexpected::Str(&kind),
+                            &self.kind,
+                        ));
+                    }
+                }
+                "msg_id" => self.out.msg_id = map.next_value()?,
+                "in_reply_to" => self.out.in_reply_to = map.next_value()?,
+                "tombstones" => self.out.tombstones = map.next_value()?,
+                "more" => self.out.more = map.next_value()?,
+                "next_offset" => self.out.next_offset = map.next_value()?,
+                "messages" => {
+                    self.out.values += map.next_value_seed(ValuesSeed {
+                        on_value: &mut *self.on_value,
+                    })?
+                }
+                "bitmap" => {
+                    if let Some(encoded) = map.next_value::<Option<String>>()? {
+                        self.out.values += decode_bitmap(&encoded, &mut *self.on_value)
+                            .map_err(de::Error::custom)?;
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bitmap-sync")]
+fn decode_bitmap(encoded: &str, on_value: impl FnMut(u32)) -> Result<usize> {
+    crate::bitmap::decode_each(encoded, on_value)
+}
+
+#[cfg(not(feature = "bitmap-sync"))]
+fn decode_bitmap(_encoded: &str, _on_value: impl FnMut(u32)) -> Result<usize> {
+    Err(crate::RpcError::not_supported(
+        "bitmap sync payloads need a build with --features bitmap-sync",
+    )
+    .into())
+}
+
+struct ValuesSeed<'a, F> {
+    on_value: &'a mut F,
+}
+
+impl<'de, F: FnMut(u32)> DeserializeSeed<'de> for ValuesSeed<'_, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(u32)> Visitor<'de> for ValuesSeed<'_, F> {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of u32 values")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+        while let Some(value) = seq.next_element::<u32>()? {
+            (self.on_value)(value);
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,491 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::{NodeId, PeerCodec, RateLimit};
+
+const PREFIX: &str = "DIST_";
+
+// All node tunables in one place. Maelstrom can't pass flags to the binary, so
+// these are read from DIST_* environment variables at startup. Defaults match
+// the behaviour before any of this was configurable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // topology neighbours a new value is forwarded to
+    pub fanout: usize,
+    // random non-neighbours a new value is also forwarded to, 0 sticks to the
+    // topology
+    pub fanout_random_peers: usize,
+    // most peers a new value is forwarded to, counting neighbours, random
+    // peers and the everyone-fallback of a node missing from the topology.
+    // Over it a random subset is picked. Unset doesn't cap, 0 would stop
+    // gossip altogether and is rejected.
+    pub max_fanout: Option<NonZeroUsize>,
+    // random peers contacted per sync round, at least sync_peers and at most
+    // sync_peers_max. In between it grows with ceil(log2(cluster size)).
+    pub sync_peers: usize,
+    pub sync_peers_max: usize,
+    // which of our values a sync carries
+    pub sync_payload: SyncPayloadStrategy,
+    // which way values travel in a sync round
+    pub gossip_mode: GossipMode,
+    // independent peer draws per sync, each contacting that many peers
+    pub sync_rounds: usize,
+    // how likely each live node is to be drawn for a sync
+    pub sync_peer_weights: SyncPeerWeights,
+    // how fast SyncPeerWeights::Newness forgets which peers fed us new values,
+    // a peer's score halves every this long
+    pub newness_half_life: Duration,
+    pub fanout_interval: Duration,
+    pub retry_interval: Duration,
+    // values resent per retry pass, shared round robin between peers. Unset
+    // resends the whole retry outbox every pass, a budget of 0 would never
+    // resend anything and is rejected.
+    pub retry_budget: Option<NonZeroUsize>,
+    // up to this much is added to each batch's retry deadline, so nodes that
+    // gossiped together don't retry in lockstep
+    pub retry_jitter: Duration,
+    // seeds the node's RNG (retry jitter) for reproducible runs
+    pub seed: Option<u64>,
+    // how often a sync round runs. Unset runs none, values then only spread
+    // through fanout and retries, plus the syncs gossip repair falls back on.
+    pub sync_interval: Option<Duration>,
+    // after an init, retries and syncs hold off this long so the topology and
+    // the rest of the cluster can arrive first. Fanout isn't held back.
+    pub startup_grace: Duration,
+    // periodic syncs, off leaves fanout and retries as the only way values
+    // spread and answers peers' syncs with NotSupported
+    pub anti_entropy: bool,
+    // how often every peer is pinged. Unset sends no heartbeats, so every
+    // peer stays up as far as fanout and sync are concerned.
+    pub heartbeat_interval: Option<Duration>,
+    // peers that haven't answered a heartbeat for this long are treated as down
+    pub peer_timeout: Duration,
+    // judge peers by the phi accrual detector instead once they have a few
+    // heartbeats on record, down at or above this phi. 8 is a common choice.
+    // Unset keeps peer_timeout for everyone.
+    pub phi_threshold: Option<f64>,
+    // how long to wait on peers before failing an RPC such as a quorum read
+    pub rpc_timeout: Duration,
+    // how client reads are answered, a read with `quorum: true` is always a
+    // quorum read
+    pub consistency: Consistency,
+    // after a quorum read, push peers the values their reply lacked. Sync
+    // already reconciles both ways so it doesn't need this.
+    pub read_repair: bool,
+    // number gossip per peer so receivers can spot lost batches and sync
+    pub sequence_gossip: bool,
+    // forward values learned from a sync_ok to our fanout peers, like values
+    // from gossip. Spreads them faster at the cost of extra gossip.
+    pub fanout_on_sync: bool,
+    // most values in one sync_ok, the rest follow once the peer syncs again.
+    // A sync carrying no values (a pull) gets our oldest values first. Unset
+    // replies with everything the peer lacks in one go.
+    pub sync_reply_limit: Option<NonZeroUsize>,
+    // inbound lines longer than this are dropped before deserializing
+    pub max_message_bytes: usize,
+    // sync_ok and quorum read_ok lines at least this long merge values while
+    // parsing instead of decoding the whole message first
+    pub stream_threshold_bytes: usize,
+    // cluster wide HMAC key, every body carrying values is signed and
+    // verified when set
+    #[cfg(feature = "signing")]
+    pub signing_key: Option<String>,
+    // set both DIST_RATE_LIMIT and DIST_RATE_BURST to enable
+    pub rate_limit: Option<RateLimit>,
+    // handlers taking longer than this are logged as slow. The loop is
+    // synchronous so a slow handler still runs to completion. Unset doesn't
+    // time handlers.
+    pub slow_handler_threshold: Option<Duration>,
+    // where the binary reads messages from and writes replies to
+    pub transport: Transport,
+    // where Transport::Udp sends to nodes that haven't sent us anything yet,
+    // e.g. gossip to a peer straight after init
+    pub udp_peers: HashMap<NodeId, SocketAddr>,
+    // how Transport::Udp encodes messages for other nodes
+    pub peer_codec: PeerCodec,
+    // serves Prometheus metrics over HTTP here when set
+    #[cfg(feature = "metrics")]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fanout: 2,
+            fanout_random_peers: 0,
+            max_fanout: None,
+            sync_peers: 2,
+            sync_peers_max: 2,
+            sync_payload: SyncPayloadStrategy::Full,
+            gossip_mode: GossipMode::PushPull,
+            sync_rounds: 1,
+            sync_peer_weights: SyncPeerWeights::Uniform,
+            newness_half_life: Duration::from_millis(5000),
+            fanout_interval: Duration::from_millis(50),
+            retry_interval: Duration::from_millis(300),
+            retry_budget: None,
+            retry_jitter: Duration::ZERO,
+            seed: None,
+            sync_interval: None,
+            startup_grace: Duration::ZERO,
+            anti_entropy: true,
+            heartbeat_interval: None,
+            peer_timeout: Duration::from_millis(2000),
+            phi_threshold: None,
+            rpc_timeout: Duration::from_millis(1000),
+            consistency: Consistency::Local,
+            read_repair: false,
+            sequence_gossip: false,
+            fanout_on_sync: false,
+            sync_reply_limit: None,
+            max_message_bytes: 4 * 1024 * 1024,
+            stream_threshold_bytes: 64 * 1024,
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            rate_limit: None,
+            slow_handler_threshold: None,
+            transport: Transport::Stdio,
+            udp_peers: HashMap::new(),
+            peer_codec: PeerCodec::Json,
+            #[cfg(feature = "metrics")]
+            metrics_addr: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    // Unknown DIST_* keys are ignored so older binaries accept newer settings
+    pub fn from_vars<I>(vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut config = Self::default();
+        let mut rate: Option<f64> = None;
+        let mut burst: Option<NonZeroU32> = None;
+
+        for (key, value) in vars {
+            let Some(name) = key.strip_prefix(PREFIX) else {
+                continue;
+            };
+            match name {
+                "FANOUT" => config.fanout = parse(&key, &value)?,
+                "FANOUT_RANDOM_PEERS" => config.fanout_random_peers = parse(&key, &value)?,
+                "MAX_FANOUT" => config.max_fanout = Some(parse(&key, &value)?),
+                "SYNC_PEERS" => config.sync_peers = parse(&key, &value)?,
+                "SYNC_PEERS_MAX" => config.sync_peers_max = parse(&key, &value)?,
+                "SYNC_PAYLOAD" => config.sync_payload = parse(&key, &value)?,
+                "GOSSIP_MODE" => config.gossip_mode = parse(&key, &value)?,
+                "SYNC_ROUNDS" => config.sync_rounds = parse(&key, &value)?,
+                "SYNC_PEER_WEIGHTS" => config.sync_peer_weights = parse(&key, &value)?,
+                "NEWNESS_HALF_LIFE_MS" => config.newness_half_life = parse_millis(&key, &value)?,
+                "FANOUT_INTERVAL_MS" => config.fanout_interval = parse_millis(&key, &value)?,
+                "RETRY_INTERVAL_MS" => config.retry_interval = parse_millis(&key, &value)?,
+                "RETRY_BUDGET" => config.retry_budget = Some(parse(&key, &value)?),
+                "RETRY_JITTER_MS" => config.retry_jitter = parse_millis(&key, &value)?,
+                "SEED" => config.seed = Some(parse(&key, &value)?),
+                "SYNC_INTERVAL_MS" => config.sync_interval = Some(parse_millis(&key, &value)?),
+                "STARTUP_GRACE_MS" => config.startup_grace = parse_millis(&key, &value)?,
+                "ANTI_ENTROPY" => config.anti_entropy = parse(&key, &value)?,
+                "HEARTBEAT_INTERVAL_MS" => {
+                    config.heartbeat_interval = Some(parse_millis(&key, &value)?)
+                }
+                "PEER_TIMEOUT_MS" => config.peer_timeout = parse_millis(&key, &value)?,
+                "PHI_THRESHOLD" => config.phi_threshold = Some(parse(&key, &value)?),
+                "RPC_TIMEOUT_MS" => config.rpc_timeout = parse_millis(&key, &value)?,
+                "CONSISTENCY" => config.consistency = parse(&key, &value)?,
+                "READ_REPAIR" => config.read_repair = parse(&key, &value)?,
+                "SEQUENCE_GOSSIP" => config.sequence_gossip = parse(&key, &value)?,
+                "FANOUT_ON_SYNC" => config.fanout_on_sync = parse(&key, &value)?,
+                "SYNC_REPLY_LIMIT" => config.sync_reply_limit = Some(parse(&key, &value)?),
+                "MAX_MESSAGE_BYTES" => config.max_message_bytes = parse(&key, &value)?,
+                "STREAM_THRESHOLD_BYTES" => config.stream_threshold_bytes = parse(&key, &value)?,
+                #[cfg(feature = "signing")]
+                "SIGNING_KEY" => config.signing_key = Some(value),
+                "RATE_LIMIT" => rate = Some(parse_rate(&key, &value)?),
+                "RATE_BURST" => burst = Some(parse(&key, &value)?),
+                "SLOW_HANDLER_MS" => {
+                    config.slow_handler_threshold = Some(parse_millis(&key, &value)?)
+                }
+                "TRANSPORT" => config.transport = parse(&key, &value)?,
+                "UDP_PEERS" => config.udp_peers = parse_peers(&key, &value)?,
+                "PEER_CODEC" => config.peer_codec = parse(&key, &value)?,
+                #[cfg(feature = "metrics")]
+                "METRICS_ADDR" => config.metrics_addr = Some(parse(&key, &value)?),
+                _ => {}
+            }
+        }
+
+        config.rate_limit = match (rate, burst) {
+            (Some(rate), Some(burst)) => Some(RateLimit {
+                rate,
+                burst: burst.get(),
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("{PREFIX}RATE_LIMIT and {PREFIX}RATE_BURST must be set together"),
+        };
+        Ok(config)
+    }
+}
+
+// What a sync request ships. The peer always answers with everything it has
+// that the payload lacked, so the smaller options only save bandwidth on our
+// half of the exchange:
+// - Full: whole store, one round reconciles a pair but costs O(store) each way
+// - Recent(n): the last n values we inserted. Fresh values spread quickly,
+//   older ones we hold but the peer lacks wait for a later full sync or a
+//   peer's own sync with us
+// - Sample(n): n random values. Every value is eventually offered, but
+//   convergence gets slower as the store grows relative to n
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPayloadStrategy {
+    Full,
+    Recent(usize),
+    Sample(usize),
+}
+
+#[derive(Debug)]
+pub struct InvalidStrategy(String);
+
+impl fmt::Display for InvalidStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected full, recent:N or sample:N, got {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidStrategy {}
+
+impl FromStr for SyncPayloadStrategy {
+    type Err = InvalidStrategy;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidStrategy(value.to_owned());
+        match value.split_once(':') {
+            None if value == "full" => Ok(Self::Full),
+            Some(("recent", n)) => n.parse().map(Self::Recent).map_err(|_| invalid()),
+            Some(("sample", n)) => n.parse().map(Self::Sample).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Which way a sync round moves values. The initiator's mode travels in the
+// sync, so the responder follows it whatever its own config says:
+// - Push: the responder merges our payload and answers with an empty sync_ok
+// - Pull: we send only our store checksum, the responder merges nothing and
+//   answers with everything it has unless the sums match. A capped answer is
+//   followed up with our whole store, see continue_sync
+// - PushPull: both of the above, one round reconciles a pair
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GossipMode {
+    Push,
+    Pull,
+    #[default]
+    PushPull,
+}
+
+impl GossipMode {
+    // whether the responder merges what the sync carries
+    pub fn pushes(self) -> bool {
+        matches!(self, Self::Push | Self::PushPull)
+    }
+
+    // whether the responder answers with what the initiator lacks
+    pub fn pulls(self) -> bool {
+        matches!(self, Self::Pull | Self::PushPull)
+    }
+
+    pub(crate) fn is_push_pull(&self) -> bool {
+        *self == Self::PushPull
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidGossipMode(String);
+
+impl fmt::Display for InvalidGossipMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected push, pull or push-pull, got {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidGossipMode {}
+
+impl FromStr for GossipMode {
+    type Err = InvalidGossipMode;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "push" => Ok(Self::Push),
+            "pull" => Ok(Self::Pull),
+            "push-pull" => Ok(Self::PushPull),
+            _ => Err(InvalidGossipMode(value.to_owned())),
+        }
+    }
+}
+
+// How sync peers are drawn:
+// - Uniform: every live node is equally likely
+// - Distance: weighted by 1/hops from us in the topology, links counting both
+//   ways, so close peers are synced with most often. Nodes the topology
+//   doesn't connect us to weigh as little as the farthest possible peer, and
+//   we never draw ourselves.
+// - Newness: weighted by 1 + how many new values a peer recently delivered
+//   to us (see Newness), so peers that are ahead are synced with most often
+//   while the others keep a baseline chance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPeerWeights {
+    Uniform,
+    Distance,
+    Newness,
+}
+
+#[derive(Debug)]
+pub struct InvalidPeerWeights(String);
+
+impl fmt::Display for InvalidPeerWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected uniform, distance or newness, got {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPeerWeights {}
+
+impl FromStr for SyncPeerWeights {
+    type Err = InvalidPeerWeights;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "uniform" => Ok(Self::Uniform),
+            "distance" => Ok(Self::Distance),
+            "newness" => Ok(Self::Newness),
+            _ => Err(InvalidPeerWeights(value.to_owned())),
+        }
+    }
+}
+
+// Read consistency levels, cheapest first:
+// - Local: our own store, may miss values still in flight to us
+// - ReadRepair: our own store right away, then every peer is read and pushed
+//   whatever it lacked, so the next read anywhere is more likely complete
+// - Quorum: the union of a majority of stores, costs a peer round trip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    Local,
+    ReadRepair,
+    Quorum,
+}
+
+#[derive(Debug)]
+pub struct InvalidConsistency(String);
+
+impl fmt::Display for InvalidConsistency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected local, read-repair or quorum, got {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidConsistency {}
+
+impl FromStr for Consistency {
+    type Err = InvalidConsistency;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "local" => Ok(Self::Local),
+            "read-repair" => Ok(Self::ReadRepair),
+            "quorum" => Ok(Self::Quorum),
+            _ => Err(InvalidConsistency(value.to_owned())),
+        }
+    }
+}
+
+// How the binary exchanges newline delimited JSON messages:
+// - Stdio: stdin and stdout, what Maelstrom runs us with
+// - Tcp(addr): listens on addr and serves the first connection, lines in and
+//   replies out on the same socket. The node shuts down when it closes, as it
+//   does when stdin does.
+// - Udp(addr): binds addr, one JSON message per datagram each way. Replies
+//   go to the address a node or client last sent from, or its udp_peers
+//   entry. Datagrams get lost and reordered, retries and syncs make up for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Stdio,
+    Tcp(SocketAddr),
+    Udp(SocketAddr),
+}
+
+#[derive(Debug)]
+pub struct InvalidTransport(String);
+
+impl fmt::Display for InvalidTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected stdio, tcp:ADDR or udp:ADDR, got {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTransport {}
+
+impl FromStr for Transport {
+    type Err = InvalidTransport;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTransport(value.to_owned());
+        match value.split_once(':') {
+            None if value == "stdio" => Ok(Self::Stdio),
+            Some(("tcp", addr)) => addr.parse().map(Self::Tcp).map_err(|_| invalid()),
+            Some(("udp", addr)) => addr.parse().map(Self::Udp).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn parse<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid value {value:?} for {key}"))
+}
+
+fn parse_millis(key: &str, value: &str) -> Result<Duration> {
+    parse(key, value).map(Duration::from_millis)
+}
+
+// Tokens per second, a bucket that never refills (or refills forever) is a
+// typo rather than a setting
+fn parse_rate(key: &str, value: &str) -> Result<f64> {
+    let rate: f64 = parse(key, value)?;
+    anyhow::ensure!(
+        rate.is_finite() && rate > 0.0,
+        "invalid value {value:?} for {key}, expected a finite rate above 0"
+    );
+    Ok(rate)
+}
+
+// `n1=127.0.0.1:7001,n2=127.0.0.1:7002`
+fn parse_peers(key: &str, value: &str) -> Result<HashMap<NodeId, SocketAddr>> {
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (id, addr) = entry
+                .split_once('=')
+                .with_context(|| format!("expected ID=ADDR in {key}, got {entry:?}"))?;
+            Ok((NodeId::from(id.trim()), parse(key, addr)?))
+        })
+        .collect()
+}
@@ -0,0 +1,118 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+// JSON types used by Maelstrom's message schemas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    // non-negative integer, every msg_id, in_reply_to and error code
+    Int,
+    Str,
+    // anything at all, e.g. the echo payload or a generated id
+    Any,
+    // array of anything
+    List,
+    StrList,
+    // object mapping node ids to lists of node ids
+    Topology,
+}
+
+impl Field {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Field::Int => value.is_u64(),
+            Field::Str => value.is_string(),
+            Field::Any => true,
+            Field::List => value.is_array(),
+            Field::StrList => value
+                .as_array()
+                .is_some_and(|items| items.iter().all(Value::is_string)),
+            Field::Topology => value
+                .as_object()
+                .is_some_and(|map| map.values().all(|ids| Field::StrList.matches(ids))),
+        }
+    }
+}
+
+type Schema = &'static [(&'static str, Field)];
+
+// Required body fields of the standard bodies, following Maelstrom's protocol
+// and workload docs. Maelstrom ignores fields it doesn't know, so extra ones
+// (our msg_id on replies, quorum on read) are fine.
+const SCHEMAS: &[(&str, Schema)] = &[
+    (
+        "init",
+        &[
+            ("msg_id", Field::Int),
+            ("node_id", Field::Str),
+            ("node_ids", Field::StrList),
+        ],
+    ),
+    ("init_ok", &[("in_reply_to", Field::Int)]),
+    ("echo", &[("msg_id", Field::Int), ("echo", Field::Any)]),
+    (
+        "echo_ok",
+        &[("in_reply_to", Field::Int), ("echo", Field::Any)],
+    ),
+    ("generate", &[("msg_id", Field::Int)]),
+    (
+        "generate_ok",
+        &[("in_reply_to", Field::Int), ("id", Field::Any)],
+    ),
+    (
+        "broadcast",
+        &[("msg_id", Field::Int), ("message", Field::Any)],
+    ),
+    ("broadcast_ok", &[("in_reply_to", Field::Int)]),
+    ("read", &[("msg_id", Field::Int)]),
+    (
+        "read_ok",
+        &[("in_reply_to", Field::Int), ("messages", Field::List)],
+    ),
+    (
+        "topology",
+        &[("msg_id", Field::Int), ("topology", Field::Topology)],
+    ),
+    ("topology_ok", &[("in_reply_to", Field::Int)]),
+    (
+        "error",
+        &[("in_reply_to", Field::Int), ("code", Field::Int)],
+    ),
+];
+
+pub fn schema(type_name: &str) -> Option<Schema> {
+    SCHEMAS
+        .iter()
+        .find(|(name, _)| *name == type_name)
+        .map(|(_, schema)| *schema)
+}
+
+// Checks a serialized message the way Maelstrom would: string src and dest,
+// and a body with every field its type requires. Bodies of our own types
+// (sync, gossip, ...) only need a `type`.
+pub fn check_message(msg: &Value) -> Result<()> {
+    for key in ["src", "dest"] {
+        if !msg.get(key).is_some_and(Value::is_string) {
+            bail!("message needs a string {key}");
+        }
+    }
+    let body = msg.get("body").context("message has no body")?;
+    check_body(body)
+}
+
+pub fn check_body(body: &Value) -> Result<()> {
+    let type_name = body
+        .get("type")
+        .and_then(Value::as_str)
+        .context("body has no string type")?;
+    let Some(schema) = schema(type_name) else {
+        return Ok(());
+    };
+    for (name, field) in schema {
+        match body.get(name) {
+            Some(value) if field.matches(value) => {}
+            Some(value) => bail!("{type_name}.{name} should be {field:?}, got {value}"),
+            None => bail!("{type_name} is missing {name}"),
+        }
+    }
+    Ok(())
+}
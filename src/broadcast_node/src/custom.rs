@@ -0,0 +1,28 @@
+use crate::{BroadcastNodeTrait, Message, MessageBody, Node};
+use anyhow::Result;
+use std::hash::Hash;
+use std::sync::mpsc::Sender;
+
+// Handler for a message type that isn't part of MessageBody yet. Receives the
+// whole message, the payload is in `MessageBody::custom`.
+pub type CustomHandler<Data> = fn(&mut Node<Data>, Message, Sender<Message>) -> Result<()>;
+
+pub fn handle_custom_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let handler = match msg.body {
+        MessageBody::custom { ref type_name, .. } => node.custom_handlers.get(type_name).copied(),
+        _ => return Ok(()),
+    };
+    match handler {
+        Some(handler) => handler(node, msg, tx),
+        // also where a built-in type whose fields don't parse ends up
+        None => node.handle_unsupported_message(msg, tx),
+    }
+}
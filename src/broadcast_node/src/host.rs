@@ -0,0 +1,413 @@
+use anyhow::Result;
+use std::borrow::Borrow;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::codec::peek_header;
+use crate::{
+    broadcast, guarded, quorum, BroadcastNodeTrait, Clock, Config, CustomHandler, Message,
+    MessageBody, Node, NodeId, SystemClock,
+};
+
+// Outcome of Host::process_batch
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub sent: Vec<Message>,
+    pub errors: Vec<(usize, anyhow::Error)>,
+}
+
+// Hosts any number of logical nodes in one process, keyed by node id. Inbound
+// messages go to the node named in `dest`. A node is created when its init
+// arrives, so under Maelstrom this is simply a host with one node, while tests
+// can run a whole cluster in one binary.
+pub struct Host<Data> {
+    nodes: HashMap<NodeId, Node<Data>>,
+    // messages between hosted nodes waiting to be delivered
+    local: VecDeque<Message>,
+    // node id -> partition group, nodes in different groups can't talk
+    partitions: Option<HashMap<NodeId, usize>>,
+    // (src, dest) -> how long messages on that link take to arrive
+    latencies: HashMap<(NodeId, NodeId), Duration>,
+    // delayed messages and when they are due, in the order they were sent
+    in_transit: Vec<(Instant, Message)>,
+    delivered: u64,
+    dropped: u64,
+    // handler runs over Config::slow_handler_threshold
+    slow_handlers: u64,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    // registered on every node, including ones created later
+    custom_handlers: HashMap<String, CustomHandler<Data>>,
+    // renders on the tick after a scrape asks, once serve_metrics started it
+    #[cfg(feature = "metrics")]
+    exporter: Option<crate::metrics::Exporter>,
+}
+
+impl<Data> Host<Data>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    pub fn new(config: Config) -> Self {
+        Self {
+            nodes: HashMap::new(),
+            local: VecDeque::new(),
+            partitions: None,
+            latencies: HashMap::new(),
+            in_transit: Vec::new(),
+            delivered: 0,
+            dropped: 0,
+            slow_handlers: 0,
+            config,
+            clock: Arc::new(SystemClock),
+            custom_handlers: HashMap::new(),
+            #[cfg(feature = "metrics")]
+            exporter: None,
+        }
+    }
+
+    // Clock handed to every node created from here on
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn register_custom(&mut self, type_name: &str, handler: CustomHandler<Data>) {
+        self.custom_handlers.insert(type_name.to_owned(), handler);
+        for node in self.nodes.values_mut() {
+            node.register_custom(type_name, handler);
+        }
+    }
+
+    // Handles an inbound message, then keeps delivering whatever the nodes send
+    // each other until nothing local is left. Only the error for `msg` itself
+    // is returned, failures further down the chain are logged.
+    pub fn route(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        let result = self.dispatch(msg, &tx);
+        self.deliver_local(&tx)?;
+        result
+    }
+
+    // Runs a whole sequence of inbound messages (e.g. a replayed log) through
+    // one output channel and returns everything sent to non-local nodes.
+    // Errors are collected with the index of the message that caused them.
+    pub fn process_batch<I>(&mut self, messages: I) -> Batch
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut errors = Vec::new();
+        for (index, msg) in messages.into_iter().enumerate() {
+            if let Err(e) = self.route(msg, tx.clone()) {
+                errors.push((index, e));
+            }
+        }
+        drop(tx);
+        Batch {
+            sent: rx.into_iter().collect(),
+            errors,
+        }
+    }
+
+    // Big sync_ok and read_ok lines are merged straight from the text, see
+    // handle_sync_ok_line and handle_read_ok_line. Returns false when the line
+    // should go through decode_line and route as usual, which includes any
+    // line that has to have its signature checked before a single value is
+    // merged. A streamed line gets what route gives any other message: the
+    // panic boundary, slow handler timing, and partitions and latency on
+    // whatever it sends.
+    pub fn try_stream(&mut self, line: &str, tx: Sender<Message>) -> Result<bool> {
+        #[cfg(feature = "signing")]
+        let signed = self.config.signing_key.is_some();
+        #[cfg(not(feature = "signing"))]
+        let signed = false;
+        if line.len() < self.config.stream_threshold_bytes
+            || line.len() > self.config.max_message_bytes
+            || signed
+        {
+            return Ok(false);
+        }
+        let header = peek_header(line)?;
+        let Some(node) = self.nodes.get_mut(&header.dest) else {
+            return Ok(false);
+        };
+        let kind = match header.body.kind.as_str() {
+            "sync_ok" => "sync_ok",
+            "read_ok" if quorum::streams_read_ok(node, header.body.in_reply_to) => "read_ok",
+            _ => return Ok(false),
+        };
+        // an uninitialized or misrouted node is left to next() to turn away
+        if *node.id() != header.dest {
+            return Ok(false);
+        }
+        let (local_tx, local_rx) = mpsc::channel();
+        let timed = self.config.slow_handler_threshold.map(|_| {
+            let description = format!("streamed {} from {} on {}", kind, header.src, header.dest);
+            (self.clock.now(), description)
+        });
+        let result = guarded(None, local_tx.clone(), || {
+            node.record_ack(kind);
+            let merged = match kind {
+                "sync_ok" => broadcast::handle_sync_ok_line(node, line, local_tx.clone())?,
+                _ => quorum::handle_read_ok_line(node, line, local_tx.clone())?,
+            };
+            eprintln!("Streamed {} values from {}", merged, header.src);
+            node.wake_read_waiters(local_tx)
+        });
+        if let Some((started, description)) = timed {
+            self.check_slow(started, &description);
+        }
+        self.forward(local_rx, &tx)?;
+        self.deliver_local(&tx)?;
+        result.map(|()| true)
+    }
+
+    pub fn tick(&mut self, tx: Sender<Message>) -> Result<()> {
+        let (local_tx, local_rx) = mpsc::channel();
+        for node in self.nodes.values_mut() {
+            node.tick(local_tx.clone())?;
+        }
+        self.forward(local_rx, &tx)?;
+        self.deliver_local(&tx)?;
+        #[cfg(feature = "metrics")]
+        if let Some(mut exporter) = self.exporter.take() {
+            exporter.publish(self);
+            self.exporter = Some(exporter);
+        }
+        Ok(())
+    }
+
+    // Starts the Prometheus endpoint on `addr`, see metrics.rs. Returns the
+    // bound address.
+    #[cfg(feature = "metrics")]
+    pub fn serve_metrics(&mut self, addr: std::net::SocketAddr) -> Result<std::net::SocketAddr> {
+        let exporter = crate::metrics::Exporter::start(addr)?;
+        let addr = exporter.addr();
+        self.exporter = Some(exporter);
+        Ok(addr)
+    }
+
+    // Pushes out everything queued, regardless of the schedule
+    pub fn flush(&mut self, tx: Sender<Message>) -> Result<()> {
+        let (local_tx, local_rx) = mpsc::channel();
+        for node in self.nodes.values_mut() {
+            node.fanout_messages(local_tx.clone())?;
+            node.retry_messages(local_tx.clone())?;
+        }
+        self.forward(local_rx, &tx)?;
+        self.deliver_local(&tx)
+    }
+
+    // Last chance for queued gossip before the process exits: one more
+    // fanout and retry pass. Returns how many values are still unacked, their
+    // acks can't be read anymore so everything in flight counts.
+    pub fn shutdown(&mut self, tx: Sender<Message>) -> Result<usize> {
+        self.flush(tx)?;
+        Ok(self.nodes.values().map(Node::unacked).sum())
+    }
+
+    fn dispatch(&mut self, msg: Message, tx: &Sender<Message>) -> Result<()> {
+        if matches!(msg.body, MessageBody::init { .. }) && !self.nodes.contains_key(&msg.dest) {
+            let mut node = Node::default()
+                .with_config(self.config.clone())
+                .with_clock(self.clock.clone());
+            node.custom_handlers = self.custom_handlers.clone();
+            self.nodes.insert(msg.dest.clone(), node);
+        }
+        let (local_tx, local_rx) = mpsc::channel();
+        // only described up front when there's a threshold to report against
+        let timed = self
+            .config
+            .slow_handler_threshold
+            .map(|_| (self.clock.now(), describe(&msg)));
+        let result = match self.nodes.get_mut(&msg.dest) {
+            Some(node) => {
+                let result = node.dispatch(msg, local_tx);
+                if let Some((started, description)) = timed {
+                    self.check_slow(started, &description);
+                }
+                result
+            }
+            None => Err(anyhow::anyhow!(
+                "no local node {} for message from {}",
+                msg.dest,
+                msg.src
+            )),
+        };
+        self.forward(local_rx, tx)?;
+        result
+    }
+
+    fn check_slow(&mut self, started: Instant, description: &str) {
+        let Some(threshold) = self.config.slow_handler_threshold else {
+            return;
+        };
+        let took = self.clock.now().saturating_duration_since(started);
+        if took > threshold {
+            self.slow_handlers = self.slow_handlers.saturating_add(1);
+            eprintln!("Slow handler: {description} took {took:?}, threshold {threshold:?}");
+        }
+    }
+
+    // Router: locally hosted destinations are queued for in-process delivery,
+    // everything else leaves through `tx` (stdout under Maelstrom).
+    fn forward(&mut self, rx: Receiver<Message>, tx: &Sender<Message>) -> Result<()> {
+        for out in rx.try_iter() {
+            if self.nodes.contains_key(&out.dest) {
+                if self.is_partitioned(&out.src, &out.dest) {
+                    self.dropped = self.dropped.saturating_add(1);
+                    continue;
+                }
+                let link = (out.src.clone(), out.dest.clone());
+                match self.latencies.get(&link) {
+                    Some(latency) => self.in_transit.push((self.clock.now() + *latency, out)),
+                    None => self.local.push_back(out),
+                }
+            } else {
+                tx.send(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn deliver_local(&mut self, tx: &Sender<Message>) -> Result<()> {
+        let now = self.clock.now();
+        let (due, later) = self
+            .in_transit
+            .drain(..)
+            .partition(|(due_at, _)| *due_at <= now);
+        self.in_transit = later;
+        self.local.extend(due.into_iter().map(|(_, msg)| msg));
+        while let Some(msg) = self.local.pop_front() {
+            self.delivered = self.delivered.saturating_add(1);
+            if let Err(e) = self.dispatch(msg, tx) {
+                eprintln!("Failed to handle local message: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // One anti-entropy round, every node syncs with its random peers
+    pub fn sync_round(&mut self, tx: Sender<Message>) -> Result<()> {
+        let (local_tx, local_rx) = mpsc::channel();
+        for node in self.nodes.values_mut() {
+            for message in node.request_sync_with_random_peers() {
+                message.send(local_tx.clone())?;
+            }
+        }
+        self.forward(local_rx, &tx)?;
+        self.deliver_local(&tx)
+    }
+
+    // Splits the hosted nodes into groups that can only reach each other.
+    // Nodes not listed in any group are cut off from everyone.
+    pub fn partition<Id>(&mut self, groups: &[&[Id]])
+    where
+        Id: Clone + Into<NodeId>,
+    {
+        let mut membership = HashMap::new();
+        for (group, ids) in groups.iter().enumerate() {
+            for id in ids.iter() {
+                membership.insert(id.clone().into(), group);
+            }
+        }
+        self.partitions = Some(membership);
+    }
+
+    pub fn heal(&mut self) {
+        self.partitions = None;
+    }
+
+    // Delays every message from `src` to `dest` by `latency` on the host's
+    // clock, so with a MockClock a reply can be made to arrive after the
+    // sender's retry fired. Delayed messages go out on the first route or
+    // tick once due. Zero removes the delay, messages already in transit
+    // keep theirs.
+    pub fn set_latency(
+        &mut self,
+        src: impl Into<NodeId>,
+        dest: impl Into<NodeId>,
+        latency: Duration,
+    ) {
+        let link = (src.into(), dest.into());
+        if latency.is_zero() {
+            self.latencies.remove(&link);
+        } else {
+            self.latencies.insert(link, latency);
+        }
+    }
+
+    // Messages between hosted nodes still held back by a link latency
+    pub fn in_transit(&self) -> usize {
+        self.in_transit.len()
+    }
+
+    fn is_partitioned(&self, src: &NodeId, dest: &NodeId) -> bool {
+        match &self.partitions {
+            Some(groups) => match (groups.get(src), groups.get(dest)) {
+                (Some(a), Some(b)) => a != b,
+                _ => true,
+            },
+            None => false,
+        }
+    }
+
+    // True once every hosted node holds the same values
+    pub fn converged(&self) -> bool {
+        let mut stores = self.nodes.values().map(|node| &node.store);
+        match stores.next() {
+            Some(first) => stores.all(|store| store == first),
+            None => true,
+        }
+    }
+
+    // Messages delivered and dropped (by partitions) between hosted nodes
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Handler runs over Config::slow_handler_threshold so far
+    pub fn slow_handlers(&self) -> u64 {
+        self.slow_handlers
+    }
+
+    // Looked up like a HashMap<NodeId, _>, by a &NodeId or a literal id
+    pub fn node<Q>(&self, id: &Q) -> Option<&Node<Data>>
+    where
+        NodeId: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.nodes.get(id)
+    }
+
+    pub fn node_mut<Q>(&mut self, id: &Q) -> Option<&mut Node<Data>>
+    where
+        NodeId: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.nodes.get_mut(id)
+    }
+
+    pub fn contains<Q>(&self, id: &Q) -> bool
+    where
+        NodeId: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.nodes.contains_key(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &NodeId> {
+        self.nodes.keys()
+    }
+}
+
+fn describe(msg: &Message) -> String {
+    format!("{} from {} on {}", msg.body.type_name(), msg.src, msg.dest)
+}
@@ -1,18 +1,74 @@
+#[cfg(feature = "bitmap-sync")]
+pub mod bitmap;
 mod broadcast;
+mod codec;
+mod config;
+pub mod conformance;
+mod custom;
 mod echo;
+mod host;
+mod liveness;
+mod membership;
 mod message_body;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod newness;
+mod quorum;
+mod rate_limit;
+mod read_wait;
+mod repair;
+mod schedule;
+pub mod selftest;
+mod sequence;
+#[cfg(feature = "signing")]
+pub mod signing;
+mod state;
+mod stats;
+mod store;
 #[cfg(test)]
 mod tests;
+mod tombstones;
 mod unique_id;
 
 use anyhow::Ok;
 use anyhow::Result;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[cfg(feature = "msgpack")]
+pub use crate::codec::MsgPackCodec;
+pub use crate::codec::{
+    decode_line, stream_read_ok, stream_sync_ok, BoundedLines, JsonCodec, OversizedMessage,
+    PeerCodec, StreamedBody, WireCodec,
+};
+pub use crate::config::{
+    Config, Consistency, GossipMode, SyncPayloadStrategy, SyncPeerWeights, Transport,
+};
+pub use crate::custom::CustomHandler;
+pub use crate::host::{Batch, Host};
+pub use crate::liveness::Liveness;
 pub use crate::message_body::MessageBody;
-pub use node_common::NodeTrait;
+pub use crate::newness::Newness;
+pub use crate::quorum::PendingRpc;
+pub use crate::rate_limit::{RateLimit, RateLimiter};
+pub use crate::schedule::{Schedule, Task};
+pub use crate::sequence::Sequences;
+pub use crate::state::NodeState;
+pub use crate::stats::Stats;
+pub use crate::store::{Store, ValueSet, ValueStore};
+pub use crate::tombstones::{AckMap, Tombstones};
+pub use node_common::{
+    canonical_members, Clock, ErrorCode, MockClock, NodeId, NodeTrait, OutboundQueue, PendingRpcs,
+    Prioritized, Priority, RpcError, SystemClock,
+};
 pub type Message = node_common::Message<MessageBody>;
 
 pub trait BroadcastNodeTrait: NodeTrait<Message = Message> {
@@ -21,55 +77,234 @@ pub trait BroadcastNodeTrait: NodeTrait<Message = Message> {
     fn handle_broadcast_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn handle_broadcast_ok_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn handle_read_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_read_ok_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn handle_topology_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn get_and_increment_msg_id(&self) -> u32;
+    // Empty until init has been handled
+    fn id(&self) -> &NodeId;
     fn handle_sync_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn handle_sync_ok_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
     fn request_sync_with_random_peers(&mut self) -> Vec<Message>;
     fn retry_messages(&mut self, tx: Sender<Message>) -> Result<()>;
     fn fanout_messages(&mut self, tx: Sender<Message>) -> Result<()>;
+    fn tick(&mut self, tx: Sender<Message>) -> Result<()>;
+    fn handle_custom_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_ping_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_pong_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_reconfigure_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_provenance_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_bulk_broadcast_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_read_wait_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_outbox_status_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_liveness_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_repair_request_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn handle_repair_reply_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()>;
+    fn wake_read_waiters(&mut self, tx: Sender<Message>) -> Result<()>;
+    fn record_ack(&mut self, type_name: &'static str);
     fn next(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
-        match msg.body {
+        // a misrouted message would be applied to the wrong node's state
+        let id = self.id();
+        if id.as_str().is_empty() && !matches!(msg.body, MessageBody::init { .. }) {
+            return self.handle_uninitialized_message(msg, tx);
+        }
+        if msg.dest != *id && !matches!(msg.body, MessageBody::init { .. }) {
+            anyhow::bail!(
+                "dropping {} from {} addressed to {}, this is {}",
+                msg.body.type_name(),
+                msg.src,
+                msg.dest,
+                id
+            );
+        }
+        if let Some(ack) = msg.body.ack_type() {
+            self.record_ack(ack);
+        }
+        let wake_tx = tx.clone();
+        let result = match msg.body {
             MessageBody::echo { .. } => self.handle_echo_message(msg, tx),
             MessageBody::init { .. } => self.handle_init_message(msg, tx),
             MessageBody::generate { .. } => self.handle_generate_message(msg, tx),
             MessageBody::broadcast { .. } => self.handle_broadcast_message(msg, tx),
             MessageBody::topology { .. } => self.handle_topology_message(msg, tx),
             MessageBody::read { .. } => self.handle_read_message(msg, tx),
+            MessageBody::read_ok { .. } => self.handle_read_ok_message(msg, tx),
             MessageBody::broadcast_ok { .. } => self.handle_broadcast_ok_message(msg, tx),
             MessageBody::sync { .. } => self.handle_sync_message(msg, tx),
             MessageBody::sync_ok { .. } => self.handle_sync_ok_message(msg, tx),
             MessageBody::gossip { .. } => self.handle_gossip_message(msg, tx),
             MessageBody::gossip_ok { .. } => self.handle_gossip_ok_message(msg, tx),
+            MessageBody::ping { .. } => self.handle_ping_message(msg, tx),
+            MessageBody::pong { .. } => self.handle_pong_message(msg, tx),
+            MessageBody::reconfigure { .. } => self.handle_reconfigure_message(msg, tx),
+            MessageBody::provenance { .. } => self.handle_provenance_message(msg, tx),
+            MessageBody::bulk_broadcast { .. } => self.handle_bulk_broadcast_message(msg, tx),
+            MessageBody::read_wait { .. } => self.handle_read_wait_message(msg, tx),
+            MessageBody::outbox_status { .. } => self.handle_outbox_status_message(msg, tx),
+            MessageBody::liveness { .. } => self.handle_liveness_message(msg, tx),
+            MessageBody::repair_request { .. } => self.handle_repair_request_message(msg, tx),
+            MessageBody::repair_reply { .. } => self.handle_repair_reply_message(msg, tx),
+            MessageBody::custom { .. } => self.handle_custom_message(msg, tx),
+            // acks nothing here waits on, e.g. a client tool's replies or a
+            // stray init_ok. Counting them above is all there is to do.
             MessageBody::init_ok { .. }
             | MessageBody::topology_ok { .. }
-            | MessageBody::read_ok { .. }
             | MessageBody::generate_ok { .. }
-            | MessageBody::echo_ok { .. } => unreachable!(),
+            | MessageBody::echo_ok { .. }
+            | MessageBody::reconfigure_ok { .. }
+            | MessageBody::provenance_ok { .. }
+            | MessageBody::bulk_broadcast_ok { .. }
+            | MessageBody::read_wait_ok { .. }
+            | MessageBody::outbox_status_ok { .. }
+            | MessageBody::liveness_ok { .. } => Ok(()),
+            MessageBody::error { .. } => self.handle_unsupported_message(msg, tx),
+        };
+        // whatever this message stored may be what a long poll waits on
+        self.wake_read_waiters(wake_tx)?;
+        result
+    }
+    // Without an id nothing can be served properly, e.g. a generated id would
+    // have no node prefix. Maelstrom may retry a TemporarilyUnavailable.
+    fn handle_uninitialized_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        let type_name = msg.body.type_name();
+        let src = msg.src.clone();
+        if let Some(msg_id) = msg.body.msg_id() {
+            let error = RpcError::temporarily_unavailable(format!("{type_name} before init"));
+            msg.into_reply(MessageBody::rpc_error(msg_id, &error))
+                .send(tx)?;
+        }
+        anyhow::bail!("{} from {} arrived before init", type_name, src)
+    }
+    // Maelstrom expects a NotSupported error instead of silence. The reply is
+    // still reported as an error so it shows up in the logs. Error bodies are
+    // never answered, otherwise two nodes could bounce them forever.
+    fn handle_unsupported_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        let type_name = msg.body.type_name();
+        let src = msg.src.clone();
+        if let Some(msg_id) = msg.body.msg_id() {
+            let error = RpcError::not_supported(format!("{type_name} is not supported"));
+            msg.into_reply(MessageBody::rpc_error(msg_id, &error))
+                .send(tx)?;
         }
+        anyhow::bail!("no handler for {} from {}", type_name, src)
+    }
+    // Panic boundary around `next`. A panicking handler is turned into an
+    // error for that one message so the node keeps serving the rest. State
+    // the handler touched before panicking is left as is. A handler error
+    // carrying an RpcError is also answered with an error reply, so the
+    // requester doesn't wait for an answer that never comes. Either way the
+    // error is returned to be logged.
+    fn dispatch(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        let request = msg
+            .body
+            .msg_id()
+            .map(|msg_id| (msg_id, msg.src.clone(), msg.dest.clone()));
+        let reply_tx = tx.clone();
+        guarded(request, reply_tx, || self.next(msg, tx))
     }
 }
 
-type Outbox = HashMap<String, HashSet<u32>>;
+// The panic boundary around a handler. A panic becomes an error instead of
+// taking the node down, and a failed request whose error is an RpcError gets
+// it as the reply. `request` is the (msg_id, src, dest) of the message being
+// handled, None when there is nobody to answer.
+pub(crate) fn guarded<F>(
+    request: Option<(u32, NodeId, NodeId)>,
+    reply_tx: Sender<Message>,
+    handler: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(handler)) {
+        std::result::Result::Ok(Err(e)) => {
+            if let (Some((msg_id, src, dest)), Some(error)) = (request, RpcError::find(&e)) {
+                let reply = Message {
+                    src: dest,
+                    dest: src,
+                    body: MessageBody::rpc_error(msg_id, error),
+                };
+                reply.send(reply_tx)?;
+            }
+            Err(e)
+        }
+        std::result::Result::Ok(result) => result,
+        Err(payload) => {
+            let reason = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(anyhow::anyhow!("handler panicked: {reason}"))
+        }
+    }
+}
+
+type Outbox = HashMap<NodeId, HashSet<u32>>;
 #[derive(Debug, Clone, Copy)]
 pub enum OutboxKind {
     RetryMsg,
     FanoutMsg,
 }
 
+// Fields are private so callers outside the crate can't break invariants
+// between them (store vs provenance, outboxes vs in-flight batches). Build a
+// node with the with_* constructors and read it through the accessors below.
 #[derive(Clone)]
 pub struct Node<Data> {
-    pub id: String,
-    pub node_ids: Vec<String>,
-    pub store: HashSet<Data>,
-    pub topology: HashMap<String, Vec<String>>,
+    id: NodeId,
+    node_ids: Vec<NodeId>,
+    //Values we hold, remembering the order they arrived in
+    store: ValueStore<Data>,
+    topology: HashMap<NodeId, Vec<NodeId>>,
     //We track our retries here
-    pub retry_outbox: Outbox,
+    retry_outbox: Outbox,
+    //Rotates which peer a budgeted retry pass starts with, see Config::retry_budget
+    retry_cursor: usize,
     //We collect fanout messages we have to send for each node, and send in one go
-    pub msg_outbox: Outbox,
+    msg_outbox: Outbox,
     //Minor Optimization : Tracking gossip messages we have sent, so that we can skip them during retries.
-    //Just to make things less chatty
-    pub in_flight_gossip: HashMap<u32, (String, HashSet<u32>)>,
+    //Just to make things less chatty. Keyed by (peer, msg_id), so an ack is a
+    //single lookup and only the peer a batch went to can acknowledge it
+    in_flight_gossip: HashMap<(NodeId, u32), InFlight>,
+    //Per destination token buckets, over-limit gossip stays in the outbox for the next pass
+    rate_limiter: RateLimiter,
+    clock: Arc<dyn Clock>,
+    schedule: Schedule,
+    config: Config,
+    //Removed values and which nodes have seen the removal, see tombstones.rs
+    tombstones: Tombstones,
+    //RPCs we sent and are waiting on, keyed by their msg_id
+    pending: PendingRpcs<PendingRpc>,
+    //Store size parked read_waits were last checked against, see read_wait.rs
+    waiters_checked_at: usize,
+    //Handlers for message types outside MessageBody, keyed by `type`
+    custom_handlers: HashMap<String, CustomHandler<Data>>,
+    //When each peer last answered a heartbeat, down peers are skipped
+    liveness: Liveness,
+    //Gossip sequence numbers per peer, in both directions
+    sequences: Sequences,
+    //Draws retry jitter, seeded from Config::seed when set
+    rng: StdRng,
+    //Who first delivered each value we hold, a client or the peer that gossiped
+    //or synced it to us. Answers the provenance debug query
+    provenance: HashMap<u32, NodeId>,
+    //How many of each `_ok` type we've received, see MessageBody::ack_type
+    acks_received: HashMap<&'static str, u64>,
+    //Broadcasts handled and gossip/syncs sent so far
+    stats: Stats,
+    //Decaying count of new values each peer delivered, see newness.rs
+    newness: Newness,
+    //When the last init was handled, retries and syncs wait out
+    //Config::startup_grace from here
+    initialized_at: Option<Instant>,
+}
+
+// A gossip batch waiting on its gossip_ok. Past `retry_at` it counts as lost
+// and its peer is retried on the next pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InFlight {
+    pub messages: HashSet<u32>,
+    pub retry_at: Instant,
 }
 
 impl<Data> Node<Data>
@@ -77,16 +312,88 @@ where
     Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Eq + Hash,
     Self: BroadcastNodeTrait,
 {
-    pub(crate) fn insert_if_absent(&mut self, payload: Data) -> Option<Data> {
-        if !self.store.contains(&payload) {
-            self.store.insert(payload.clone());
+    // `source` is whoever delivered the value, kept in provenance the first
+    // time the value is stored
+    pub(crate) fn insert_if_absent(&mut self, payload: Data, source: &NodeId) -> Option<Data> {
+        if self.tombstones.contains(payload.into()) {
+            return None;
+        }
+        if self.store.insert(payload) {
+            self.provenance
+                .entry(payload.into())
+                .or_insert_with(|| source.clone());
+            if *source != self.id && self.node_ids.contains(source) {
+                self.newness.record(source, 1, self.clock.now());
+            }
             Some(payload)
         } else {
             None
         }
     }
+    // Starts the node with `values` already stored, in iteration order, as if
+    // each had been broadcast to it. Provenance names them "seed". Lets tests
+    // set up divergent stores without replaying broadcasts.
+    pub fn with_store(mut self, values: impl IntoIterator<Item = Data>) -> Self {
+        let seed = NodeId::new("seed");
+        for value in values {
+            self.insert_if_absent(value, &seed);
+        }
+        self
+    }
+
+    // Deletes a value cluster wide, the tombstone travels with the next syncs
+    pub fn remove(&mut self, value: Data) {
+        self.store.remove(&value);
+        self.provenance.remove(&value.into());
+        self.tombstones.record(value.into(), &self.id);
+    }
+
+    // Applies a peer's tombstones and collects the ones everybody has seen
+    pub(crate) fn merge_tombstones(&mut self, peer: &NodeId, incoming: &AckMap) {
+        for value in self.tombstones.merge(peer, &self.id, incoming) {
+            self.store.remove(&Data::from(value));
+            self.provenance.remove(&value);
+        }
+        self.tombstones.collect_garbage(&self.node_ids);
+    }
+
+    // Ourselves included, so callers can filter any list of node ids
+    pub fn peer_is_up(&self, peer: &NodeId) -> bool {
+        *peer == self.id || self.liveness.is_up(peer, self.clock.now())
+    }
+
+    // Values only we hold and values only `other` holds, handy when a
+    // convergence test fails
+    pub fn store_diff(&self, other: &Self) -> (Vec<Data>, Vec<Data>) {
+        broadcast::split_difference(self.store.as_set(), other.store.as_set())
+    }
+
+    // Gossiped values still waiting on a gossip_ok, counted once per peer
+    pub fn unacked(&self) -> usize {
+        self.retry_outbox.values().map(|values| values.len()).sum()
+    }
+
+    pub fn store_checksum(&self) -> u64 {
+        broadcast::checksum(self.store.iter().map(|data| (*data).into()))
+    }
+
     pub(crate) fn read(&self) -> Vec<u32> {
-        self.store.iter().map(|data| data.clone().into()).collect()
+        self.store.iter().map(|data| (*data).into()).collect()
+    }
+
+    // Up to `limit` values starting at `offset` in insertion order, and where
+    // the next page starts if there is one. Pages stay stable while values
+    // are only added since new ones go to the end.
+    pub(crate) fn read_page(&self, offset: usize, limit: usize) -> (Vec<u32>, Option<usize>) {
+        let page: Vec<u32> = self
+            .store
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|data| (*data).into())
+            .collect();
+        let end = offset.saturating_add(page.len());
+        (page, (end < self.store.len()).then_some(end))
     }
     fn outbox_mut(&mut self, kind: OutboxKind) -> &mut Outbox {
         match kind {
@@ -98,56 +405,228 @@ where
     pub(crate) fn add_to_outbox(
         &mut self,
         kind: OutboxKind,
-        node_id: &str,
+        node_id: &NodeId,
         message: u32,
     ) -> Result<()> {
+        // clients only ever get replies, never gossip
+        if node_id.is_client() {
+            return Ok(());
+        }
         self.outbox_mut(kind)
-            .entry(node_id.to_owned())
+            .entry(node_id.clone())
             .or_default()
             .insert(message);
 
         Ok(())
     }
 
-    pub(crate) fn track_gossip_batch(
-        &mut self,
-        msg_id: u32,
-        node_id: String,
-        messages: HashSet<u32>,
-    ) {
-        self.in_flight_gossip.insert(msg_id, (node_id, messages));
+    // Records a batch about to be sent and returns the msg_id to send it
+    // under. The id is fresh and unused by any other batch in flight, so a
+    // gossip_ok can only ever acknowledge the batch it answers, whatever ids
+    // the broadcasts behind it arrived with.
+    pub(crate) fn track_gossip_batch(&mut self, node_id: NodeId, messages: HashSet<u32>) -> u32 {
+        let mut msg_id = self.get_and_increment_msg_id();
+        while self
+            .in_flight_gossip
+            .contains_key(&(node_id.clone(), msg_id))
+        {
+            msg_id = self.get_and_increment_msg_id();
+        }
+        let retry_at = self.clock.now() + self.config.retry_interval + self.retry_jitter();
+        self.in_flight_gossip
+            .insert((node_id, msg_id), InFlight { messages, retry_at });
+        msg_id
     }
 
-    pub(crate) fn has_in_flight_gossip_for(&self, node_id: &str) -> bool {
+    // Uniform in 0..=Config::retry_jitter. Each batch gets its own draw, so
+    // nodes that gossiped at the same moment don't all retry together.
+    fn retry_jitter(&mut self) -> Duration {
+        let max = self.config.retry_jitter.as_millis() as u64;
+        if max == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(self.rng.random_range(0..=max))
+    }
+
+    // True while `node_id` has a batch in flight that isn't due for a retry
+    pub(crate) fn has_in_flight_gossip_for(&self, node_id: &NodeId, now: Instant) -> bool {
         self.in_flight_gossip
-            .values()
-            .any(|(peer, _)| peer == node_id)
+            .iter()
+            .any(|((peer, _), batch)| peer == node_id && batch.retry_at > now)
     }
 
-    pub(crate) fn acknowledge_gossip_batch(&mut self, msg_id: u32) {
-        if let Some((node_id, acked_messages)) = self.in_flight_gossip.remove(&msg_id) {
-            if let Some(node_outbox) = self.retry_outbox.get_mut(&node_id) {
-                for message in acked_messages {
+    // Forgets batches past their retry deadline, their values are still in
+    // the retry outbox and go out again with the next retry
+    pub(crate) fn expire_in_flight_gossip(&mut self, now: Instant) {
+        self.in_flight_gossip
+            .retain(|_, batch| batch.retry_at > now);
+    }
+
+    pub(crate) fn reset_transient_state(&mut self) {
+        self.retry_outbox.clear();
+        self.msg_outbox.clear();
+        self.in_flight_gossip.clear();
+        self.pending.clear();
+        self.sequences.clear();
+    }
+
+    pub(crate) fn acknowledge_gossip_batch(&mut self, node_id: &NodeId, msg_id: u32) {
+        let key = (node_id.clone(), msg_id);
+        if let Some(acked) = self.in_flight_gossip.remove(&key) {
+            if let Some(node_outbox) = self.retry_outbox.get_mut(node_id) {
+                for message in acked.messages {
                     node_outbox.remove(&message);
                 }
                 if node_outbox.is_empty() {
-                    self.retry_outbox.remove(&node_id);
+                    self.retry_outbox.remove(node_id);
                 }
             }
         }
     }
 }
 
+impl<Data> Node<Data> {
+    // Joins the node to a cluster without an init message, as Maelstrom's
+    // init would
+    pub fn with_membership(mut self, id: impl Into<NodeId>, node_ids: Vec<NodeId>) -> Self {
+        self.id = id.into();
+        self.node_ids = canonical_members(node_ids);
+        self
+    }
+
+    pub fn with_topology(mut self, topology: HashMap<NodeId, Vec<NodeId>>) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.schedule.fanout_interval = config.fanout_interval;
+        self.schedule.retry_interval = config.retry_interval;
+        self.schedule.sync_interval = config.sync_interval;
+        self.schedule.heartbeat_interval = config.heartbeat_interval;
+        self.liveness.timeout = config.peer_timeout;
+        self.liveness.phi_threshold = config.phi_threshold;
+        self.newness.half_life = config.newness_half_life;
+        if let Some(seed) = config.seed {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+        self.rate_limiter = match config.rate_limit {
+            Some(limit) => RateLimiter::new(limit),
+            None => RateLimiter::unlimited(),
+        };
+        self.config = config;
+        self
+    }
+
+    // Routes messages of `type_name` to `handler`, replacing any earlier one
+    pub fn register_custom(&mut self, type_name: &str, handler: CustomHandler<Data>) {
+        self.custom_handlers.insert(type_name.to_owned(), handler);
+    }
+
+    // Swaps the time source, e.g. for a MockClock in tests. The schedule is
+    // restarted so intervals are measured on the new clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.schedule.restart(clock.now());
+        self.liveness.restart();
+        self.clock = clock;
+        self
+    }
+    pub fn node_ids(&self) -> &[NodeId] {
+        &self.node_ids
+    }
+
+    pub fn store(&self) -> &ValueStore<Data> {
+        &self.store
+    }
+
+    pub fn store_len(&self) -> usize {
+        self.store.len()
+    }
+
+    // Our topology entry, empty before a topology message arrives
+    pub fn neighbors(&self) -> &[NodeId] {
+        self.topology.get(&self.id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // `_ok` bodies received per type, whether or not anything was waiting on them
+    pub fn acks_received(&self) -> &HashMap<&'static str, u64> {
+        &self.acks_received
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // Values each peer hasn't acked yet. Everything we gossip stays in the
+    // retry outbox until its gossip_ok, so this counts queued and in-flight
+    // values alike.
+    pub fn outbox_depths(&self) -> HashMap<NodeId, usize> {
+        outbox_sizes(&self.retry_outbox)
+    }
+
+    // (peer, msg_id) of every gossip batch still waiting on its gossip_ok
+    pub fn in_flight_batches(&self) -> impl Iterator<Item = (&NodeId, u32)> {
+        self.in_flight_gossip
+            .keys()
+            .map(|(peer, msg_id)| (peer, *msg_id))
+    }
+}
+
+fn outbox_sizes(outbox: &Outbox) -> HashMap<NodeId, usize> {
+    outbox
+        .iter()
+        .map(|(peer, messages)| (peer.clone(), messages.len()))
+        .collect()
+}
+
+// Summary rather than a full dump, stores can hold thousands of values
+impl<Data> fmt::Debug for Node<Data> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node")
+            .field("id", &self.id)
+            .field("node_ids", &self.node_ids)
+            .field("store_len", &self.store.len())
+            .field("topology", &self.topology)
+            .field("retry_outbox", &outbox_sizes(&self.retry_outbox))
+            .field("msg_outbox", &outbox_sizes(&self.msg_outbox))
+            .field("in_flight_gossip", &self.in_flight_gossip.len())
+            .field("tombstones", &self.tombstones.len())
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
 impl<Data> Default for Node<Data> {
     fn default() -> Self {
         Self {
             id: Default::default(),
             node_ids: Default::default(),
-            store: HashSet::new(),
+            store: ValueStore::new(),
             topology: HashMap::new(),
             retry_outbox: HashMap::new(),
             msg_outbox: HashMap::new(),
             in_flight_gossip: HashMap::new(),
+            rate_limiter: RateLimiter::unlimited(),
+            clock: Arc::new(SystemClock),
+            schedule: Schedule::new(SystemClock.now()),
+            config: Config::default(),
+            tombstones: Tombstones::new(),
+            pending: PendingRpcs::new(),
+            waiters_checked_at: 0,
+            custom_handlers: HashMap::new(),
+            liveness: Liveness::new(Config::default().peer_timeout),
+            sequences: Sequences::new(),
+            retry_cursor: 0,
+            rng: StdRng::from_os_rng(),
+            provenance: HashMap::new(),
+            acks_received: HashMap::new(),
+            stats: Stats::default(),
+            newness: Newness::new(Config::default().newness_half_life),
+            initialized_at: None,
         }
     }
 }
@@ -160,13 +639,30 @@ where
 
     fn new() -> Self {
         Self {
-            id: String::new(),
+            id: NodeId::default(),
             node_ids: vec![],
-            store: HashSet::new(),
+            store: ValueStore::new(),
             topology: HashMap::new(),
             retry_outbox: HashMap::new(),
             msg_outbox: HashMap::new(),
             in_flight_gossip: HashMap::new(),
+            rate_limiter: RateLimiter::unlimited(),
+            clock: Arc::new(SystemClock),
+            schedule: Schedule::new(SystemClock.now()),
+            config: Config::default(),
+            tombstones: Tombstones::new(),
+            pending: PendingRpcs::new(),
+            waiters_checked_at: 0,
+            custom_handlers: HashMap::new(),
+            liveness: Liveness::new(Config::default().peer_timeout),
+            sequences: Sequences::new(),
+            retry_cursor: 0,
+            rng: StdRng::from_os_rng(),
+            provenance: HashMap::new(),
+            acks_received: HashMap::new(),
+            stats: Stats::default(),
+            newness: Newness::new(Config::default().newness_half_life),
+            initialized_at: None,
         }
     }
     fn handle_init_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
@@ -176,7 +672,15 @@ where
             node_id,
         } = msg.body
         {
-            (self.id, self.node_ids) = (node_id.clone(), node_ids.clone());
+            // A second init means Maelstrom restarted us. The store is kept since
+            // values are never retracted, but anything in flight refers to the
+            // previous incarnation and is dropped.
+            if !self.id.as_str().is_empty() {
+                eprintln!("Re-init of {} as {}, resetting outboxes", self.id, node_id);
+                self.reset_transient_state();
+            }
+            (self.id, self.node_ids) = (node_id.clone(), canonical_members(node_ids));
+            self.initialized_at = Some(self.clock.now());
 
             let reply = Message {
                 src: node_id,
@@ -219,6 +723,10 @@ where
         broadcast::handle_read_message(self, msg, tx)
     }
 
+    fn handle_read_ok_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        quorum::handle_read_ok_message(self, msg, tx)
+    }
+
     fn handle_topology_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
         broadcast::handle_topology_message(self, msg, tx)
     }
@@ -227,6 +735,10 @@ where
         unique_id::generate_message_id()
     }
 
+    fn id(&self) -> &NodeId {
+        &self.id
+    }
+
     fn handle_sync_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
         broadcast::handle_sync_message(self, msg, tx)
     }
@@ -249,4 +761,47 @@ where
     fn fanout_messages(&mut self, tx: Sender<Message>) -> Result<()> {
         broadcast::fanout_messages(self, tx)
     }
+    fn tick(&mut self, tx: Sender<Message>) -> Result<()> {
+        broadcast::tick(self, tx)
+    }
+    fn handle_custom_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        custom::handle_custom_message(self, msg, tx)
+    }
+    fn handle_ping_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        liveness::handle_ping_message(self, msg, tx)
+    }
+    fn handle_pong_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        liveness::handle_pong_message(self, msg, tx)
+    }
+    fn handle_reconfigure_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        membership::handle_reconfigure_message(self, msg, tx)
+    }
+    fn handle_provenance_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        broadcast::handle_provenance_message(self, msg, tx)
+    }
+    fn handle_bulk_broadcast_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        broadcast::handle_bulk_broadcast_message(self, msg, tx)
+    }
+    fn handle_read_wait_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        read_wait::handle_read_wait_message(self, msg, tx)
+    }
+    fn handle_outbox_status_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        broadcast::handle_outbox_status_message(self, msg, tx)
+    }
+    fn handle_liveness_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        liveness::handle_liveness_message(self, msg, tx)
+    }
+    fn handle_repair_request_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        repair::handle_repair_request_message(self, msg, tx)
+    }
+    fn handle_repair_reply_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        repair::handle_repair_reply_message(self, msg, tx)
+    }
+    fn wake_read_waiters(&mut self, tx: Sender<Message>) -> Result<()> {
+        read_wait::wake_read_waiters(self, tx)
+    }
+    fn record_ack(&mut self, type_name: &'static str) {
+        let count = self.acks_received.entry(type_name).or_default();
+        *count = count.saturating_add(1);
+    }
 }
@@ -0,0 +1,241 @@
+use crate::{BroadcastNodeTrait, Message, MessageBody, Node, NodeId};
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+// Inter-arrival times kept per peer for phi
+const WINDOW: usize = 100;
+// arrivals needed before phi replaces the fixed timeout for a peer
+const MIN_SAMPLES: usize = 3;
+
+// Last time each peer showed a sign of life, a pong or a ping of its own. A
+// peer silent for longer than `timeout` counts as down and is left out of
+// fanout and sync until it answers again. Peers we never heard from count as
+// up, so without heartbeats (or before the first one is answered) nobody is
+// left out.
+//
+// With a phi threshold set, a peer with enough history is judged by the phi
+// accrual detector instead: phi is how unlikely (-log10 of the probability)
+// it is to still hear from the peer after this much silence, given the
+// spread of its past inter-arrival times. Only pongs answering our own pings
+// are sampled, so the intervals follow our heartbeat schedule and aren't
+// skewed by when the peer happens to ping us. A peer whose heartbeats are
+// regular is suspected soon after one is late, a jittery one is given more
+// slack.
+#[derive(Debug, Clone)]
+pub struct Liveness {
+    pub timeout: Duration,
+    pub phi_threshold: Option<f64>,
+    last_seen: HashMap<NodeId, Instant>,
+    // msg_id of the latest ping we sent each peer, until it's answered
+    pinged: HashMap<NodeId, u32>,
+    // last pong answering one of our pings, what phi measures silence from
+    last_answer: HashMap<NodeId, Instant>,
+    // recent inter-arrival times of those pongs per peer in ms, oldest first
+    intervals: HashMap<NodeId, VecDeque<f64>>,
+}
+
+impl Liveness {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            phi_threshold: None,
+            last_seen: HashMap::new(),
+            pinged: HashMap::new(),
+            last_answer: HashMap::new(),
+            intervals: HashMap::new(),
+        }
+    }
+
+    pub fn pinged(&mut self, peer: &NodeId, msg_id: u32) {
+        self.pinged.insert(peer.clone(), msg_id);
+    }
+
+    // Returns true if `peer` was down until now
+    pub fn saw(&mut self, peer: &NodeId, now: Instant) -> bool {
+        let was_up = self.is_up(peer, now);
+        self.last_seen.insert(peer.clone(), now);
+        !was_up
+    }
+
+    // A pong from `peer`. Sampled for phi only if it answers the latest ping
+    // we sent it, a stale or duplicated one just counts as a sign of life.
+    // Returns true if `peer` was down until now.
+    pub fn answered(&mut self, peer: &NodeId, in_reply_to: u32, now: Instant) -> bool {
+        let was_up = self.is_up(peer, now);
+        if self.pinged.get(peer) == Some(&in_reply_to) {
+            self.pinged.remove(peer);
+            if let Some(last) = self.last_answer.insert(peer.clone(), now) {
+                let samples = self.intervals.entry(peer.clone()).or_default();
+                if samples.len() == WINDOW {
+                    samples.pop_front();
+                }
+                samples.push_back(millis(now.saturating_duration_since(last)));
+            }
+        }
+        self.last_seen.insert(peer.clone(), now);
+        !was_up
+    }
+
+    pub fn is_up(&self, peer: &NodeId, now: Instant) -> bool {
+        if let (Some(threshold), Some(phi)) = (self.phi_threshold, self.phi(peer, now)) {
+            return phi < threshold;
+        }
+        self.last_seen
+            .get(peer)
+            .is_none_or(|last| now.saturating_duration_since(*last) < self.timeout)
+    }
+
+    // Suspicion level of `peer`, None until it has MIN_SAMPLES answered
+    // pings on record. Computed whether or not a threshold is set.
+    pub fn phi(&self, peer: &NodeId, now: Instant) -> Option<f64> {
+        let samples = self
+            .intervals
+            .get(peer)
+            .filter(|s| s.len() >= MIN_SAMPLES)?;
+        let silence = millis(now.saturating_duration_since(*self.last_answer.get(peer)?));
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+        // perfectly regular heartbeats would make any delay infinitely suspect
+        let std_dev = variance.sqrt().max(mean / 10.0).max(1.0);
+        Some(phi(silence, mean, std_dev))
+    }
+
+    // phi of every peer that has one
+    pub fn phis(&self, now: Instant) -> HashMap<NodeId, f64> {
+        self.intervals
+            .keys()
+            .filter_map(|peer| Some((peer.clone(), self.phi(peer, now)?)))
+            .collect()
+    }
+
+    pub fn forget(&mut self, peer: &NodeId) {
+        self.last_seen.remove(peer);
+        self.pinged.remove(peer);
+        self.last_answer.remove(peer);
+        self.intervals.remove(peer);
+    }
+
+    pub fn restart(&mut self) {
+        self.last_seen.clear();
+        self.pinged.clear();
+        self.last_answer.clear();
+        self.intervals.clear();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// -log10 of the chance an arrival comes more than `silence` after the last
+// one, with the normal CDF approximated by a logistic as in Akka's detector.
+// Capped so a long silence stays a finite number on the wire.
+fn phi(silence: f64, mean: f64, std_dev: f64) -> f64 {
+    let y = (silence - mean) / std_dev;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    let phi = if silence > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    };
+    phi.min(f64::MAX)
+}
+
+// One ping to every other node, sent on the heartbeat interval
+pub fn heartbeat<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let peers: Vec<NodeId> = node
+        .node_ids
+        .iter()
+        .filter(|peer| **peer != node.id)
+        .cloned()
+        .collect();
+    for peer in peers {
+        let msg_id = node.get_and_increment_msg_id();
+        node.liveness.pinged(&peer, msg_id);
+        Message {
+            src: node.id.clone(),
+            dest: peer,
+            body: MessageBody::ping { msg_id },
+        }
+        .send(tx.clone())?;
+    }
+    Ok(())
+}
+
+pub fn handle_ping_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::ping { msg_id } = msg.body {
+        // a sign of life, but not an answer to our heartbeat, so phi
+        // doesn't sample it
+        if node.liveness.saw(&msg.src, node.clock.now()) {
+            eprintln!("Peer {} is back up", msg.src);
+        }
+        let reply = msg.into_reply(MessageBody::pong {
+            in_reply_to: msg_id,
+        });
+        reply.send(tx)?;
+    }
+    Ok(())
+}
+
+pub fn handle_pong_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    _tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::pong { in_reply_to } = msg.body {
+        if node
+            .liveness
+            .answered(&msg.src, in_reply_to, node.clock.now())
+        {
+            eprintln!("Peer {} is back up", msg.src);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_liveness_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::liveness { msg_id } = msg.body {
+        let now = node.clock.now();
+        let down = node
+            .node_ids
+            .iter()
+            .filter(|peer| !node.peer_is_up(peer))
+            .cloned()
+            .collect();
+        msg.into_reply(MessageBody::liveness_ok {
+            in_reply_to: msg_id,
+            phi: node.liveness.phis(now),
+            down,
+        })
+        .send(tx)?;
+    }
+    Ok(())
+}
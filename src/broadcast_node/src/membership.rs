@@ -0,0 +1,88 @@
+use crate::{canonical_members, BroadcastNodeTrait, Message, MessageBody, Node, NodeId};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::mpsc::Sender;
+
+// Replaces the membership without a restart. Everything kept per peer for a
+// node that left is dropped, its unacked gossip included, and tombstones
+// only it hadn't acked can now be collected. Nodes that joined start out
+// like peers we never heard from.
+pub fn handle_reconfigure_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::reconfigure {
+        msg_id,
+        ref node_ids,
+    } = msg.body
+    {
+        let members: HashSet<&str> = node_ids.iter().map(NodeId::as_str).collect();
+        let removed: Vec<NodeId> = node
+            .node_ids
+            .iter()
+            .filter(|id| !members.contains(id.as_str()))
+            .cloned()
+            .collect();
+        let mut unacked = 0;
+        for peer in &removed {
+            unacked += node.retry_outbox.get(peer.as_str()).map_or(0, HashSet::len);
+            node.retry_outbox.remove(peer.as_str());
+            node.msg_outbox.remove(peer.as_str());
+            node.liveness.forget(peer);
+            node.newness.forget(peer);
+            node.sequences.forget(peer);
+            node.topology.remove(peer.as_str());
+        }
+        node.in_flight_gossip
+            .retain(|(peer, _), _| members.contains(peer.as_str()));
+        for neighbours in node.topology.values_mut() {
+            neighbours.retain(|peer| members.contains(peer.as_str()));
+        }
+        node.node_ids = canonical_members(node_ids.clone());
+        node.tombstones.collect_garbage(&node.node_ids);
+        if !removed.is_empty() {
+            eprintln!(
+                "Removed {:?} from the membership, dropping {} unacked values",
+                removed, unacked
+            );
+        }
+
+        let reply = msg.into_reply(MessageBody::reconfigure_ok {
+            in_reply_to: msg_id,
+        });
+        reply.send(tx)?;
+    }
+    Ok(())
+}
+
+// Outbox entries for a peer outside node_ids would be retried forever, it's
+// never going to ack them. Reconfigure prunes departed peers itself, this
+// catches the rest, e.g. a topology naming a node that isn't a member.
+pub(crate) fn drop_non_member_outboxes<Data>(node: &mut Node<Data>) {
+    if node.node_ids.is_empty() {
+        return;
+    }
+    let strays: Vec<NodeId> = node
+        .retry_outbox
+        .keys()
+        .chain(node.msg_outbox.keys())
+        .filter(|peer| !node.node_ids.iter().any(|id| id == *peer))
+        .cloned()
+        .collect();
+    for peer in strays {
+        let unacked = node
+            .retry_outbox
+            .remove(&peer)
+            .map_or(0, |values| values.len());
+        node.msg_outbox.remove(&peer);
+        if unacked > 0 {
+            eprintln!("Warning: {peer} isn't a member, dropping {unacked} unacked values for it");
+        }
+    }
+}
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 
+use crate::config::GossipMode;
+use crate::tombstones::AckMap;
+use node_common::{NodeId, Prioritized, Priority, RpcError};
 use serde::{Deserialize, Serialize};
 
+// Bodies are JSON objects, so the order fields are declared in here (or sent
+// in by Maelstrom) doesn't matter, serde matches them by name.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
+#[allow(non_camel_case_types)]
 pub enum MessageBody {
     #[serde(rename_all = "snake_case")]
     broadcast {
@@ -11,12 +17,16 @@ pub enum MessageBody {
         msg_id: u32,
     },
 
+    // Acks our own nodes send always carry in_reply_to. Others might not, an
+    // ack without one is logged and otherwise ignored rather than failing to
+    // decode.
     broadcast_ok {
-        in_reply_to: u32,
+        #[serde(default)]
+        in_reply_to: Option<u32>,
         msg_id: u32,
     },
     topology {
-        topology: HashMap<String, Vec<String>>,
+        topology: HashMap<NodeId, Vec<NodeId>>,
         msg_id: u32,
     },
     topology_ok {
@@ -25,11 +35,30 @@ pub enum MessageBody {
     },
     read {
         msg_id: u32,
+        // union the stores of a majority of nodes before replying
+        #[serde(default)]
+        quorum: bool,
+        // page through the store in insertion order, local reads only.
+        // Without either field the whole store is returned.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+        // answer with this node's store instead of ours, to inspect how two
+        // nodes diverge. Paging applies to theirs.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<NodeId>,
     },
     read_ok {
         messages: Vec<u32>,
         in_reply_to: u32,
         msg_id: u32,
+        // offset of the next page, absent on the last one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_offset: Option<usize>,
+        // hex HMAC, see signing.rs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
     },
 
     generate {
@@ -53,29 +82,309 @@ pub enum MessageBody {
         echo: String,
     },
 
+    // fields a newer Maelstrom adds are ignored, nothing here denies unknown
+    // fields. Only a missing or mistyped one fails, and then the body falls
+    // through to custom.
     init {
         msg_id: u32,
-        node_id: String,
-        node_ids: Vec<String>,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
     },
     init_ok {
         in_reply_to: u32,
     },
+    error {
+        in_reply_to: u32,
+        code: u32,
+        text: String,
+    },
     //Custom messages not part of the protocol
     sync {
         msg_id: u32,
         messages: Vec<u32>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        tombstones: AckMap,
+        // Node::store_checksum of the sender, equal sums mean nothing to swap
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checksum: Option<u64>,
+        // per-peer sync round, see Sequences::next_sync_for
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        // what the responder does with this sync, see GossipMode
+        #[serde(default, skip_serializing_if = "GossipMode::is_push_pull")]
+        mode: GossipMode,
+        // the payload encoded by bitmap.rs in place of `messages`, only set
+        // when built with the `bitmap-sync` feature
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bitmap: Option<String>,
+        // hex HMAC, see signing.rs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
     },
     sync_ok {
         msg_id: u32,
-        in_reply_to: u32,
+        // only logged when missing, values are merged either way
+        #[serde(default)]
+        in_reply_to: Option<u32>,
         messages: Vec<u32>,
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        tombstones: AckMap,
+        // echoed from the sync, replies without one are always merged
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        // cut short by Config::sync_reply_limit, sync again for the rest
+        #[serde(default)]
+        more: bool,
+        // `messages` encoded by bitmap.rs, as on sync
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        bitmap: Option<String>,
+        // hex HMAC, see signing.rs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
     },
     gossip {
         msg_id: u32,
         messages: Vec<u32>,
+        // hex HMAC, only set when built with the `signing` feature and a key,
+        // as on every other body that carries values
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        // per-link sequence number, set when Config::sequence_gossip is on
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        // a retry or read-repair push, queued behind fresh gossip. Only the
+        // sender's queue looks at it, it never goes on the wire.
+        #[serde(skip)]
+        background: bool,
     },
     gossip_ok {
+        // without it no batch can be acked, the values are simply retried
+        #[serde(default)]
+        in_reply_to: Option<u32>,
+    },
+    ping {
+        msg_id: u32,
+    },
+    pong {
+        in_reply_to: u32,
+    },
+    // membership change, replaces node_ids
+    reconfigure {
+        msg_id: u32,
+        node_ids: Vec<NodeId>,
+    },
+    reconfigure_ok {
+        in_reply_to: u32,
+    },
+    // debug query, which node first delivered each value we hold
+    provenance {
+        msg_id: u32,
+    },
+    provenance_ok {
+        in_reply_to: u32,
+        sources: HashMap<u32, NodeId>,
+    },
+    // load testing, many broadcasts in one request and one reply
+    bulk_broadcast {
+        msg_id: u32,
+        messages: Vec<u32>,
+    },
+    bulk_broadcast_ok {
+        msg_id: u32,
         in_reply_to: u32,
     },
+    // debug query, values each peer still hasn't acked. A peer whose depth
+    // keeps growing is likely partitioned from us.
+    outbox_status {
+        msg_id: u32,
+    },
+    outbox_status_ok {
+        in_reply_to: u32,
+        depths: HashMap<NodeId, usize>,
+    },
+    // debug query, phi of every peer with enough heartbeat history and which
+    // members we currently treat as down
+    liveness {
+        msg_id: u32,
+    },
+    liveness_ok {
+        in_reply_to: u32,
+        phi: HashMap<NodeId, f64>,
+        down: Vec<NodeId>,
+    },
+    // asks for the gossip batches with these sequence numbers again, after a
+    // gap in them showed they were lost
+    repair_request {
+        msg_id: u32,
+        missing: Vec<u64>,
+    },
+    // the values of every requested batch still logged, `unknown` lists the
+    // ones that aren't
+    repair_reply {
+        in_reply_to: u32,
+        values: Vec<u32>,
+        unknown: Vec<u64>,
+        // hex HMAC, see signing.rs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+    },
+    // long poll, answered once the store holds more than `since` values or
+    // after `timeout` ms, whichever comes first
+    read_wait {
+        msg_id: u32,
+        since: usize,
+        timeout: u64,
+    },
+    // values stored after the first `since`, empty on timeout. `next` is the
+    // store size to wait on in the following read_wait.
+    read_wait_ok {
+        msg_id: u32,
+        in_reply_to: u32,
+        messages: Vec<u32>,
+        next: usize,
+    },
+    // Catch-all for any other `type`, handed to a handler registered with
+    // Node::register_custom. Must stay last, serde only tries it once none of
+    // the tagged variants above match. The payload is the rest of the body.
+    #[serde(untagged)]
+    custom {
+        #[serde(rename = "type")]
+        type_name: String,
+        #[serde(flatten)]
+        payload: serde_json::Value,
+    },
+}
+
+impl MessageBody {
+    // The error reply carrying a handler's RpcError
+    pub fn rpc_error(in_reply_to: u32, error: &RpcError) -> Self {
+        MessageBody::error {
+            in_reply_to,
+            code: error.code.code(),
+            text: error.text.clone(),
+        }
+    }
+
+    // The request id, if this body carries one. Custom bodies are searched for
+    // a numeric `msg_id` in their payload.
+    pub fn msg_id(&self) -> Option<u32> {
+        match self {
+            MessageBody::broadcast { msg_id, .. }
+            | MessageBody::broadcast_ok { msg_id, .. }
+            | MessageBody::topology { msg_id, .. }
+            | MessageBody::topology_ok { msg_id, .. }
+            | MessageBody::read { msg_id, .. }
+            | MessageBody::read_ok { msg_id, .. }
+            | MessageBody::generate { msg_id }
+            | MessageBody::generate_ok { msg_id, .. }
+            | MessageBody::echo { msg_id, .. }
+            | MessageBody::echo_ok { msg_id, .. }
+            | MessageBody::init { msg_id, .. }
+            | MessageBody::sync { msg_id, .. }
+            | MessageBody::sync_ok { msg_id, .. }
+            | MessageBody::gossip { msg_id, .. }
+            | MessageBody::ping { msg_id }
+            | MessageBody::reconfigure { msg_id, .. }
+            | MessageBody::provenance { msg_id }
+            | MessageBody::bulk_broadcast { msg_id, .. }
+            | MessageBody::bulk_broadcast_ok { msg_id, .. }
+            | MessageBody::read_wait { msg_id, .. }
+            | MessageBody::read_wait_ok { msg_id, .. }
+            | MessageBody::outbox_status { msg_id }
+            | MessageBody::liveness { msg_id }
+            | MessageBody::repair_request { msg_id, .. } => Some(*msg_id),
+            MessageBody::custom { payload, .. } => payload
+                .get("msg_id")
+                .and_then(|id| id.as_u64())
+                .and_then(|id| u32::try_from(id).ok()),
+            MessageBody::init_ok { .. }
+            | MessageBody::error { .. }
+            | MessageBody::gossip_ok { .. }
+            | MessageBody::pong { .. }
+            | MessageBody::reconfigure_ok { .. }
+            | MessageBody::provenance_ok { .. }
+            | MessageBody::outbox_status_ok { .. }
+            | MessageBody::liveness_ok { .. }
+            | MessageBody::repair_reply { .. } => None,
+        }
+    }
+
+    // The `type` tag of an `_ok` body, without serializing it like type_name
+    // does. Node::acks_received is keyed by these.
+    pub fn ack_type(&self) -> Option<&'static str> {
+        match self {
+            MessageBody::init_ok { .. } => Some("init_ok"),
+            MessageBody::topology_ok { .. } => Some("topology_ok"),
+            MessageBody::broadcast_ok { .. } => Some("broadcast_ok"),
+            MessageBody::read_ok { .. } => Some("read_ok"),
+            MessageBody::generate_ok { .. } => Some("generate_ok"),
+            MessageBody::echo_ok { .. } => Some("echo_ok"),
+            MessageBody::sync_ok { .. } => Some("sync_ok"),
+            MessageBody::gossip_ok { .. } => Some("gossip_ok"),
+            MessageBody::reconfigure_ok { .. } => Some("reconfigure_ok"),
+            MessageBody::provenance_ok { .. } => Some("provenance_ok"),
+            MessageBody::bulk_broadcast_ok { .. } => Some("bulk_broadcast_ok"),
+            MessageBody::read_wait_ok { .. } => Some("read_wait_ok"),
+            MessageBody::outbox_status_ok { .. } => Some("outbox_status_ok"),
+            MessageBody::liveness_ok { .. } => Some("liveness_ok"),
+            _ => None,
+        }
+    }
+
+    // The wire `type` tag
+    pub fn type_name(&self) -> String {
+        match self {
+            MessageBody::custom { type_name, .. } => type_name.clone(),
+            other => serde_json::to_value(other)
+                .ok()
+                .and_then(|body| body.get("type")?.as_str().map(str::to_owned))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Prioritized for MessageBody {
+    fn priority(&self) -> Priority {
+        match self {
+            MessageBody::init_ok { .. }
+            | MessageBody::topology_ok { .. }
+            | MessageBody::broadcast_ok { .. }
+            | MessageBody::read_ok { .. }
+            | MessageBody::generate_ok { .. }
+            | MessageBody::echo_ok { .. }
+            | MessageBody::gossip_ok { .. }
+            | MessageBody::pong { .. }
+            | MessageBody::reconfigure_ok { .. }
+            | MessageBody::provenance_ok { .. }
+            | MessageBody::bulk_broadcast_ok { .. }
+            | MessageBody::read_wait_ok { .. }
+            | MessageBody::outbox_status_ok { .. }
+            | MessageBody::liveness_ok { .. }
+            | MessageBody::error { .. } => Priority::High,
+            MessageBody::broadcast { .. }
+            | MessageBody::topology { .. }
+            | MessageBody::read { .. }
+            | MessageBody::generate { .. }
+            | MessageBody::echo { .. }
+            | MessageBody::init { .. }
+            | MessageBody::ping { .. }
+            | MessageBody::reconfigure { .. }
+            | MessageBody::provenance { .. }
+            | MessageBody::bulk_broadcast { .. }
+            | MessageBody::read_wait { .. }
+            | MessageBody::outbox_status { .. }
+            | MessageBody::liveness { .. }
+            | MessageBody::custom { .. }
+            | MessageBody::gossip {
+                background: false, ..
+            } => Priority::Normal,
+            MessageBody::sync { .. }
+            | MessageBody::sync_ok { .. }
+            | MessageBody::gossip {
+                background: true, ..
+            }
+            | MessageBody::repair_request { .. }
+            | MessageBody::repair_reply { .. } => Priority::Low,
+        }
+    }
 }
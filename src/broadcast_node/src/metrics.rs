@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::hash::Hash;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::{BroadcastNodeTrait, Host, Node};
+
+// How long a scrape waits for the node loop to render a fresh page before it
+// is served the last one
+const RENDER_WAIT: Duration = Duration::from_millis(500);
+
+// Serves GET /metrics over HTTP on a side port in the Prometheus text format.
+// HTTP is answered by hand, hyper isn't available to this build and a scrape
+// needs nothing more than a request line and a body.
+//
+// Only the node loop can read the nodes, so a scrape asks for a page and
+// waits for the next Host::tick to render one. Nothing is rendered while
+// nobody scrapes, and each connection is answered on its own thread so a
+// slow client can't hold up the others. The server threads never touch the
+// nodes or stdout.
+pub struct Exporter {
+    shared: Arc<Shared>,
+    addr: SocketAddr,
+}
+
+#[derive(Default)]
+struct Shared {
+    page: Mutex<Page>,
+    rendered: Condvar,
+}
+
+#[derive(Default)]
+struct Page {
+    text: String,
+    // bumped on every render, a scrape waits for it to move
+    generation: u64,
+    // a scrape is waiting for a render
+    wanted: bool,
+}
+
+impl Exporter {
+    pub fn start(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("binding {addr}"))?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        let served = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let served = served.clone();
+                thread::spawn(move || {
+                    if let Err(e) = answer(stream, &served) {
+                        eprintln!("Failed to answer metrics scrape: {:#}", e);
+                    }
+                });
+            }
+        });
+        eprintln!("Serving metrics on http://{addr}/metrics");
+        Ok(Self { shared, addr })
+    }
+
+    // The bound address, useful when started on port 0
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Renders a page if a scrape is waiting for one
+    pub fn publish<Data>(&mut self, host: &Host<Data>)
+    where
+        Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+        Node<Data>: BroadcastNodeTrait,
+    {
+        if !self.shared.page.lock().is_ok_and(|page| page.wanted) {
+            return;
+        }
+        let text = render(host);
+        if let Ok(mut page) = self.shared.page.lock() {
+            page.text = text;
+            page.generation = page.generation.wrapping_add(1);
+            page.wanted = false;
+        }
+        self.shared.rendered.notify_all();
+    }
+}
+
+// The page rendered after this call, or the last one if the node loop
+// doesn't get to it within RENDER_WAIT
+fn fresh_page(shared: &Shared) -> String {
+    let Ok(mut page) = shared.page.lock() else {
+        return String::new();
+    };
+    page.wanted = true;
+    let asked_at = page.generation;
+    match shared
+        .rendered
+        .wait_timeout_while(page, RENDER_WAIT, |page| page.generation == asked_at)
+    {
+        Ok((page, _)) => page.text.clone(),
+        Err(_) => String::new(),
+    }
+}
+
+fn answer(stream: TcpStream, shared: &Shared) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // the rest of the request head, nothing in it matters to us
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut stream = reader.into_inner();
+    let (status, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", fresh_page(shared)),
+        _ => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+// Every hosted node's counters and gauges, labelled by node id
+pub fn render<Data>(host: &Host<Data>) -> String
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let mut ids: Vec<_> = host.ids().collect();
+    ids.sort();
+    let nodes: Vec<&Node<Data>> = ids.into_iter().filter_map(|id| host.node(id)).collect();
+
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    };
+    let per_node = |f: &dyn Fn(&Node<Data>) -> u64| {
+        nodes
+            .iter()
+            .map(|node| (format!("node=\"{}\"", node.id()), f(node)))
+            .collect()
+    };
+
+    family(
+        "dist_broadcasts_total",
+        "counter",
+        "Values clients broadcast to the node",
+        per_node(&|node| node.stats().broadcasts),
+    );
+    family(
+        "dist_gossip_sent_total",
+        "counter",
+        "Gossip batches sent by fanout",
+        per_node(&|node| node.stats().gossip_sent),
+    );
+    family(
+        "dist_retries_sent_total",
+        "counter",
+        "Gossip batches resent from the retry outbox",
+        per_node(&|node| node.stats().retries_sent),
+    );
+    family(
+        "dist_syncs_sent_total",
+        "counter",
+        "Sync requests sent",
+        per_node(&|node| node.stats().syncs_sent),
+    );
+    family(
+        "dist_store_values",
+        "gauge",
+        "Values held in the store",
+        per_node(&|node| node.store_len() as u64),
+    );
+
+    let mut acks = Vec::new();
+    let mut depths = Vec::new();
+    for node in &nodes {
+        let sorted: BTreeMap<_, _> = node.acks_received().iter().collect();
+        for (kind, count) in sorted {
+            acks.push((format!("node=\"{}\",type=\"{kind}\"", node.id()), *count));
+        }
+        let sorted: BTreeMap<_, _> = node.outbox_depths().into_iter().collect();
+        for (peer, depth) in sorted {
+            depths.push((
+                format!("node=\"{}\",peer=\"{peer}\"", node.id()),
+                depth as u64,
+            ));
+        }
+    }
+    family(
+        "dist_acks_received_total",
+        "counter",
+        "Replies received, by type",
+        acks,
+    );
+    family(
+        "dist_outbox_depth",
+        "gauge",
+        "Values a peer hasn't acked yet",
+        depths,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP dist_slow_handlers_total Handler runs over the slow handler threshold"
+    );
+    let _ = writeln!(out, "# TYPE dist_slow_handlers_total counter");
+    let _ = writeln!(out, "dist_slow_handlers_total {}", host.slow_handlers());
+    out
+}
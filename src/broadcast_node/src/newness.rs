@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::NodeId;
+
+// How many values each peer recently delivered that were new to us. A peer
+// that keeps handing us new values is likely ahead of us, which makes it a
+// good sync target for SyncPeerWeights::Newness. Scores halve every
+// `half_life`, so a peer that stops being ahead fades back to the rest.
+#[derive(Debug, Clone)]
+pub struct Newness {
+    pub half_life: Duration,
+    // score as of the instant it was last updated
+    scores: HashMap<NodeId, (f64, Instant)>,
+}
+
+impl Newness {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, peer: &NodeId, new_values: usize, now: Instant) {
+        let score = self.score(peer, now) + new_values as f64;
+        self.scores.insert(peer.clone(), (score, now));
+    }
+
+    pub fn score(&self, peer: &NodeId, now: Instant) -> f64 {
+        let Some(&(score, at)) = self.scores.get(peer) else {
+            return 0.0;
+        };
+        if self.half_life.is_zero() {
+            return 0.0;
+        }
+        let half_lives =
+            now.saturating_duration_since(at).as_secs_f64() / self.half_life.as_secs_f64();
+        score * 0.5f64.powf(half_lives)
+    }
+
+    pub fn forget(&mut self, peer: &NodeId) {
+        self.scores.remove(peer);
+    }
+
+    pub fn restart(&mut self) {
+        self.scores.clear();
+    }
+}
@@ -0,0 +1,475 @@
+use crate::codec::stream_read_ok;
+use crate::{
+    broadcast, read_wait, repair, BroadcastNodeTrait, ErrorCode, Message, MessageBody, Node,
+    NodeId, RpcError, ValueSet,
+};
+use anyhow::Result;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::mpsc::Sender;
+
+// State of RPCs this node is waiting on, see Node::pending
+#[derive(Debug, Clone)]
+pub enum PendingRpc {
+    // A client read that needs `needed` peers to answer before we reply.
+    // All peers are asked with the same msg_id and told apart by src.
+    QuorumRead {
+        client: NodeId,
+        client_msg_id: u32,
+        needed: usize,
+        responders: HashSet<NodeId>,
+        merged: HashSet<u32>,
+    },
+    // A read-repair round started after a client was already answered from
+    // the local store. Nobody waits on it, it just expires.
+    RepairRead {
+        responders: HashSet<NodeId>,
+    },
+    // A client's read_wait, answered once the store holds more than `since`
+    // values. Never matches a peer reply, see read_wait.rs.
+    ReadWait {
+        client: NodeId,
+        client_msg_id: u32,
+        since: usize,
+    },
+    // A client read asking for a peer's store. The peer's read_ok is relayed
+    // as is, nothing in it is merged into ours.
+    ForwardedRead {
+        client: NodeId,
+        client_msg_id: u32,
+        from: NodeId,
+    },
+    // A repair_request for gossip from `peer` that never arrived, see
+    // repair.rs. Unanswered, it falls back to a full sync with the peer.
+    Repair {
+        peer: NodeId,
+    },
+}
+
+// Majority of the cluster, counting ourselves
+pub(crate) fn majority(cluster_size: usize) -> usize {
+    cluster_size / 2 + 1
+}
+
+// Fans a plain read out to every peer and parks the client request until a
+// majority (us included) has answered or the RPC times out.
+pub fn start_quorum_read<Data>(
+    node: &mut Node<Data>,
+    client: NodeId,
+    client_msg_id: u32,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let peers = peers(node);
+    let needed = majority(peers.len() + 1) - 1;
+    let merged: HashSet<u32> = node.read().into_iter().collect();
+
+    if needed == 0 {
+        return reply_to_client(node, client, client_msg_id, merged, tx);
+    }
+
+    let rpc = PendingRpc::QuorumRead {
+        client,
+        client_msg_id,
+        needed,
+        responders: HashSet::new(),
+        merged,
+    };
+    read_peers(node, peers, rpc, tx)
+}
+
+// Reads every peer in the background. Their replies are merged into our
+// store and each is pushed the values it was missing.
+pub fn start_repair_read<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let peers = peers(node);
+    if peers.is_empty() {
+        return Ok(());
+    }
+    let rpc = PendingRpc::RepairRead {
+        responders: HashSet::new(),
+    };
+    read_peers(node, peers, rpc, tx)
+}
+
+// Asks `from` for its store on a client's behalf. Only members can be asked,
+// anything else would sit in pending until it timed out.
+pub fn start_forwarded_read<Data>(
+    node: &mut Node<Data>,
+    client: NodeId,
+    client_msg_id: u32,
+    from: NodeId,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if !node.node_ids.contains(&from) {
+        return Err(
+            RpcError::new(ErrorCode::NodeNotFound, format!("{from} is not a member")).into(),
+        );
+    }
+    let rpc_id = node.get_and_increment_msg_id();
+    Message {
+        src: node.id.clone(),
+        dest: from.clone(),
+        body: MessageBody::read {
+            msg_id: rpc_id,
+            quorum: false,
+            offset,
+            limit,
+            from: None,
+        },
+    }
+    .send(tx)?;
+    let rpc = PendingRpc::ForwardedRead {
+        client,
+        client_msg_id,
+        from,
+    };
+    let deadline = node.clock.now() + node.config.rpc_timeout;
+    node.pending.insert(rpc_id, rpc, deadline);
+    Ok(())
+}
+
+fn peers<Data>(node: &Node<Data>) -> Vec<NodeId> {
+    node.node_ids
+        .iter()
+        .filter(|id| **id != node.id)
+        .cloned()
+        .collect()
+}
+
+// Sends one plain read to each peer under a shared msg_id and parks `rpc`
+fn read_peers<Data>(
+    node: &mut Node<Data>,
+    peers: Vec<NodeId>,
+    rpc: PendingRpc,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let rpc_id = node.get_and_increment_msg_id();
+    for peer in peers {
+        Message {
+            src: node.id.clone(),
+            dest: peer,
+            body: MessageBody::read {
+                msg_id: rpc_id,
+                quorum: false,
+                offset: None,
+                limit: None,
+                from: None,
+            },
+        }
+        .send(tx.clone())?;
+    }
+    let deadline = node.clock.now() + node.config.rpc_timeout;
+    node.pending.insert(rpc_id, rpc, deadline);
+    Ok(())
+}
+
+pub fn handle_read_ok_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if !broadcast::is_authentic(node, &msg) {
+        anyhow::bail!("dropping read_ok from {} with a bad signature", msg.src);
+    }
+    if let MessageBody::read_ok {
+        messages,
+        in_reply_to,
+        next_offset,
+        ..
+    } = msg.body
+    {
+        if let Some(PendingRpc::ForwardedRead { from, .. }) = node.pending.get_mut(in_reply_to) {
+            if *from == msg.src {
+                return relay_read(node, in_reply_to, messages, next_offset, tx);
+            }
+            return Ok(());
+        }
+        merge_read_ok(
+            node,
+            msg.src,
+            in_reply_to,
+            messages.into_iter().collect(),
+            tx,
+        )?;
+    }
+    Ok(())
+}
+
+// Whether a read_ok answering `in_reply_to` can go through
+// handle_read_ok_line. Forwarded reads are relayed in order and page by page,
+// so they take the normal path.
+pub(crate) fn streams_read_ok<Data>(node: &mut Node<Data>, in_reply_to: Option<u32>) -> bool {
+    matches!(
+        in_reply_to.and_then(|id| node.pending.get_mut(id)),
+        Some(PendingRpc::QuorumRead { .. } | PendingRpc::RepairRead { .. })
+    )
+}
+
+// handle_read_ok_message for a raw line, collecting the values straight into
+// the set they are merged from. Host::try_stream only hands it replies that
+// streams_read_ok accepts. Returns how many values the reply held.
+pub fn handle_read_ok_line<Data>(
+    node: &mut Node<Data>,
+    line: &str,
+    tx: Sender<Message>,
+) -> Result<usize>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let mut theirs = HashSet::new();
+    let streamed = stream_read_ok(line, |m| {
+        theirs.insert(m);
+    })?;
+    merge_read_ok(node, streamed.src, streamed.in_reply_to, theirs, tx)?;
+    Ok(streamed.values)
+}
+
+fn merge_read_ok<Data>(
+    node: &mut Node<Data>,
+    src: NodeId,
+    in_reply_to: u32,
+    theirs: HashSet<u32>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let (needed, responders, merged) = match node.pending.get_mut(in_reply_to) {
+        Some(PendingRpc::QuorumRead {
+            needed,
+            responders,
+            merged,
+            ..
+        }) => (needed, responders, merged),
+        Some(PendingRpc::RepairRead { responders }) => {
+            if responders.insert(src.clone()) {
+                return repair_from_reply(node, src, theirs, tx);
+            }
+            return Ok(());
+        }
+        // forwarded reads are relayed by handle_read_ok_message, anything
+        // else is a late reply for a read that already completed or timed out
+        Some(
+            PendingRpc::ForwardedRead { .. }
+            | PendingRpc::ReadWait { .. }
+            | PendingRpc::Repair { .. },
+        )
+        | None => return Ok(()),
+    };
+    if !responders.insert(src.clone()) {
+        return Ok(());
+    }
+    let missing: Vec<u32> = merged.difference(&theirs).copied().collect();
+    merged.extend(theirs);
+    let done = responders.len() >= *needed;
+
+    if node.config.read_repair && !missing.is_empty() {
+        repair_peer(node, src.clone(), missing, tx.clone())?;
+    }
+    if !done {
+        return Ok(());
+    }
+
+    if let Some(PendingRpc::QuorumRead {
+        client,
+        client_msg_id,
+        merged,
+        ..
+    }) = node.pending.remove(in_reply_to)
+    {
+        // keep what we learned so a later local read can't go backwards.
+        // The merge doesn't say which responder had a value, the reply that
+        // completed the quorum is named as its source
+        for value in &merged {
+            node.insert_if_absent(Data::from(*value), &src);
+        }
+        reply_to_client(node, client, client_msg_id, merged, tx)?;
+    }
+    Ok(())
+}
+
+// Hands a forwarded read's answer to the client that asked for it
+fn relay_read<Data>(
+    node: &mut Node<Data>,
+    rpc_id: u32,
+    messages: Vec<u32>,
+    next_offset: Option<usize>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let Some(PendingRpc::ForwardedRead {
+        client,
+        client_msg_id,
+        ..
+    }) = node.pending.remove(rpc_id)
+    else {
+        return Ok(());
+    };
+    Message {
+        src: node.id.clone(),
+        dest: client,
+        body: MessageBody::read_ok {
+            messages,
+            in_reply_to: client_msg_id,
+            msg_id: node.get_and_increment_msg_id(),
+            next_offset,
+            signature: None,
+        },
+    }
+    .send(tx)
+}
+
+fn repair_from_reply<Data>(
+    node: &mut Node<Data>,
+    peer: NodeId,
+    messages: HashSet<u32>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let theirs: ValueSet<Data> = messages.into_iter().map(Data::from).collect();
+    let (missing, new_to_us) = broadcast::split_difference(node.store.as_set(), &theirs);
+    for value in new_to_us {
+        node.insert_if_absent(value, &peer);
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let missing = missing.into_iter().map(Data::into).collect();
+    repair_peer(node, peer, missing, tx)
+}
+
+// Read-repair: push a peer the values its read_ok showed it was missing.
+// Sent as untracked gossip, so the peer stores and forwards them like any
+// other batch. If it's lost the regular sync rounds catch the peer up.
+fn repair_peer<Data>(
+    node: &mut Node<Data>,
+    peer: NodeId,
+    missing: Vec<u32>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let msg_id = node.get_and_increment_msg_id();
+    let body = broadcast::gossip_body(node, &peer, msg_id, missing, true);
+    Message {
+        src: node.id.clone(),
+        dest: peer,
+        body,
+    }
+    .send(tx)
+}
+
+// Drops every RPC whose deadline passed. A client still waiting on one gets a
+// timeout error (or an empty read_wait_ok), and since this runs on every tick
+// peers that never reply leak nothing.
+pub fn expire_pending<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let now = node.clock.now();
+    for (_, rpc) in node.pending.take_expired(now) {
+        match rpc {
+            PendingRpc::QuorumRead {
+                client,
+                client_msg_id,
+                needed,
+                responders,
+                ..
+            } => {
+                let error = RpcError::timeout(format!(
+                    "quorum read got {} of {} peer replies",
+                    responders.len(),
+                    needed
+                ));
+                Message {
+                    src: node.id.clone(),
+                    dest: client,
+                    body: MessageBody::rpc_error(client_msg_id, &error),
+                }
+                .send(tx.clone())?
+            }
+            PendingRpc::RepairRead { .. } => {}
+            PendingRpc::ReadWait {
+                client,
+                client_msg_id,
+                since,
+            } => read_wait::reply(node, client, client_msg_id, since, tx.clone())?,
+            PendingRpc::ForwardedRead {
+                client,
+                client_msg_id,
+                from,
+            } => {
+                let error = RpcError::timeout(format!("{from} didn't answer the read"));
+                Message {
+                    src: node.id.clone(),
+                    dest: client,
+                    body: MessageBody::rpc_error(client_msg_id, &error),
+                }
+                .send(tx.clone())?
+            }
+            PendingRpc::Repair { peer } => {
+                eprintln!("{} never answered a repair request, syncing with it", peer);
+                let msg_id = node.get_and_increment_msg_id();
+                repair::full_sync(node, &peer, msg_id, tx.clone())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn reply_to_client<Data>(
+    node: &mut Node<Data>,
+    client: NodeId,
+    client_msg_id: u32,
+    merged: HashSet<u32>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    Message {
+        src: node.id.clone(),
+        dest: client,
+        body: MessageBody::read_ok {
+            messages: merged.into_iter().collect(),
+            in_reply_to: client_msg_id,
+            msg_id: node.get_and_increment_msg_id(),
+            next_offset: None,
+            signature: None,
+        },
+    }
+    .send(tx)
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::NodeId;
+
+// Token bucket settings shared by every destination.
+// `rate` is tokens refilled per second, `burst` is the bucket capacity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst as f64);
+        self.last_refill = now;
+    }
+}
+
+// One bucket per destination, created lazily on first send.
+// Without a limit every send is allowed, which is the default.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    limit: Option<RateLimit>,
+    buckets: HashMap<NodeId, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit: Some(limit),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    // Every bucket starts full again
+    pub fn restart(&mut self) {
+        self.buckets.clear();
+    }
+
+    // Takes a token for `peer` if one is available. Callers are expected to
+    // put the message back into the outbox when this returns false.
+    pub fn try_acquire(&mut self, peer: &NodeId, now: Instant) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let bucket = self
+            .buckets
+            .entry(peer.clone())
+            .or_insert_with(|| TokenBucket::full(limit, now));
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{BroadcastNodeTrait, Message, MessageBody, Node, NodeId, PendingRpc};
+use anyhow::Result;
+use std::hash::Hash;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+// Longest a read_wait is parked for, whatever timeout the client asked for
+pub(crate) const MAX_WAIT: Duration = Duration::from_secs(60);
+
+// Long-polling read. If the store already holds more than `since` values the
+// client gets the rest right away, otherwise the request is parked in
+// Node::pending under a fresh id until a later insert wakes it or the timeout
+// sweep answers it with nothing. Offsets are insertion order, as in a paged
+// read.
+pub fn handle_read_wait_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::read_wait {
+        msg_id,
+        since,
+        timeout,
+    } = msg.body
+    {
+        if node.store.len() > since || timeout == 0 {
+            return reply(node, msg.src, msg_id, since, tx);
+        }
+        // clamped, a client asking for u64::MAX ms would overflow Instant
+        let wait = Duration::from_millis(timeout).min(MAX_WAIT);
+        let Some(deadline) = node.clock.now().checked_add(wait) else {
+            return reply(node, msg.src, msg_id, since, tx);
+        };
+        let wait_id = node.get_and_increment_msg_id();
+        let rpc = PendingRpc::ReadWait {
+            client: msg.src,
+            client_msg_id: msg_id,
+            since,
+        };
+        node.pending.insert(wait_id, rpc, deadline);
+    }
+    Ok(())
+}
+
+// Answers every parked read_wait the store has grown past. Called after
+// every message and tick, but only scans the waiters when the store grew
+// since the last call, a waiter is parked only while the store is no bigger
+// than it was then.
+pub fn wake_read_waiters<Data>(node: &mut Node<Data>, tx: Sender<Message>) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let len = node.store.len();
+    let grew = len > node.waiters_checked_at;
+    node.waiters_checked_at = len;
+    if !grew || node.pending.is_empty() {
+        return Ok(());
+    }
+    let ready = node
+        .pending
+        .take_where(|rpc| matches!(rpc, PendingRpc::ReadWait { since, .. } if len > *since));
+    for (_, rpc) in ready {
+        if let PendingRpc::ReadWait {
+            client,
+            client_msg_id,
+            since,
+        } = rpc
+        {
+            reply(node, client, client_msg_id, since, tx.clone())?;
+        }
+    }
+    Ok(())
+}
+
+// Everything stored after the first `since` values, nothing if the store
+// hasn't grown past it
+pub(crate) fn reply<Data>(
+    node: &mut Node<Data>,
+    client: NodeId,
+    client_msg_id: u32,
+    since: usize,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let (messages, _) = node.read_page(since, usize::MAX);
+    Message {
+        src: node.id.clone(),
+        dest: client,
+        body: MessageBody::read_wait_ok {
+            msg_id: node.get_and_increment_msg_id(),
+            in_reply_to: client_msg_id,
+            messages,
+            next: node.store.len(),
+        },
+    }
+    .send(tx)
+}
@@ -0,0 +1,153 @@
+use crate::broadcast::{forward_new_values, is_authentic, signed, sync_payload, sync_request};
+use crate::sequence::SENT_LOG;
+use crate::{BroadcastNodeTrait, Message, MessageBody, Node, NodeId, PendingRpc};
+use anyhow::Result;
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::mpsc::Sender;
+
+// Targeted recovery of dropped gossip. A gap in a peer's gossip numbers names
+// batches we never saw, so we ask that peer for exactly those by number and
+// it answers with the values it logged for them. Batches it no longer has
+// (too old, or sent before it restarted) come back as `unknown` and we fall
+// back to a full sync, as we do right away for a gap wider than the log. The
+// request is tracked in Node::pending, so one that goes unanswered falls back
+// to a full sync too and a reply we never asked for is dropped.
+pub(crate) fn request_repair<Data>(
+    node: &mut Node<Data>,
+    peer: &NodeId,
+    missing: Range<u64>,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let msg_id = node.get_and_increment_msg_id();
+    if missing.end - missing.start > SENT_LOG as u64 {
+        eprintln!(
+            "Gossip {:?} from {} never arrived, too many to repair, syncing with it",
+            missing, peer
+        );
+        return full_sync(node, peer, msg_id, tx);
+    }
+    eprintln!(
+        "Gossip {:?} from {} never arrived, asking for a repair",
+        missing, peer
+    );
+    let deadline = node.clock.now() + node.config.rpc_timeout;
+    let rpc = PendingRpc::Repair { peer: peer.clone() };
+    node.pending.insert(msg_id, rpc, deadline);
+    Message {
+        src: node.id.clone(),
+        dest: peer.clone(),
+        body: MessageBody::repair_request {
+            msg_id,
+            missing: missing.collect(),
+        },
+    }
+    .send(tx)
+}
+
+pub fn handle_repair_request_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if let MessageBody::repair_request { msg_id, missing } = msg.body {
+        let mut values = Vec::new();
+        let mut unknown = Vec::new();
+        for seq in missing {
+            match node.sequences.sent_batch(&msg.src, seq) {
+                Some(batch) => values.extend_from_slice(batch),
+                None => unknown.push(seq),
+            }
+        }
+        values.sort_unstable();
+        values.dedup();
+        let body = MessageBody::repair_reply {
+            in_reply_to: msg_id,
+            values,
+            unknown,
+            signature: None,
+        };
+        Message {
+            src: node.id.clone(),
+            body: signed(node, &msg.src, body),
+            dest: msg.src,
+        }
+        .send(tx)?;
+    }
+    Ok(())
+}
+
+pub fn handle_repair_reply_message<Data>(
+    node: &mut Node<Data>,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    if !is_authentic(node, &msg) {
+        anyhow::bail!(
+            "dropping repair_reply from {} with a bad signature",
+            msg.src
+        );
+    }
+    if let MessageBody::repair_reply {
+        in_reply_to,
+        values,
+        unknown,
+        ..
+    } = msg.body
+    {
+        match node.pending.get_mut(in_reply_to) {
+            Some(PendingRpc::Repair { peer }) if *peer == msg.src => {
+                node.pending.remove(in_reply_to);
+            }
+            _ => anyhow::bail!(
+                "dropping repair_reply {} from {}, it answers no repair request of ours",
+                in_reply_to,
+                msg.src
+            ),
+        }
+        let mut newly_seen = Vec::new();
+        for value in values {
+            if node.insert_if_absent(Data::from(value), &msg.src).is_some() {
+                newly_seen.push(value);
+            }
+        }
+        forward_new_values(node, &msg.src, &newly_seen)?;
+        if !unknown.is_empty() {
+            eprintln!(
+                "{} can't repair gossip {:?}, syncing with it",
+                msg.src, unknown
+            );
+            let msg_id = node.get_and_increment_msg_id();
+            full_sync(node, &msg.src, msg_id, tx)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn full_sync<Data>(
+    node: &mut Node<Data>,
+    peer: &NodeId,
+    msg_id: u32,
+    tx: Sender<Message>,
+) -> Result<()>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Hash + Eq,
+    Node<Data>: BroadcastNodeTrait,
+{
+    let seq = node.sequences.next_sync_for(peer);
+    node.stats.syncs_sent = node.stats.syncs_sent.saturating_add(1);
+    let payload = sync_payload(node);
+    sync_request(node, peer, msg_id, seq, payload).send(tx)
+}
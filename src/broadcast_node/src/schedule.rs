@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Fanout,
+    Retry,
+    Sync,
+    Heartbeat,
+}
+
+// Periodic work driven from `tick`. Only tracks when each task last ran, the
+// current time always comes from the node's clock.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub fanout_interval: Duration,
+    pub retry_interval: Duration,
+    // None never runs Task::Sync
+    pub sync_interval: Option<Duration>,
+    // None never runs Task::Heartbeat
+    pub heartbeat_interval: Option<Duration>,
+    last_fanout: Instant,
+    last_retry: Instant,
+    last_sync: Instant,
+    last_heartbeat: Instant,
+}
+
+impl Schedule {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            fanout_interval: Duration::from_millis(50),
+            retry_interval: Duration::from_millis(300),
+            sync_interval: None,
+            heartbeat_interval: None,
+            last_fanout: now,
+            last_retry: now,
+            last_sync: now,
+            last_heartbeat: now,
+        }
+    }
+
+    // Returns true and restarts the interval if `task` is due at `now`. A task
+    // without an interval is never due.
+    pub fn take_due(&mut self, task: Task, now: Instant) -> bool {
+        let (last, interval) = match task {
+            Task::Fanout => (&mut self.last_fanout, Some(self.fanout_interval)),
+            Task::Retry => (&mut self.last_retry, Some(self.retry_interval)),
+            Task::Sync => (&mut self.last_sync, self.sync_interval),
+            Task::Heartbeat => (&mut self.last_heartbeat, self.heartbeat_interval),
+        };
+        let Some(interval) = interval else {
+            return false;
+        };
+        if now.saturating_duration_since(*last) >= interval {
+            *last = now;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn restart(&mut self, now: Instant) {
+        self.last_fanout = now;
+        self.last_retry = now;
+        self.last_sync = now;
+        self.last_heartbeat = now;
+    }
+}
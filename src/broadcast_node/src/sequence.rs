@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::NodeId;
+
+// Per-link gossip sequence numbers. Each gossip to a peer takes the next
+// number for that peer, and the receiver remembers the highest number seen
+// from each source, so a jump means batches went missing in between.
+// Sync rounds are numbered the same way but separately, the peer echoes the
+// number on its sync_ok and only replies newer than the last one applied are
+// merged, so a duplicated reply has no effect even for non-idempotent merges.
+// The last SENT_LOG numbered batches to each peer are kept, so a peer that
+// spots a gap can ask for exactly those, see repair.rs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sequences {
+    next_out: HashMap<NodeId, u64>,
+    last_in: HashMap<NodeId, u64>,
+    next_sync: HashMap<NodeId, u64>,
+    last_sync_ok: HashMap<NodeId, u64>,
+    // only useful to the incarnation that sent them, left out of snapshots
+    #[serde(skip)]
+    sent: HashMap<NodeId, BTreeMap<u64, Vec<u32>>>,
+}
+
+pub const SENT_LOG: usize = 64;
+
+impl Sequences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Numbers start at 1 for every peer
+    pub fn next_for(&mut self, peer: &NodeId) -> u64 {
+        let next = self.next_out.entry(peer.clone()).or_insert(0);
+        *next += 1;
+        *next
+    }
+
+    // Records `seq` from `peer` and returns the numbers skipped since the last
+    // one, if any. Late or repeated numbers are not a gap.
+    pub fn observe(&mut self, peer: &NodeId, seq: u64) -> Option<Range<u64>> {
+        let last = self.last_in.entry(peer.clone()).or_insert(0);
+        if seq <= *last {
+            return None;
+        }
+        let missing = (*last + 1)..seq;
+        *last = seq;
+        (!missing.is_empty()).then_some(missing)
+    }
+
+    // Keeps the values of batch `seq` to `peer`, dropping the oldest past
+    // SENT_LOG
+    pub fn record_sent(&mut self, peer: &NodeId, seq: u64, values: &[u32]) {
+        let log = self.sent.entry(peer.clone()).or_default();
+        log.insert(seq, values.to_vec());
+        while log.len() > SENT_LOG {
+            log.pop_first();
+        }
+    }
+
+    pub fn sent_batch(&self, peer: &NodeId, seq: u64) -> Option<&[u32]> {
+        self.sent.get(peer)?.get(&seq).map(Vec::as_slice)
+    }
+
+    // Numbers start at 1 for every peer, independent of the gossip ones
+    pub fn next_sync_for(&mut self, peer: &NodeId) -> u64 {
+        let next = self.next_sync.entry(peer.clone()).or_insert(0);
+        *next += 1;
+        *next
+    }
+
+    // True if a sync_ok numbered `seq` from `peer` should be merged, and
+    // records it. Replies at or below the last merged number are stale.
+    pub fn accept_sync_ok(&mut self, peer: &NodeId, seq: u64) -> bool {
+        let last = self.last_sync_ok.entry(peer.clone()).or_insert(0);
+        if seq <= *last {
+            return false;
+        }
+        *last = seq;
+        true
+    }
+
+    pub fn forget(&mut self, peer: &NodeId) {
+        self.next_out.remove(peer);
+        self.last_in.remove(peer);
+        self.next_sync.remove(peer);
+        self.last_sync_ok.remove(peer);
+        self.sent.remove(peer);
+    }
+
+    pub fn clear(&mut self) {
+        self.next_out.clear();
+        self.last_in.clear();
+        self.next_sync.clear();
+        self.last_sync_ok.clear();
+        self.sent.clear();
+    }
+}
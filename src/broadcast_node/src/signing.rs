@@ -0,0 +1,111 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::Write;
+
+use crate::{MessageBody, NodeId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// HMAC-SHA256 with the cluster wide key over every body that carries values
+// into a store: gossip, sync, sync_ok, read_ok and repair_reply. Covers src,
+// dest and the body's type as well as its id and values, so a captured body
+// can't be replayed under another node's name or as another kind of message.
+pub fn sign(key: &[u8], src: &NodeId, dest: &NodeId, body: &mut MessageBody) {
+    let Some(tag) = mac(key, src, dest, body).map(|mac| mac.finalize().into_bytes()) else {
+        return;
+    };
+    let hex = tag
+        .iter()
+        .fold(String::with_capacity(tag.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+    if let Some(signature) = signature_mut(body) {
+        *signature = Some(hex);
+    }
+}
+
+// Bodies that carry no values always pass, the rest need a valid signature
+pub fn verify(key: &[u8], src: &NodeId, dest: &NodeId, body: &MessageBody) -> bool {
+    let Some(mac) = mac(key, src, dest, body) else {
+        return true;
+    };
+    let signature = match body {
+        MessageBody::gossip { signature, .. }
+        | MessageBody::sync { signature, .. }
+        | MessageBody::sync_ok { signature, .. }
+        | MessageBody::read_ok { signature, .. }
+        | MessageBody::repair_reply { signature, .. } => signature.as_deref(),
+        _ => None,
+    };
+    signature
+        .and_then(decode_hex)
+        .is_some_and(|tag| mac.verify_slice(&tag).is_ok())
+}
+
+fn signature_mut(body: &mut MessageBody) -> Option<&mut Option<String>> {
+    match body {
+        MessageBody::gossip { signature, .. }
+        | MessageBody::sync { signature, .. }
+        | MessageBody::sync_ok { signature, .. }
+        | MessageBody::read_ok { signature, .. }
+        | MessageBody::repair_reply { signature, .. } => Some(signature),
+        _ => None,
+    }
+}
+
+// None for a body that isn't signed
+fn mac(key: &[u8], src: &NodeId, dest: &NodeId, body: &MessageBody) -> Option<HmacSha256> {
+    let (kind, id, values, bitmap) = match body {
+        MessageBody::gossip {
+            msg_id, messages, ..
+        } => ("gossip", *msg_id, messages, None),
+        MessageBody::read_ok {
+            msg_id, messages, ..
+        } => ("read_ok", *msg_id, messages, None),
+        MessageBody::sync {
+            msg_id,
+            messages,
+            bitmap,
+            ..
+        } => ("sync", *msg_id, messages, bitmap.as_deref()),
+        MessageBody::sync_ok {
+            msg_id,
+            messages,
+            bitmap,
+            ..
+        } => ("sync_ok", *msg_id, messages, bitmap.as_deref()),
+        MessageBody::repair_reply {
+            in_reply_to,
+            values,
+            ..
+        } => ("repair_reply", *in_reply_to, values, None),
+        _ => return None,
+    };
+    // HMAC accepts keys of any length, so this never bails
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    for part in [
+        src.as_str().as_bytes(),
+        dest.as_str().as_bytes(),
+        kind.as_bytes(),
+        bitmap.unwrap_or_default().as_bytes(),
+    ] {
+        mac.update(&(part.len() as u32).to_be_bytes());
+        mac.update(part);
+    }
+    mac.update(&id.to_be_bytes());
+    for value in values {
+        mac.update(&value.to_be_bytes());
+    }
+    Some(mac)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
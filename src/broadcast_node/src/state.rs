@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use crate::{BroadcastNodeTrait, Node, NodeId, Sequences, Tombstones};
+
+// Everything about a node that outlives a single tick, so a test can save a
+// checkpoint and rerun from it. Runtime state tied to the clock or to
+// messages in flight is left out and starts fresh on restore: the schedule,
+// liveness, pending RPCs (parked read_waits included), in-flight gossip, rate
+// limiter buckets, newness scores and the startup grace. Unacked values are
+// still in the restored outboxes, so the first retry pass resends them. The
+// config, clock, RNG and custom handlers the node was built with are kept, as
+// are its stats and ack counters, which count what this process did rather
+// than what the checkpoint holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeState {
+    pub id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    // insertion order, so recent syncs and paged reads match after restore
+    pub store: Vec<u32>,
+    pub topology: HashMap<NodeId, Vec<NodeId>>,
+    pub retry_outbox: HashMap<NodeId, HashSet<u32>>,
+    pub retry_cursor: usize,
+    pub msg_outbox: HashMap<NodeId, HashSet<u32>>,
+    pub tombstones: Tombstones,
+    pub sequences: Sequences,
+    pub provenance: HashMap<u32, NodeId>,
+}
+
+impl<Data> Node<Data>
+where
+    Data: PartialEq + Clone + Copy + From<u32> + Into<u32> + Eq + Hash,
+    Self: BroadcastNodeTrait,
+{
+    pub fn state(&self) -> NodeState {
+        NodeState {
+            id: self.id.clone(),
+            node_ids: self.node_ids.clone(),
+            store: self.read(),
+            topology: self.topology.clone(),
+            retry_outbox: self.retry_outbox.clone(),
+            retry_cursor: self.retry_cursor,
+            msg_outbox: self.msg_outbox.clone(),
+            tombstones: self.tombstones.clone(),
+            sequences: self.sequences.clone(),
+            provenance: self.provenance.clone(),
+        }
+    }
+
+    pub fn serialize_state(&self) -> Result<String> {
+        serde_json::to_string(&self.state()).context("serializing node state")
+    }
+
+    // Replaces the saved fields and drops the runtime ones, see NodeState
+    pub fn restore_state(&mut self, json: &str) -> Result<()> {
+        let state: NodeState = serde_json::from_str(json).context("deserializing node state")?;
+        self.id = state.id;
+        self.node_ids = state.node_ids;
+        self.store = state.store.into_iter().map(Data::from).collect();
+        self.topology = state.topology;
+        self.retry_outbox = state.retry_outbox;
+        self.retry_cursor = state.retry_cursor;
+        self.msg_outbox = state.msg_outbox;
+        self.tombstones = state.tombstones;
+        self.sequences = state.sequences;
+        self.provenance = state.provenance;
+        self.in_flight_gossip.clear();
+        self.pending.clear();
+        self.waiters_checked_at = 0;
+        let now = self.clock.now();
+        self.schedule.restart(now);
+        self.liveness.restart();
+        self.rate_limiter.restart();
+        self.newness.restart();
+        // a checkpoint is taken from a running node, there's no startup to
+        // wait out
+        self.initialized_at = None;
+        Ok(())
+    }
+}
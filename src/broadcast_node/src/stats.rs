@@ -0,0 +1,15 @@
+// Running totals of what a node has done since it started, never reset. Read
+// through Node::stats, e.g. by the metrics endpoint. They saturate rather than
+// overflow, a counter must never be what panics a handler.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    // values clients broadcast to us, bulk ones counted per value
+    pub broadcasts: u64,
+    // gossip batches sent by fanout
+    pub gossip_sent: u64,
+    // gossip batches resent from the retry outbox
+    pub retries_sent: u64,
+    // sync requests sent: periodic ones, the follow-up to a capped reply and
+    // the fallback for gossip a repair couldn't recover
+    pub syncs_sent: u64,
+}
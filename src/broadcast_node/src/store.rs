@@ -0,0 +1,183 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::hash::Hash;
+
+// What a node needs from the set holding its values. ValueStore is the one
+// Node uses, the plain sets (and bitmap.rs's Bitmap) are here so
+// benches/store.rs can compare them on the same workload.
+pub trait Store<Data> {
+    // Returns false if the value was already present
+    fn insert(&mut self, value: Data) -> bool;
+    fn contains(&self, value: &Data) -> bool;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // Every value, in whatever order the store keeps them, as a read returns
+    fn read_all(&self) -> Vec<Data>;
+}
+
+// The set ValueStore answers membership from, and what a sync's values are
+// collected into to diff against it. A hash set, or with the `bitmap-sync`
+// feature a bitmap.rs Bitmap, whose diffs run on whole containers.
+#[cfg(not(feature = "bitmap-sync"))]
+pub type ValueSet<Data> = HashSet<Data>;
+#[cfg(feature = "bitmap-sync")]
+pub type ValueSet<Data> = crate::bitmap::Bitmap<Data>;
+
+// The node's values. The set answers membership, the Vec keeps the order
+// values were first inserted for recent-only syncs and anything else that
+// reads "what's new". Equality ignores order, two stores holding the same
+// values are equal however they got there.
+#[derive(Clone)]
+pub struct ValueStore<Data> {
+    set: ValueSet<Data>,
+    order: Vec<Data>,
+}
+
+impl<Data> ValueStore<Data> {
+    pub fn new() -> Self {
+        Self {
+            set: ValueSet::default(),
+            order: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Data> {
+        self.order.iter()
+    }
+
+    // The last `n` values inserted, oldest first
+    pub fn recent(&self, n: usize) -> &[Data] {
+        &self.order[self.order.len().saturating_sub(n)..]
+    }
+
+    pub fn as_set(&self) -> &ValueSet<Data> {
+        &self.set
+    }
+}
+
+impl<Data> ValueStore<Data>
+where
+    Data: Copy + Hash + Eq + From<u32> + Into<u32>,
+{
+    // Returns false if the value was already present
+    pub fn insert(&mut self, value: Data) -> bool {
+        let inserted = self.set.insert(value);
+        if inserted {
+            self.order.push(value);
+        }
+        inserted
+    }
+
+    // O(n) for the order log, removals only come from tombstones
+    pub fn remove(&mut self, value: &Data) -> bool {
+        let removed = self.set.remove(value);
+        if removed {
+            self.order.retain(|v| v != value);
+        }
+        removed
+    }
+
+    pub fn contains(&self, value: &Data) -> bool {
+        self.set.contains(value)
+    }
+}
+
+impl<Data> Default for ValueStore<Data> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Data: Copy + Hash + Eq + From<u32> + Into<u32>> Extend<Data> for ValueStore<Data> {
+    fn extend<I: IntoIterator<Item = Data>>(&mut self, values: I) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+}
+
+impl<Data: Copy + Hash + Eq + From<u32> + Into<u32>> FromIterator<Data> for ValueStore<Data> {
+    fn from_iter<I: IntoIterator<Item = Data>>(values: I) -> Self {
+        let mut store = Self::new();
+        store.extend(values);
+        store
+    }
+}
+
+impl<Data: Hash + Eq> PartialEq for ValueStore<Data> {
+    fn eq(&self, other: &Self) -> bool {
+        self.set == other.set
+    }
+}
+
+impl<Data: fmt::Debug> fmt::Debug for ValueStore<Data> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.order.iter()).finish()
+    }
+}
+
+impl<Data: Copy + Hash + Eq + From<u32> + Into<u32>> Store<Data> for ValueStore<Data> {
+    fn insert(&mut self, value: Data) -> bool {
+        ValueStore::insert(self, value)
+    }
+
+    fn contains(&self, value: &Data) -> bool {
+        ValueStore::contains(self, value)
+    }
+
+    fn len(&self) -> usize {
+        ValueStore::len(self)
+    }
+
+    fn read_all(&self) -> Vec<Data> {
+        self.order.clone()
+    }
+}
+
+impl<Data: Copy + Hash + Eq> Store<Data> for HashSet<Data> {
+    fn insert(&mut self, value: Data) -> bool {
+        HashSet::insert(self, value)
+    }
+
+    fn contains(&self, value: &Data) -> bool {
+        HashSet::contains(self, value)
+    }
+
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+
+    fn read_all(&self) -> Vec<Data> {
+        self.iter().copied().collect()
+    }
+}
+
+// Sorted, so a read comes back in value order rather than arrival order
+impl<Data: Copy + Ord> Store<Data> for BTreeSet<Data> {
+    fn insert(&mut self, value: Data) -> bool {
+        BTreeSet::insert(self, value)
+    }
+
+    fn contains(&self, value: &Data) -> bool {
+        BTreeSet::contains(self, value)
+    }
+
+    fn len(&self) -> usize {
+        BTreeSet::len(self)
+    }
+
+    fn read_all(&self) -> Vec<Data> {
+        self.iter().copied().collect()
+    }
+}
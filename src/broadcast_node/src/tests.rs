@@ -1,15 +1,24 @@
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::{BroadcastNodeTrait, Message, MessageBody, Node, NodeTrait};
+use crate::{
+    decode_line, stream_sync_ok, AckMap, BoundedLines, BroadcastNodeTrait, Clock, Config,
+    Consistency, ErrorCode, GossipMode, Host, JsonCodec, Message, MessageBody, MockClock, Node,
+    NodeId, NodeTrait, OutboundQueue, OutboxKind, OversizedMessage, PeerCodec, PendingRpc,
+    RateLimit, RateLimiter, RpcError, Sequences, SyncPayloadStrategy, SyncPeerWeights, Tombstones,
+    Transport, ValueStore, WireCodec,
+};
 
 // ── Helpers ──────────────────────────────────────────────────────────────────
 
 fn make_node() -> Node<u32> {
     let mut node = Node::<u32>::new();
-    node.id = "n1".to_string();
-    node.node_ids = vec!["n1".to_string(), "n2".to_string(), "n3".to_string()];
+    node.id = "n1".into();
+    node.node_ids = vec!["n1".into(), "n2".into(), "n3".into()];
     node
 }
 
@@ -19,12 +28,16 @@ fn channel() -> (Sender<Message>, Receiver<Message>) {
 
 fn msg(src: &str, dest: &str, body: MessageBody) -> Message {
     Message {
-        src: src.to_string(),
-        dest: dest.to_string(),
+        src: src.into(),
+        dest: dest.into(),
         body,
     }
 }
 
+fn node_id(id: &str) -> NodeId {
+    NodeId::from(id)
+}
+
 fn drain(rx: &Receiver<Message>) -> Vec<Message> {
     let mut msgs = vec![];
     while let Ok(m) = rx.try_recv() {
@@ -33,6 +46,55 @@ fn drain(rx: &Receiver<Message>) -> Vec<Message> {
     msgs
 }
 
+// A member of the two node cluster n1, n2 holding `store`
+fn seeded(id: &str, store: &[u32]) -> Node<u32> {
+    Node::<u32>::new()
+        .with_membership(id, vec!["n1".into(), "n2".into()])
+        .with_store(store.iter().copied())
+}
+
+// One sync round: `n1` syncs with `n2` and every reply goes straight back.
+// Returns the sync and the replies for tests that look at them.
+fn sync_round(n1: &mut Node<u32>, n2: &mut Node<u32>) -> (Message, Vec<Message>) {
+    let (tx, rx) = channel();
+    let sync = n1
+        .request_sync_with_random_peers()
+        .into_iter()
+        .find(|m| m.dest == "n2")
+        .unwrap();
+    n2.next(sync.clone(), tx.clone()).unwrap();
+    let replies = drain(&rx);
+    for reply in &replies {
+        n1.next(reply.clone(), tx.clone()).unwrap();
+    }
+    (sync, replies)
+}
+
+fn sorted(node: &Node<u32>) -> Vec<u32> {
+    let mut values = node.read();
+    values.sort_unstable();
+    values
+}
+
+// The values a sync or sync_ok carries, sorted, whether they travel as a list
+// or a bitmap
+fn sync_values(body: &MessageBody) -> Vec<u32> {
+    let (MessageBody::sync {
+        messages, bitmap, ..
+    }
+    | MessageBody::sync_ok {
+        messages, bitmap, ..
+    }) = body
+    else {
+        panic!("expected sync or sync_ok body, got {:?}", body);
+    };
+    let mut values = crate::broadcast::sync_values(messages, bitmap.as_deref())
+        .unwrap()
+        .into_owned();
+    values.sort_unstable();
+    values
+}
+
 // ── Init ─────────────────────────────────────────────────────────────────────
 
 #[test]
@@ -45,8 +107,8 @@ fn init_sets_node_state_and_replies() {
         "n1",
         MessageBody::init {
             msg_id: 1,
-            node_id: "n1".to_string(),
-            node_ids: vec!["n1".to_string(), "n2".to_string()],
+            node_id: "n1".into(),
+            node_ids: vec!["n1".into(), "n2".into()],
         },
     );
     node.handle_init_message(incoming, tx).unwrap();
@@ -64,6 +126,172 @@ fn init_sets_node_state_and_replies() {
     ));
 }
 
+#[test]
+fn init_with_extra_fields_still_parses_as_init() {
+    let line = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2"],"maelstrom_version":"0.3","cluster":{"region":"eu"}}}"#;
+    let message = decode_line(line, usize::MAX).unwrap();
+    assert_eq!(
+        message.body,
+        MessageBody::init {
+            msg_id: 1,
+            node_id: "n1".into(),
+            node_ids: vec!["n1".into(), "n2".into()],
+        }
+    );
+
+    let mut node = Node::<u32>::new();
+    let (tx, rx) = channel();
+    node.next(message, tx).unwrap();
+    assert_eq!(node.id(), "n1");
+    assert!(matches!(
+        drain(&rx)[..],
+        [Message {
+            body: MessageBody::init_ok { in_reply_to: 1 },
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn reinit_resets_outboxes_but_keeps_store() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let init = |msg_id| {
+        msg(
+            "c1",
+            "n1",
+            MessageBody::init {
+                msg_id,
+                node_id: "n1".into(),
+                node_ids: vec!["n1".into(), "n4".into()],
+            },
+        )
+    };
+    node.handle_init_message(init(1), tx.clone()).unwrap();
+
+    node.store.insert(7u32);
+    node.add_to_outbox(OutboxKind::FanoutMsg, &node_id("n4"), 7)
+        .unwrap();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n4"), 7)
+        .unwrap();
+    node.track_gossip_batch("n4".into(), [7].into_iter().collect());
+    assert!(!node.msg_outbox.is_empty());
+    assert!(!node.retry_outbox.is_empty());
+    assert!(!node.in_flight_gossip.is_empty());
+
+    node.handle_init_message(init(2), tx).unwrap();
+
+    assert_eq!(node.node_ids, vec!["n1", "n4"]);
+    assert!(node.store.contains(&7u32), "store survives a restart");
+    assert!(node.msg_outbox.is_empty());
+    assert!(node.retry_outbox.is_empty());
+    assert!(node.in_flight_gossip.is_empty());
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 2, "both inits are acknowledged");
+    assert!(matches!(
+        sent[1].body,
+        MessageBody::init_ok { in_reply_to: 2 }
+    ));
+}
+
+#[test]
+fn stray_init_ok_is_ignored() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+
+    node.dispatch(msg("n2", "n1", MessageBody::init_ok { in_reply_to: 1 }), tx)
+        .unwrap();
+
+    assert!(drain(&rx).is_empty());
+    assert_eq!(node.id, "n1");
+}
+
+#[test]
+fn acks_are_counted_per_type_and_never_answered() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let acks = [
+        MessageBody::init_ok { in_reply_to: 1 },
+        MessageBody::topology_ok {
+            msg_id: 2,
+            in_reply_to: 1,
+        },
+        MessageBody::topology_ok {
+            msg_id: 3,
+            in_reply_to: 1,
+        },
+        MessageBody::gossip_ok { in_reply_to: None },
+    ];
+    for ack in acks {
+        node.dispatch(msg("n2", "n1", ack), tx.clone()).unwrap();
+    }
+
+    assert!(drain(&rx).is_empty());
+    let counts = node.acks_received();
+    assert_eq!(counts.get("init_ok"), Some(&1));
+    assert_eq!(counts.get("topology_ok"), Some(&2));
+    assert_eq!(counts.get("gossip_ok"), Some(&1));
+    assert_eq!(counts.get("read_ok"), None);
+}
+
+#[test]
+fn message_for_another_node_is_ignored() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+
+    let misrouted = msg(
+        "c1",
+        "n2",
+        MessageBody::broadcast {
+            message: 7,
+            msg_id: 1,
+        },
+    );
+    assert!(node.dispatch(misrouted, tx).is_err());
+
+    assert!(drain(&rx).is_empty());
+    assert!(!node.store.contains(&7));
+}
+
+#[test]
+fn node_ids_are_plain_strings_on_the_wire() {
+    let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2"]}}"#;
+    let message = decode_line(init, usize::MAX).unwrap();
+    assert!(message.src.is_client() && !message.src.is_node());
+    assert!(message.dest.is_node() && !message.dest.is_client());
+    assert_eq!(message.dest, "n1");
+    assert_eq!(serde_json::to_string(&message.src).unwrap(), r#""c1""#);
+}
+
+#[test]
+fn node_debug_summarizes_state() {
+    let mut node = make_node();
+    node.store.extend([1, 2, 3]);
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 1)
+        .unwrap();
+
+    let printed = format!("{:?}", node);
+    assert!(printed.contains(r#"id: "n1""#));
+    assert!(printed.contains("store_len: 3"));
+    assert!(printed.contains(r#"retry_outbox: {"n2": 1}"#));
+}
+
+#[test]
+fn constructors_and_accessors_describe_the_node() {
+    let ids = vec!["n1".into(), "n2".into()];
+    let node = Node::<u32>::new().with_membership("n1", ids.clone());
+    assert_eq!(node.id(), "n1");
+    assert_eq!(node.node_ids(), &ids[..]);
+    assert!(node.neighbors().is_empty(), "no topology yet");
+
+    let topology = HashMap::from([("n1".into(), vec!["n2".into()])]);
+    let node = node.with_topology(topology);
+    assert_eq!(node.neighbors(), ["n2"]);
+    assert_eq!(node.store_len(), 0);
+    assert_eq!(node.in_flight_batches().count(), 0);
+}
+
 // ── Echo ──────────────────────────────────────────────────────────────────────
 
 #[test]
@@ -100,6 +328,31 @@ fn echo_replies_with_same_text() {
 
 // ── Unique ID (generate) ─────────────────────────────────────────────────────
 
+#[test]
+fn generate_before_init_is_temporarily_unavailable() {
+    let mut node = Node::<u32>::new();
+    let (tx, rx) = channel();
+
+    let early = msg("c1", "n1", MessageBody::generate { msg_id: 1 });
+    assert!(node.next(early, tx.clone()).is_err());
+    assert!(matches!(
+        drain(&rx)[0].body,
+        MessageBody::error {
+            in_reply_to: 1,
+            code: 11,
+            ..
+        }
+    ));
+
+    node.next(init_msg("n1", &["n1"]), tx.clone()).unwrap();
+    let generate = msg("c1", "n1", MessageBody::generate { msg_id: 2 });
+    node.next(generate, tx).unwrap();
+    match &drain(&rx)[1].body {
+        MessageBody::generate_ok { id, .. } => assert!(id.starts_with("n1-")),
+        other => panic!("expected generate_ok, got {:?}", other),
+    }
+}
+
 #[test]
 fn generate_replies_with_unique_non_empty_id() {
     let mut node = make_node();
@@ -150,14 +403,171 @@ fn generate_ids_are_unique() {
     assert_eq!(ids.len(), unique.len(), "all generated ids must be unique");
 }
 
+#[test]
+fn generate_ids_from_different_nodes_never_collide() {
+    let (tx, rx) = channel();
+    let mut ids = std::collections::HashSet::new();
+    for node_id in ["n1", "n2", "n3"] {
+        let mut node = make_node();
+        node.id = node_id.into();
+        for i in 0..100u32 {
+            node.handle_generate_message(
+                msg("c1", node_id, MessageBody::generate { msg_id: i }),
+                tx.clone(),
+            )
+            .unwrap();
+        }
+        for reply in drain(&rx) {
+            let MessageBody::generate_ok { id, .. } = reply.body else {
+                panic!("unexpected body");
+            };
+            assert!(id.starts_with(&format!("{node_id}-")));
+            assert!(ids.insert(id), "duplicate id across nodes");
+        }
+    }
+    assert_eq!(ids.len(), 300);
+}
+
 // ── Broadcast ─────────────────────────────────────────────────────────────────
 
+#[test]
+fn node_missing_from_topology_fans_out_to_all_other_nodes() {
+    let mut node = make_node();
+    node.topology.insert("n2".into(), vec!["n3".into()]);
+    let (tx, _rx) = channel();
+
+    node.handle_broadcast_message(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::broadcast {
+                message: 5,
+                msg_id: 1,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    let queued: Vec<&NodeId> = node.msg_outbox.keys().collect();
+    assert_eq!(queued, vec!["n3"], "everyone but us and the source");
+}
+
+#[test]
+fn max_fanout_caps_peers_per_broadcast_with_a_random_subset() {
+    let ids: Vec<NodeId> = (1..=10).map(|i| NodeId::from(format!("n{i}"))).collect();
+    let config = Config {
+        fanout: usize::MAX,
+        max_fanout: NonZeroUsize::new(3),
+        seed: Some(7),
+        ..Config::default()
+    };
+    for all_to_all in [true, false] {
+        let mut node = Node::<u32>::new()
+            .with_config(config.clone())
+            .with_membership("n1", ids.clone());
+        if all_to_all {
+            node.topology.insert("n1".into(), ids.clone());
+        }
+        let (tx, rx) = channel();
+        let mut reached = std::collections::HashSet::new();
+        for value in 0..20 {
+            let body = MessageBody::broadcast {
+                message: value,
+                msg_id: value,
+            };
+            node.handle_broadcast_message(msg("c1", "n1", body), tx.clone())
+                .unwrap();
+            drain(&rx);
+            node.fanout_messages(tx.clone()).unwrap();
+            let gossip: Vec<Message> = drain(&rx)
+                .into_iter()
+                .filter(|m| matches!(m.body, MessageBody::gossip { .. }))
+                .collect();
+            assert_eq!(gossip.len(), 3, "value {value}, all-to-all {all_to_all}");
+            reached.extend(gossip.into_iter().map(|m| m.dest));
+        }
+        assert!(reached.len() > 3, "the subset is drawn per value");
+    }
+    assert!(Config::from_vars(vars(&[("DIST_MAX_FANOUT", "0")])).is_err());
+}
+
+#[test]
+fn broadcast_is_never_fanned_back_to_its_source_neighbour() {
+    let mut node = make_node();
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    let (tx, rx) = channel();
+
+    let broadcast = MessageBody::broadcast {
+        message: 6,
+        msg_id: 1,
+    };
+    node.handle_broadcast_message(msg("n2", "n1", broadcast), tx.clone())
+        .unwrap();
+    assert!(!node.msg_outbox.contains_key("n2"));
+    assert!(!node.retry_outbox.contains_key("n2"));
+    node.fanout_messages(tx.clone()).unwrap();
+    node.retry_messages(tx).unwrap();
+
+    let sent = drain(&rx);
+    let to_source: Vec<&MessageBody> = sent
+        .iter()
+        .filter(|m| m.dest == "n2")
+        .map(|m| &m.body)
+        .collect();
+    assert!(
+        matches!(to_source[..], [MessageBody::broadcast_ok { .. }]),
+        "n2 only gets its ack, got {:?}",
+        to_source
+    );
+    assert!(sent
+        .iter()
+        .any(|m| m.dest == "n3" && matches!(m.body, MessageBody::gossip { .. })));
+}
+
+#[test]
+fn client_broadcast_is_acked_but_never_gossiped_back() {
+    let mut node = make_node();
+    // a topology that wrongly lists the client as a neighbour
+    node.topology
+        .insert("n1".into(), vec!["c1".into(), "c2".into()]);
+    let (tx, rx) = channel();
+
+    node.handle_broadcast_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 99,
+                msg_id: 1,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    node.fanout_messages(tx.clone()).unwrap();
+    node.retry_messages(tx).unwrap();
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "c1");
+    assert!(matches!(
+        sent[0].body,
+        MessageBody::broadcast_ok {
+            in_reply_to: Some(1),
+            ..
+        }
+    ));
+    assert_eq!(node.unacked(), 0);
+}
+
 #[test]
 fn broadcast_new_message_acks_and_fans_out_to_neighbours() {
     let mut node = make_node();
     // topology: n1 -> [n2, n3]
     node.topology
-        .insert("n1".to_string(), vec!["n2".to_string(), "n3".to_string()]);
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
     let (tx, rx) = channel();
 
     // Message arrives from a client (not a neighbour)
@@ -177,7 +587,13 @@ fn broadcast_new_message_acks_and_fans_out_to_neighbours() {
     let ack = &sent[0];
     assert_eq!(ack.dest, "c1");
     assert!(
-        matches!(ack.body, MessageBody::broadcast_ok { in_reply_to: 1, .. }),
+        matches!(
+            ack.body,
+            MessageBody::broadcast_ok {
+                in_reply_to: Some(1),
+                ..
+            }
+        ),
         "ack should be broadcast_ok in_reply_to=1"
     );
 
@@ -199,7 +615,7 @@ fn broadcast_does_not_fan_out_back_to_sender() {
     let mut node = make_node();
     // n2 is both the sender and a topology neighbour
     node.topology
-        .insert("n1".to_string(), vec!["n2".to_string(), "n3".to_string()]);
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
     let (tx, rx) = channel();
 
     let incoming = msg(
@@ -234,8 +650,7 @@ fn broadcast_does_not_fan_out_back_to_sender() {
 #[test]
 fn broadcast_duplicate_message_acks_without_fanout() {
     let mut node = make_node();
-    node.topology
-        .insert("n1".to_string(), vec!["n2".to_string()]);
+    node.topology.insert("n1".into(), vec!["n2".into()]);
     let (tx, rx) = channel();
 
     let make_msg = || {
@@ -259,7 +674,10 @@ fn broadcast_duplicate_message_acks_without_fanout() {
     assert_eq!(sent.len(), 1);
     assert!(matches!(
         sent[0].body,
-        MessageBody::broadcast_ok { in_reply_to: 1, .. }
+        MessageBody::broadcast_ok {
+            in_reply_to: Some(1),
+            ..
+        }
     ));
     assert!(
         node.msg_outbox
@@ -270,10 +688,81 @@ fn broadcast_duplicate_message_acks_without_fanout() {
 }
 
 #[test]
-fn broadcast_new_message_added_to_outbox() {
+fn value_looping_back_around_a_ring_is_acked_but_not_refanned() {
+    let mut node = make_node();
+    node.topology = HashMap::from([
+        ("n1".into(), vec!["n2".into()]),
+        ("n2".into(), vec!["n3".into()]),
+        ("n3".into(), vec!["n1".into()]),
+    ]);
+    let (tx, rx) = channel();
+    let broadcast = |msg_id| MessageBody::broadcast { message: 7, msg_id };
+
+    node.next(msg("c1", "n1", broadcast(1)), tx.clone())
+        .unwrap();
+    drain(&rx);
+    let outbox_before = node.msg_outbox.clone();
+
+    // n2 passed it on to n3, which hands it back to where it started
+    node.next(msg("n3", "n1", broadcast(40)), tx).unwrap();
+    let replies = drain(&rx);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].dest, "n3");
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::broadcast_ok {
+            in_reply_to: Some(40),
+            ..
+        }
+    ));
+    assert_eq!(node.msg_outbox, outbox_before, "nothing new to fan out");
+}
+
+#[test]
+fn bulk_broadcast_replies_once_and_gossips_new_values_in_one_batch() {
     let mut node = make_node();
     node.topology
-        .insert("n1".to_string(), vec!["n2".to_string()]);
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    node.store.insert(3);
+    let (tx, rx) = channel();
+
+    let bulk = MessageBody::bulk_broadcast {
+        msg_id: 8,
+        messages: vec![1, 2, 2, 3],
+    };
+    node.next(msg("c1", "n1", bulk), tx.clone()).unwrap();
+    node.fanout_messages(tx).unwrap();
+
+    let sent = drain(&rx);
+    let replies: Vec<&Message> = sent.iter().filter(|m| m.dest == "c1").collect();
+    assert_eq!(replies.len(), 1);
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::bulk_broadcast_ok { in_reply_to: 8, .. }
+    ));
+    let mut gossip: Vec<(NodeId, Vec<u32>)> = sent
+        .into_iter()
+        .filter_map(|m| match m.body {
+            MessageBody::gossip { mut messages, .. } => {
+                messages.sort();
+                Some((m.dest, messages))
+            }
+            _ => None,
+        })
+        .collect();
+    gossip.sort();
+    assert_eq!(
+        gossip,
+        vec![("n2".into(), vec![1, 2]), ("n3".into(), vec![1, 2])],
+        "3 was already known"
+    );
+    assert_eq!(node.store.len(), 3);
+}
+
+#[test]
+fn broadcast_new_message_added_to_outbox() {
+    let mut node = make_node();
+    node.topology.insert("n1".into(), vec!["n2".into()]);
     let (tx, _rx) = channel();
 
     node.handle_broadcast_message(
@@ -296,6 +785,47 @@ fn broadcast_new_message_added_to_outbox() {
     assert!(node.retry_outbox["n2"].contains(&42));
 }
 
+#[test]
+fn provenance_names_whoever_first_delivered_each_value() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let broadcast = |message| MessageBody::broadcast { message, msg_id: 1 };
+    node.next(msg("c1", "n1", broadcast(1)), tx.clone())
+        .unwrap();
+    node.next(msg("n2", "n1", broadcast(1)), tx.clone())
+        .unwrap();
+    node.next(
+        msg(
+            "n3",
+            "n1",
+            MessageBody::sync {
+                msg_id: 2,
+                messages: vec![1, 2],
+                tombstones: Default::default(),
+                checksum: None,
+                seq: None,
+                mode: Default::default(),
+                bitmap: None,
+                signature: None,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    drain(&rx);
+
+    node.next(msg("c2", "n1", MessageBody::provenance { msg_id: 3 }), tx)
+        .unwrap();
+    let sent = drain(&rx);
+    let expected = HashMap::from([(1, "c1".into()), (2, "n3".into())]);
+    assert!(
+        matches!(&sent[..], [reply] if reply.dest == "c2"
+            && reply.body == MessageBody::provenance_ok { in_reply_to: 3, sources: expected }),
+        "unexpected reply {:?}",
+        sent
+    );
+}
+
 // ── Read ─────────────────────────────────────────────────────────────────────
 
 #[test]
@@ -303,8 +833,21 @@ fn read_returns_empty_store() {
     let mut node = make_node();
     let (tx, rx) = channel();
 
-    node.handle_read_message(msg("c1", "n1", MessageBody::read { msg_id: 3 }), tx)
-        .unwrap();
+    node.handle_read_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::read {
+                msg_id: 3,
+                quorum: false,
+                offset: None,
+                limit: None,
+                from: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
 
     let sent = drain(&rx);
     assert_eq!(sent.len(), 1);
@@ -323,6 +866,26 @@ fn read_returns_empty_store() {
     }
 }
 
+// strict clients reject `null`, an empty store still has to go out as []
+#[test]
+fn read_before_any_broadcast_sends_an_empty_array() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let read: Message =
+        serde_json::from_str(r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#)
+            .unwrap();
+
+    node.next(read, tx).unwrap();
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    let body = serde_json::to_value(&sent[0].body).unwrap();
+    assert_eq!(body["type"], "read_ok");
+    assert_eq!(body["in_reply_to"], 3);
+    assert_eq!(body["messages"], serde_json::json!([]));
+    assert!(body.get("next_offset").is_none());
+}
+
 #[test]
 fn read_returns_all_stored_values() {
     let mut node = make_node();
@@ -331,8 +894,21 @@ fn read_returns_all_stored_values() {
     node.store.insert(3u32);
     let (tx, rx) = channel();
 
-    node.handle_read_message(msg("c1", "n1", MessageBody::read { msg_id: 5 }), tx)
-        .unwrap();
+    node.handle_read_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::read {
+                msg_id: 5,
+                quorum: false,
+                offset: None,
+                limit: None,
+                from: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
 
     let sent = drain(&rx);
     match &sent[0].body {
@@ -345,17 +921,200 @@ fn read_returns_all_stored_values() {
     }
 }
 
+#[test]
+fn paged_read_walks_store_in_insertion_order() {
+    let mut node = make_node();
+    for value in [40, 10, 30, 20, 50] {
+        node.insert_if_absent(value, &node_id("c1"));
+    }
+    let (tx, rx) = channel();
+    let mut pages = Vec::new();
+    let mut offset = Some(0);
+    while let Some(start) = offset {
+        let read = MessageBody::read {
+            msg_id: 1,
+            quorum: false,
+            offset: Some(start),
+            limit: Some(2),
+            from: None,
+        };
+        node.handle_read_message(msg("c1", "n1", read), tx.clone())
+            .unwrap();
+        let MessageBody::read_ok {
+            messages,
+            next_offset,
+            ..
+        } = drain(&rx).remove(0).body
+        else {
+            panic!("expected read_ok");
+        };
+        pages.push(messages);
+        offset = next_offset;
+    }
+    assert_eq!(pages, vec![vec![40, 10], vec![30, 20], vec![50]]);
+
+    // without paging fields the whole store comes back with no cursor
+    let read = MessageBody::read {
+        msg_id: 2,
+        quorum: false,
+        offset: None,
+        limit: None,
+        from: None,
+    };
+    node.handle_read_message(msg("c1", "n1", read), tx).unwrap();
+    assert!(matches!(
+        drain(&rx)[0].body,
+        MessageBody::read_ok { ref messages, next_offset: None, .. } if messages.len() == 5
+    ));
+}
+
+#[test]
+fn read_wait_is_answered_by_a_broadcast_arriving_while_it_waits() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    node.insert_if_absent(7, &node_id("c1"));
+    let (tx, rx) = channel();
+    let wait = |msg_id, since| {
+        msg(
+            "c2",
+            "n1",
+            MessageBody::read_wait {
+                msg_id,
+                since,
+                timeout: 100,
+            },
+        )
+    };
+
+    node.next(wait(1, 1), tx.clone()).unwrap();
+    clock.advance(Duration::from_millis(50));
+    node.tick(tx.clone()).unwrap();
+    assert!(
+        !drain(&rx).iter().any(|m| m.dest == "c2"),
+        "nothing new yet"
+    );
+
+    let broadcast = MessageBody::broadcast {
+        message: 9,
+        msg_id: 5,
+    };
+    node.next(msg("c1", "n1", broadcast), tx.clone()).unwrap();
+    let woken: Vec<MessageBody> = drain(&rx)
+        .into_iter()
+        .filter(|m| m.dest == "c2")
+        .map(|m| m.body)
+        .collect();
+    assert!(matches!(
+        woken[..],
+        [MessageBody::read_wait_ok { in_reply_to: 1, ref messages, next: 2, .. }] if messages == &[9]
+    ));
+    assert!(node.pending.is_empty());
+
+    // a store that already grew past `since` answers right away
+    node.next(wait(2, 0), tx.clone()).unwrap();
+    assert!(matches!(
+        drain(&rx)[0].body,
+        MessageBody::read_wait_ok { in_reply_to: 2, ref messages, .. } if messages == &[7, 9]
+    ));
+}
+
+#[test]
+fn read_wait_times_out_with_an_empty_list() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    let wait = MessageBody::read_wait {
+        msg_id: 1,
+        since: 0,
+        timeout: 100,
+    };
+    node.next(msg("c2", "n1", wait), tx.clone()).unwrap();
+
+    clock.advance(Duration::from_millis(100));
+    node.tick(tx.clone()).unwrap();
+    let replies: Vec<MessageBody> = drain(&rx)
+        .into_iter()
+        .filter(|m| m.dest == "c2")
+        .map(|m| m.body)
+        .collect();
+    assert!(matches!(
+        replies[..],
+        [MessageBody::read_wait_ok { in_reply_to: 1, ref messages, next: 0, .. }] if messages.is_empty()
+    ));
+    assert!(node.pending.is_empty());
+}
+
+#[test]
+fn read_wait_timeout_is_clamped_to_the_max_wait() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    let wait = MessageBody::read_wait {
+        msg_id: 1,
+        since: 0,
+        timeout: u64::MAX,
+    };
+    node.next(msg("c2", "n1", wait), tx.clone()).unwrap();
+    assert!(drain(&rx).is_empty(), "parked, not answered");
+
+    clock.advance(crate::read_wait::MAX_WAIT);
+    node.tick(tx.clone()).unwrap();
+    assert!(drain(&rx)
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::read_wait_ok { in_reply_to: 1, .. })));
+    assert!(node.pending.is_empty());
+}
+
 // ── Topology ──────────────────────────────────────────────────────────────────
 
+#[test]
+fn topology_change_only_applies_to_later_broadcasts() {
+    let mut node = make_node();
+    node.topology.insert("n1".into(), vec!["n2".into()]);
+    let (tx, rx) = channel();
+    let broadcast = |message| {
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message,
+                msg_id: message,
+            },
+        )
+    };
+
+    node.next(broadcast(1), tx.clone()).unwrap();
+    let topology = MessageBody::topology {
+        topology: [("n1".into(), vec!["n3".into()])].into_iter().collect(),
+        msg_id: 9,
+    };
+    node.next(msg("c0", "n1", topology), tx.clone()).unwrap();
+    node.next(broadcast(2), tx.clone()).unwrap();
+    node.fanout_messages(tx).unwrap();
+
+    let mut gossip: Vec<(NodeId, Vec<u32>)> = drain(&rx)
+        .into_iter()
+        .filter_map(|m| match m.body {
+            MessageBody::gossip { messages, .. } => Some((m.dest, messages)),
+            _ => None,
+        })
+        .collect();
+    gossip.sort();
+    assert_eq!(
+        gossip,
+        vec![("n2".into(), vec![1]), ("n3".into(), vec![2])],
+        "1 keeps the neighbours it was queued for"
+    );
+}
+
 #[test]
 fn topology_updates_node_and_acks() {
     let mut node = make_node();
     let (tx, rx) = channel();
 
-    let topo: HashMap<String, Vec<String>> =
-        [("n1".to_string(), vec!["n2".to_string(), "n3".to_string()])]
-            .into_iter()
-            .collect();
+    let topo: HashMap<NodeId, Vec<NodeId>> = [("n1".into(), vec!["n2".into(), "n3".into()])]
+        .into_iter()
+        .collect();
 
     node.handle_topology_message(
         msg(
@@ -385,6 +1144,76 @@ fn topology_updates_node_and_acks() {
     );
 }
 
+// ── Reconfigure ──────────────────────────────────────────────────────────────
+
+#[test]
+fn reconfigure_replaces_members_and_prunes_removed_peers() {
+    let mut node = make_node();
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    for peer in ["n2", "n3"] {
+        node.add_to_outbox(OutboxKind::RetryMsg, &node_id(peer), 1)
+            .unwrap();
+        node.add_to_outbox(OutboxKind::FanoutMsg, &node_id(peer), 1)
+            .unwrap();
+    }
+    node.track_gossip_batch("n3".into(), [1].into_iter().collect());
+    let (tx, rx) = channel();
+
+    let members = vec!["n1".into(), "n2".into(), "n4".into()];
+    node.dispatch(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::reconfigure {
+                msg_id: 3,
+                node_ids: members.clone(),
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        drain(&rx)[0].body,
+        MessageBody::reconfigure_ok { in_reply_to: 3 }
+    ));
+    assert_eq!(node.node_ids, members);
+    assert!(!node.retry_outbox.contains_key("n3"));
+    assert!(!node.msg_outbox.contains_key("n3"));
+    assert!(node.retry_outbox.contains_key("n2"));
+    assert!(node.in_flight_gossip.is_empty());
+    assert_eq!(node.topology["n1"], ["n2"]);
+}
+
+#[test]
+fn retry_drops_outboxes_of_peers_that_are_not_members() {
+    let mut node = make_node();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 1)
+        .unwrap();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n3"), 1)
+        .unwrap();
+    // a topology can name a node that never joined
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n9"), 1)
+        .unwrap();
+    node.add_to_outbox(OutboxKind::FanoutMsg, &node_id("n9"), 2)
+        .unwrap();
+    let (tx, rx) = channel();
+
+    let reconfigure = MessageBody::reconfigure {
+        msg_id: 1,
+        node_ids: vec!["n1".into(), "n2".into()],
+    };
+    node.next(msg("c1", "n1", reconfigure), tx.clone()).unwrap();
+    drain(&rx);
+    node.retry_messages(tx).unwrap();
+
+    let sent: Vec<NodeId> = drain(&rx).into_iter().map(|m| m.dest).collect();
+    assert_eq!(sent, ["n2"]);
+    assert_eq!(node.outbox_depths(), HashMap::from([(node_id("n2"), 1)]));
+    assert!(!node.msg_outbox.contains_key("n9"));
+}
+
 // ── Sync ─────────────────────────────────────────────────────────────────────
 
 #[test]
@@ -402,6 +1231,12 @@ fn sync_merges_their_data_and_replies_with_ours() {
         MessageBody::sync {
             msg_id: 10,
             messages: vec![2, 3, 4],
+            tombstones: Default::default(),
+            checksum: None,
+            seq: None,
+            mode: Default::default(),
+            bitmap: None,
+            signature: None,
         },
     );
     node.handle_sync_message(incoming, tx).unwrap();
@@ -414,18 +1249,19 @@ fn sync_merges_their_data_and_replies_with_ours() {
     assert_eq!(sent.len(), 1);
     assert_eq!(sent[0].src, "n1");
     assert_eq!(sent[0].dest, "n2");
-    match &sent[0].body {
+    assert!(matches!(
+        sent[0].body,
         MessageBody::sync_ok {
-            in_reply_to,
-            messages,
+            in_reply_to: Some(10),
             ..
-        } => {
-            assert_eq!(*in_reply_to, 10);
-            // We reply with what we had that they didn't: [1]
-            assert_eq!(messages, &vec![1u32], "sync_ok should contain only value 1");
         }
-        other => panic!("expected sync_ok, got {:?}", other),
-    }
+    ));
+    // We reply with what we had that they didn't: [1]
+    assert_eq!(
+        sync_values(&sent[0].body),
+        vec![1u32],
+        "sync_ok should contain only value 1"
+    );
 }
 
 #[test]
@@ -441,6 +1277,12 @@ fn sync_with_identical_stores_sends_empty_sync_ok() {
             MessageBody::sync {
                 msg_id: 1,
                 messages: vec![5],
+                tombstones: Default::default(),
+                checksum: None,
+                seq: None,
+                mode: Default::default(),
+                bitmap: None,
+                signature: None,
             },
         ),
         tx,
@@ -448,12 +1290,144 @@ fn sync_with_identical_stores_sends_empty_sync_ok() {
     .unwrap();
 
     let sent = drain(&rx);
-    match &sent[0].body {
-        MessageBody::sync_ok { messages, .. } => {
-            assert!(messages.is_empty(), "nothing new to send back");
+    assert!(
+        sync_values(&sent[0].body).is_empty(),
+        "nothing new to send back"
+    );
+}
+
+#[test]
+fn empty_sync_is_a_pull_capped_by_the_reply_limit() {
+    let pull = |node: &mut Node<u32>| {
+        let (tx, rx) = channel();
+        node.handle_sync_message(
+            msg(
+                "n2",
+                "n1",
+                MessageBody::sync {
+                    msg_id: 4,
+                    messages: vec![],
+                    tombstones: Default::default(),
+                    checksum: None,
+                    seq: None,
+                    mode: Default::default(),
+                    bitmap: None,
+                    signature: None,
+                },
+            ),
+            tx,
+        )
+        .unwrap();
+        let reply = drain(&rx).remove(0);
+        assert!(matches!(
+            reply.body,
+            MessageBody::sync_ok {
+                in_reply_to: Some(4),
+                ..
+            }
+        ));
+        sync_values(&reply.body)
+    };
+    let mut node = make_node();
+    node.store.extend([7u32, 3, 9, 1]);
+
+    assert_eq!(pull(&mut node), vec![1, 3, 7, 9]);
+    node.config.sync_reply_limit = NonZeroUsize::new(2);
+    assert_eq!(pull(&mut node), vec![3, 7], "oldest values first");
+    assert_eq!(node.store.len(), 4);
+    assert!(Config::from_vars(vars(&[("DIST_SYNC_REPLY_LIMIT", "0")])).is_err());
+}
+
+#[test]
+fn capped_sync_ok_is_continued_until_the_peer_catches_up() {
+    let mut ahead = make_node();
+    ahead.config.sync_reply_limit = NonZeroUsize::new(3);
+    ahead.store.extend(0..10u32);
+    let mut behind = make_node();
+    behind.id = "n2".into();
+    // both ids get picked, so there's always a sync for n1
+    behind.node_ids = vec!["n1".into(), "n2".into()];
+    behind.store.insert(100);
+    let (tx, rx) = channel();
+
+    let mut sync = behind
+        .request_sync_with_random_peers()
+        .into_iter()
+        .find(|m| m.dest == "n1")
+        .unwrap();
+    let mut replies = 0;
+    loop {
+        ahead.handle_sync_message(sync, tx.clone()).unwrap();
+        let reply = drain(&rx).remove(0);
+        let MessageBody::sync_ok { more, .. } = reply.body else {
+            panic!("expected sync_ok, got {:?}", reply.body);
+        };
+        assert!(sync_values(&reply.body).len() <= 3);
+        replies += 1;
+        behind
+            .handle_sync_ok_message(reply.clone(), tx.clone())
+            .unwrap();
+        if !more {
+            break;
         }
-        other => panic!("expected sync_ok, got {:?}", other),
+        sync = drain(&rx).remove(0);
+        assert!(matches!(sync.body, MessageBody::sync { .. }));
     }
+
+    assert_eq!(replies, 4, "10 values in pages of 3");
+    assert_eq!(behind.store.len(), 11);
+    assert_eq!(ahead.store.len(), 11);
+    assert!(drain(&rx).is_empty());
+}
+
+#[test]
+fn matching_checksum_skips_the_exchange() {
+    let mut a = make_node();
+    let mut b = make_node();
+    a.store.extend([1, 2, 3]);
+    b.store.extend([3, 2, 1]);
+    assert_eq!(a.store_checksum(), b.store_checksum());
+    b.store.insert(4);
+    assert_ne!(a.store_checksum(), b.store_checksum());
+    b.store.remove(&4);
+
+    let (tx, rx) = channel();
+    let sync = a
+        .request_sync_with_random_peers()
+        .into_iter()
+        .find(|m| m.dest == "n2")
+        .unwrap();
+    assert!(matches!(
+        sync.body,
+        MessageBody::sync { checksum: Some(sum), .. } if sum == b.store_checksum()
+    ));
+    b.handle_sync_message(sync, tx.clone()).unwrap();
+    let sent = drain(&rx);
+    assert!(sync_values(&sent[0].body).is_empty());
+
+    // the checksum alone decides, values that differ aren't looked at
+    let sync = msg(
+        "n2",
+        "n1",
+        MessageBody::sync {
+            msg_id: 9,
+            messages: vec![7, 8],
+            tombstones: Default::default(),
+            checksum: Some(b.store_checksum()),
+            seq: None,
+            mode: GossipMode::PushPull,
+            bitmap: None,
+            signature: None,
+        },
+    );
+    b.handle_sync_message(sync, tx).unwrap();
+    assert_eq!(b.read(), vec![3, 2, 1], "nothing merged");
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(
+        &sent[0].body,
+        MessageBody::sync_ok { in_reply_to: Some(9), messages, .. } if messages.is_empty()
+    ));
 }
 
 #[test]
@@ -468,8 +1442,13 @@ fn sync_ok_inserts_new_values_no_reply() {
             "n1",
             MessageBody::sync_ok {
                 msg_id: 1,
-                in_reply_to: 0,
+                in_reply_to: Some(0),
                 messages: vec![2, 3],
+                tombstones: Default::default(),
+                seq: None,
+                more: false,
+                bitmap: None,
+                signature: None,
             },
         ),
         tx,
@@ -482,6 +1461,193 @@ fn sync_ok_inserts_new_values_no_reply() {
     assert!(drain(&rx).is_empty(), "sync_ok must not send any reply");
 }
 
+#[test]
+fn sync_ok_merge_is_order_independent() {
+    let replies = [vec![1u32, 2], vec![2, 3, 4], vec![5, 1]];
+    let orders = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ];
+
+    let stores: Vec<_> = orders
+        .iter()
+        .map(|order| {
+            let mut node = make_node();
+            node.store.insert(9u32);
+            let (tx, _rx) = channel();
+            for &i in order {
+                node.handle_sync_ok_message(
+                    msg(
+                        "n2",
+                        "n1",
+                        MessageBody::sync_ok {
+                            msg_id: i as u32,
+                            in_reply_to: Some(0),
+                            messages: replies[i].clone(),
+                            tombstones: Default::default(),
+                            seq: None,
+                            more: false,
+                            bitmap: None,
+                            signature: None,
+                        },
+                    ),
+                    tx.clone(),
+                )
+                .unwrap();
+            }
+            node.store
+        })
+        .collect();
+
+    for store in &stores[1..] {
+        assert_eq!(store, &stores[0], "merge must be commutative");
+    }
+    assert_eq!(stores[0].len(), 6);
+}
+
+#[test]
+fn one_sync_round_reconciles_both_sides_whoever_initiates() {
+    for (initiator, responder) in [("n1", "n2"), ("n2", "n1")] {
+        let mut host = Host::<u32>::new(Config::default());
+        let (tx, rx) = channel();
+        let ids = ["n1", "n2"];
+        for id in ids {
+            host.route(init_msg(id, &ids), tx.clone()).unwrap();
+        }
+        host.node_mut("n1").unwrap().store.extend([1, 2, 3]);
+        host.node_mut("n2").unwrap().store.extend([3, 4]);
+
+        let request = host
+            .node_mut(initiator)
+            .unwrap()
+            .request_sync_with_random_peers()
+            .into_iter()
+            .find(|m| m.dest == responder)
+            .unwrap();
+        // delivers the sync and then the sync_ok it triggers
+        host.route(request, tx).unwrap();
+
+        assert!(host.converged(), "{initiator} initiating left stores apart");
+        assert_eq!(host.node("n1").unwrap().store.len(), 4);
+        drain(&rx);
+    }
+}
+
+#[test]
+fn seeded_divergent_nodes_reconcile_in_one_sync_exchange() {
+    let mut n1 = seeded("n1", &[5, 1, 3]);
+    let mut n2 = seeded("n2", &[3, 8]);
+    assert_eq!(n1.read_page(0, usize::MAX).0, vec![5, 1, 3]);
+    assert_eq!(n1.provenance[&5], "seed");
+
+    sync_round(&mut n1, &mut n2);
+    assert_eq!(sorted(&n1), vec![1, 3, 5, 8]);
+    assert_eq!(sorted(&n2), vec![1, 3, 5, 8]);
+}
+
+#[test]
+fn gossip_mode_decides_which_way_a_sync_moves_values() {
+    let cases = [
+        (GossipMode::Push, vec![1, 2], vec![1, 2, 3]),
+        (GossipMode::Pull, vec![1, 2, 3], vec![2, 3]),
+        (GossipMode::PushPull, vec![1, 2, 3], vec![1, 2, 3]),
+    ];
+    for (mode, initiator_after, responder_after) in cases {
+        let mut n1 = seeded("n1", &[1, 2]).with_config(Config {
+            gossip_mode: mode,
+            ..Config::default()
+        });
+        let mut n2 = seeded("n2", &[2, 3]);
+        let (sync, _) = sync_round(&mut n1, &mut n2);
+        let line = serde_json::to_string(&sync).unwrap();
+        assert_eq!(line.contains(r#""mode":"#), mode != GossipMode::PushPull);
+        assert_eq!(
+            sync_values(&sync.body).is_empty(),
+            mode == GossipMode::Pull,
+            "{mode:?} payload"
+        );
+
+        assert_eq!(sorted(&n1), initiator_after, "{mode:?} initiator");
+        assert_eq!(sorted(&n2), responder_after, "{mode:?} responder");
+    }
+
+    let config = Config::from_vars([("DIST_GOSSIP_MODE".to_string(), "pull".to_string())]);
+    assert_eq!(config.unwrap().gossip_mode, GossipMode::Pull);
+}
+
+#[test]
+fn store_diff_splits_symmetric_difference() {
+    let mut a = make_node();
+    let mut b = make_node();
+    a.store.extend([1, 2, 3]);
+    b.store.extend([3, 4]);
+
+    let (mut only_a, only_b) = a.store_diff(&b);
+    only_a.sort_unstable();
+    assert_eq!(only_a, vec![1, 2]);
+    assert_eq!(only_b, vec![4]);
+    assert_eq!(a.store_diff(&a), (vec![], vec![]));
+}
+
+#[test]
+fn value_store_keeps_insertion_order() {
+    let mut store: ValueStore<u32> = [7, 3].into_iter().collect();
+    assert!(store.insert(9));
+    assert!(!store.insert(3), "duplicates are ignored");
+    assert_eq!(store.recent(2), &[3, 9]);
+    assert_eq!(store.recent(10), &[7, 3, 9]);
+
+    store.remove(&3);
+    assert_eq!(store.iter().copied().collect::<Vec<_>>(), vec![7, 9]);
+    let reordered: ValueStore<u32> = [9, 7].into_iter().collect();
+    assert_eq!(store, reordered, "equality ignores order");
+}
+
+#[test]
+fn fanout_on_sync_forwards_values_learned_from_sync_ok() {
+    let sync_ok = || {
+        msg(
+            "n2",
+            "n1",
+            MessageBody::sync_ok {
+                msg_id: 1,
+                in_reply_to: Some(0),
+                messages: vec![5, 6],
+                tombstones: Default::default(),
+                seq: None,
+                more: false,
+                bitmap: None,
+                signature: None,
+            },
+        )
+    };
+    let (tx, _rx) = channel();
+    let topology = || {
+        [("n1".into(), vec!["n2".into(), "n3".into()])]
+            .into_iter()
+            .collect()
+    };
+
+    let mut quiet = make_node();
+    quiet.topology = topology();
+    quiet.handle_sync_ok_message(sync_ok(), tx.clone()).unwrap();
+    assert!(quiet.msg_outbox.is_empty(), "off by default");
+
+    let mut node = make_node().with_config(Config {
+        fanout_on_sync: true,
+        ..Config::default()
+    });
+    node.topology = topology();
+    node.store.insert(6);
+    node.handle_sync_ok_message(sync_ok(), tx).unwrap();
+    assert_eq!(node.msg_outbox.len(), 1, "not back to the sender");
+    assert_eq!(node.msg_outbox["n3"], [5].into_iter().collect());
+}
+
 // ── Gossip OK (outbox management) ────────────────────────────────────────────
 
 #[test]
@@ -489,21 +1655,20 @@ fn gossip_ok_removes_messages_from_outbox() {
     let mut node = make_node();
     let (tx, rx) = channel();
 
-    node.retry_outbox
-        .entry("n2".to_string())
-        .or_default()
-        .insert(10);
-    node.retry_outbox
-        .entry("n2".to_string())
-        .or_default()
-        .insert(11);
-    node.in_flight_gossip
-        .insert(5, ("n2".to_string(), [10].into_iter().collect()));
+    node.retry_outbox.entry("n2".into()).or_default().insert(10);
+    node.retry_outbox.entry("n2".into()).or_default().insert(11);
+    let batch = node.track_gossip_batch("n2".into(), [10].into_iter().collect());
 
     assert_eq!(node.retry_outbox["n2"].len(), 2);
 
     node.handle_gossip_ok_message(
-        msg("n2", "n1", MessageBody::gossip_ok { in_reply_to: 5 }),
+        msg(
+            "n2",
+            "n1",
+            MessageBody::gossip_ok {
+                in_reply_to: Some(batch),
+            },
+        ),
         tx,
     )
     .unwrap();
@@ -516,52 +1681,260 @@ fn gossip_ok_removes_messages_from_outbox() {
     );
 }
 
-// ── Retry ─────────────────────────────────────────────────────────────────────
-
 #[test]
-fn retry_resends_all_outbox_messages() {
+fn outbox_status_reports_unacked_values_per_peer() {
     let mut node = make_node();
     let (tx, rx) = channel();
-
-    node.retry_outbox
-        .entry("n2".to_string())
-        .or_default()
-        .insert(1);
-    node.retry_outbox
-        .entry("n3".to_string())
-        .or_default()
-        .insert(2);
-
-    node.retry_messages(tx).unwrap();
-
-    let sent = drain(&rx);
-    assert_eq!(sent.len(), 2, "retry should resend all 2 outbox messages");
-
-    let dests: std::collections::HashSet<_> = sent.iter().map(|m| m.dest.as_str()).collect();
-    assert!(dests.contains("n2"));
-    assert!(dests.contains("n3"));
-    for message in sent {
-        assert!(matches!(message.body, MessageBody::gossip { .. }));
+    for value in [10, 11, 12] {
+        node.retry_outbox
+            .entry("n3".into())
+            .or_default()
+            .insert(value);
     }
+    node.retry_outbox.entry("n2".into()).or_default().insert(10);
+    let batch = node.track_gossip_batch("n2".into(), [10].into_iter().collect());
+    let ack = MessageBody::gossip_ok {
+        in_reply_to: Some(batch),
+    };
+    node.next(msg("n2", "n1", ack), tx.clone()).unwrap();
+
+    node.next(
+        msg("c1", "n1", MessageBody::outbox_status { msg_id: 4 }),
+        tx,
+    )
+    .unwrap();
+    let replies = drain(&rx);
+    let MessageBody::outbox_status_ok {
+        in_reply_to,
+        ref depths,
+    } = replies[0].body
+    else {
+        panic!("expected outbox_status_ok, got {:?}", replies[0].body);
+    };
+    assert_eq!(in_reply_to, 4);
+    assert_eq!(depths.get("n3"), Some(&3), "n3 never acked anything");
+    assert_eq!(depths.get("n2").copied().unwrap_or(0), 0);
 }
 
 #[test]
-fn retry_skips_peer_with_in_flight_gossip() {
+fn gossip_ok_from_another_peer_acks_nothing() {
     let mut node = make_node();
-    let (tx, rx) = channel();
-
-    node.retry_outbox
-        .entry("n2".to_string())
-        .or_default()
-        .insert(1);
-    node.retry_outbox
-        .entry("n3".to_string())
-        .or_default()
-        .insert(2);
-    node.in_flight_gossip
-        .insert(100, ("n2".to_string(), [1].into_iter().collect()));
+    let (tx, _rx) = channel();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 10)
+        .unwrap();
+    let msg_id = node.track_gossip_batch("n2".into(), [10].into_iter().collect());
 
-    node.retry_messages(tx).unwrap();
+    node.handle_gossip_ok_message(
+        msg(
+            "n3",
+            "n1",
+            MessageBody::gossip_ok {
+                in_reply_to: Some(msg_id),
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    assert!(node.retry_outbox["n2"].contains(&10));
+    assert_eq!(node.in_flight_gossip.len(), 1);
+}
+
+#[test]
+fn duplicate_and_unknown_gossip_ok_are_no_ops() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 10)
+        .unwrap();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 11)
+        .unwrap();
+    let first = node.track_gossip_batch("n2".into(), [10].into_iter().collect());
+    node.track_gossip_batch("n2".into(), [11].into_iter().collect());
+    let ack = |src: &str, in_reply_to| {
+        msg(
+            src,
+            "n1",
+            MessageBody::gossip_ok {
+                in_reply_to: Some(in_reply_to),
+            },
+        )
+    };
+
+    node.dispatch(ack("n2", first), tx.clone()).unwrap();
+    node.dispatch(ack("n2", first), tx.clone()).unwrap();
+    assert_eq!(node.retry_outbox["n2"], [11].into_iter().collect());
+    assert_eq!(
+        node.in_flight_gossip.len(),
+        1,
+        "the other batch stays in flight"
+    );
+
+    // a peer we never gossiped to
+    node.dispatch(ack("n3", first), tx).unwrap();
+    assert!(!node.retry_outbox.contains_key("n3"));
+    assert_eq!(node.unacked(), 1);
+    assert!(drain(&rx).is_empty());
+}
+
+// Replies from peers that leave out in_reply_to are logged, not rejected
+#[test]
+fn replies_without_in_reply_to_are_tolerated() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 10)
+        .unwrap();
+    node.track_gossip_batch("n2".into(), [10].into_iter().collect());
+
+    for line in [
+        r#"{"src":"n2","dest":"n1","body":{"type":"gossip_ok"}}"#,
+        r#"{"src":"n2","dest":"n1","body":{"type":"broadcast_ok","msg_id":2}}"#,
+        r#"{"src":"n2","dest":"n1","body":{"type":"sync_ok","msg_id":3,"messages":[7]}}"#,
+    ] {
+        let message = decode_line(line, usize::MAX).unwrap();
+        node.dispatch(message, tx.clone()).unwrap();
+    }
+
+    assert_eq!(node.retry_outbox["n2"], [10].into_iter().collect());
+    assert_eq!(node.unacked(), 1, "nothing to ack without an id");
+    assert!(node.store.contains(&7), "sync_ok values are merged anyway");
+    assert!(drain(&rx).is_empty());
+}
+
+// Peers are sent gossip batches under fresh msg_ids, never the client's
+// broadcast, and the gossip_ok echoing that id is what clears the outbox
+#[test]
+fn neighbour_ack_clears_its_outbox_entry() {
+    let mut node = make_node();
+    node.topology.insert("n1".into(), vec!["n2".into()]);
+    let mut peer = make_node();
+    peer.id = "n2".into();
+    let (tx, rx) = channel();
+
+    node.handle_broadcast_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 8,
+                msg_id: 1,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    node.fanout_messages(tx.clone()).unwrap();
+    let gossip = drain(&rx)
+        .into_iter()
+        .find(|m| m.dest == "n2")
+        .expect("value fanned out to n2");
+    let gossip_id = gossip.body.msg_id().unwrap();
+    assert_ne!(gossip_id, 1, "the client's msg_id isn't reused");
+    assert!(node.retry_outbox["n2"].contains(&8));
+
+    // a stray broadcast_ok doesn't count as an ack for the batch
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::broadcast_ok {
+                in_reply_to: Some(gossip_id),
+                msg_id: 2,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    assert!(node.retry_outbox["n2"].contains(&8));
+
+    peer.dispatch(gossip, tx.clone()).unwrap();
+    let ack = drain(&rx).into_iter().find(|m| m.dest == "n1").unwrap();
+    assert!(
+        matches!(ack.body, MessageBody::gossip_ok { in_reply_to } if in_reply_to == Some(gossip_id))
+    );
+    node.dispatch(ack, tx).unwrap();
+
+    assert!(!node.retry_outbox.contains_key("n2"));
+    assert!(node.in_flight_gossip.is_empty());
+}
+
+// Two clients both number their first broadcast 1
+#[test]
+fn broadcasts_sharing_a_msg_id_are_acked_separately() {
+    let mut node = make_node();
+    node.topology.insert("n1".into(), vec!["n2".into()]);
+    let (tx, rx) = channel();
+
+    let mut gossip_ids = Vec::new();
+    for (client, value) in [("c1", 10), ("c2", 20)] {
+        node.handle_broadcast_message(
+            msg(
+                client,
+                "n1",
+                MessageBody::broadcast {
+                    message: value,
+                    msg_id: 1,
+                },
+            ),
+            tx.clone(),
+        )
+        .unwrap();
+        node.fanout_messages(tx.clone()).unwrap();
+        let gossip = drain(&rx).into_iter().find(|m| m.dest == "n2").unwrap();
+        gossip_ids.push(gossip.body.msg_id().unwrap());
+    }
+    assert_ne!(gossip_ids[0], gossip_ids[1]);
+
+    node.handle_gossip_ok_message(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::gossip_ok {
+                in_reply_to: Some(gossip_ids[1]),
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+    assert_eq!(
+        node.retry_outbox["n2"],
+        [10].into_iter().collect(),
+        "only the acked batch is cleared"
+    );
+}
+
+// ── Retry ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn retry_resends_all_outbox_messages() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+
+    node.retry_outbox.entry("n2".into()).or_default().insert(1);
+    node.retry_outbox.entry("n3".into()).or_default().insert(2);
+
+    node.retry_messages(tx).unwrap();
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 2, "retry should resend all 2 outbox messages");
+
+    let dests: std::collections::HashSet<_> = sent.iter().map(|m| m.dest.as_str()).collect();
+    assert!(dests.contains("n2"));
+    assert!(dests.contains("n3"));
+    for message in sent {
+        assert!(matches!(message.body, MessageBody::gossip { .. }));
+    }
+}
+
+#[test]
+fn retry_skips_peer_with_in_flight_gossip() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+
+    node.retry_outbox.entry("n2".into()).or_default().insert(1);
+    node.retry_outbox.entry("n3".into()).or_default().insert(2);
+    node.track_gossip_batch("n2".into(), [1].into_iter().collect());
+
+    node.retry_messages(tx).unwrap();
 
     let sent = drain(&rx);
     assert_eq!(sent.len(), 1);
@@ -569,6 +1942,125 @@ fn retry_skips_peer_with_in_flight_gossip() {
     assert!(matches!(sent[0].body, MessageBody::gossip { .. }));
 }
 
+#[test]
+fn lost_batch_is_retried_once_its_deadline_passes() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    node.add_to_outbox(OutboxKind::RetryMsg, &node_id("n2"), 1)
+        .unwrap();
+    node.track_gossip_batch("n2".into(), [1].into_iter().collect());
+
+    node.retry_messages(tx.clone()).unwrap();
+    assert!(drain(&rx).is_empty(), "still waiting on the ack");
+
+    clock.advance(node.config.retry_interval);
+    node.retry_messages(tx).unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "n2");
+    assert_eq!(node.in_flight_gossip.len(), 1, "only the resent batch");
+}
+
+#[test]
+fn retry_jitter_is_bounded_and_reproducible_from_seed() {
+    let config = Config {
+        retry_jitter: Duration::from_millis(100),
+        seed: Some(7),
+        ..Config::default()
+    };
+    let clock = MockClock::new();
+    let offsets = |config: Config| {
+        let mut node = make_node()
+            .with_clock(Arc::new(clock.clone()))
+            .with_config(config);
+        let start = node.clock.now();
+        let mut offsets: Vec<Duration> = (0..20)
+            .map(|i| {
+                let peer = NodeId::new(format!("n{}", i + 2));
+                let id = node.track_gossip_batch(peer.clone(), [1].into_iter().collect());
+                node.in_flight_gossip[&(peer, id)].retry_at - start
+            })
+            .collect();
+        offsets.dedup();
+        offsets
+    };
+
+    let first = offsets(config.clone());
+    assert_eq!(first, offsets(config.clone()), "same seed, same jitter");
+    assert!(first.len() > 1, "batches don't share a deadline");
+    let interval = config.retry_interval;
+    assert!(first
+        .iter()
+        .all(|d| *d >= interval && *d <= interval + config.retry_jitter));
+}
+
+#[test]
+fn sync_peers_and_samples_are_reproducible_from_seed() {
+    let draws = |sync_peer_weights| {
+        let mut node = make_node().with_config(Config {
+            sync_peers: 2,
+            sync_rounds: 2,
+            sync_payload: SyncPayloadStrategy::Sample(3),
+            sync_peer_weights,
+            seed: Some(7),
+            ..Config::default()
+        });
+        node.node_ids = (1..=10).map(|i| format!("n{i}").as_str().into()).collect();
+        node.store.extend(0..50);
+        (0..5)
+            .flat_map(|_| node.request_sync_with_random_peers())
+            .map(|m| (m.dest, sync_values(&m.body)))
+            .collect::<Vec<_>>()
+    };
+    for weights in [SyncPeerWeights::Uniform, SyncPeerWeights::Newness] {
+        assert_eq!(draws(weights), draws(weights), "same seed, same draws");
+    }
+}
+
+#[test]
+fn retry_budget_spreads_backlog_round_robin_over_passes() {
+    let mut node = make_node().with_config(Config {
+        retry_budget: NonZeroUsize::new(1),
+        ..Config::default()
+    });
+    let (tx, rx) = channel();
+    node.retry_outbox
+        .insert("n2".into(), [1, 2, 3, 4].into_iter().collect());
+    node.retry_outbox
+        .insert("n3".into(), [5].into_iter().collect());
+
+    // every pass starts with the next peer, the batches themselves are lost
+    let pass = |node: &mut Node<u32>| {
+        node.in_flight_gossip.clear();
+        node.retry_messages(tx.clone()).unwrap();
+        let mut sent: Vec<(NodeId, Vec<u32>)> = drain(&rx)
+            .into_iter()
+            .map(|m| match m.body {
+                MessageBody::gossip { mut messages, .. } => {
+                    messages.sort();
+                    (m.dest, messages)
+                }
+                other => panic!("expected gossip, got {:?}", other),
+            })
+            .collect();
+        sent.sort();
+        sent
+    };
+    assert_eq!(pass(&mut node), vec![("n2".into(), vec![1])]);
+    assert_eq!(pass(&mut node), vec![("n3".into(), vec![5])]);
+
+    // a bigger budget is dealt one value per peer in turn
+    node.config.retry_budget = NonZeroUsize::new(3);
+    assert_eq!(
+        pass(&mut node),
+        vec![("n2".into(), vec![1, 2]), ("n3".into(), vec![5])]
+    );
+    assert_eq!(node.unacked(), 5, "nothing leaves the outbox unacked");
+
+    assert!(Config::from_vars(vars(&[("DIST_RETRY_BUDGET", "0")])).is_err());
+}
+
 // ── Request sync with random peers ───────────────────────────────────────────
 
 #[test]
@@ -585,13 +2077,3062 @@ fn request_sync_creates_sync_messages_from_this_node() {
 
     for m in &messages {
         assert_eq!(m.src, "n1", "sync message src must be this node");
-        match &m.body {
-            MessageBody::sync { messages, .. } => {
-                let mut vals = messages.clone();
-                vals.sort();
-                assert_eq!(vals, vec![10u32, 20u32], "sync should carry current store");
-            }
-            other => panic!("expected sync body, got {:?}", other),
+        assert_eq!(
+            sync_values(&m.body),
+            vec![10u32, 20u32],
+            "sync should carry current store"
+        );
+    }
+}
+
+#[test]
+fn request_sync_runs_configured_rounds_with_unique_msg_ids() {
+    let mut node = make_node().with_config(Config {
+        sync_rounds: 3,
+        ..Config::default()
+    });
+
+    let messages = node.request_sync_with_random_peers();
+
+    assert_eq!(messages.len(), 3 * 2);
+    let msg_ids: std::collections::HashSet<u32> =
+        messages.iter().filter_map(|m| m.body.msg_id()).collect();
+    assert_eq!(
+        msg_ids.len(),
+        messages.len(),
+        "msg_ids must not repeat across rounds"
+    );
+}
+
+#[test]
+fn sync_fanout_grows_with_log2_of_the_cluster() {
+    use crate::broadcast::sync_fanout;
+
+    let sizes = [1, 2, 3, 4, 5, 8, 9, 25, 100, 1000];
+    let unclamped: Vec<usize> = sizes.iter().map(|&n| sync_fanout(n, 0, 64)).collect();
+    assert_eq!(unclamped, vec![0, 1, 2, 2, 3, 3, 4, 5, 7, 10]);
+    let clamped: Vec<usize> = sizes.iter().map(|&n| sync_fanout(n, 2, 5)).collect();
+    assert_eq!(clamped, vec![2, 2, 2, 2, 3, 3, 4, 5, 5, 5]);
+    assert_eq!(sync_fanout(100, 3, 1), 3, "min wins when the bounds cross");
+
+    // the defaults keep the old fixed fanout of 2
+    let config = Config::default();
+    assert_eq!(sync_fanout(25, config.sync_peers, config.sync_peers_max), 2);
+
+    let mut node = make_node().with_config(Config {
+        sync_peers: 1,
+        sync_peers_max: 8,
+        ..Config::default()
+    });
+    node.node_ids = (1..=9).map(|i| format!("n{i}").into()).collect();
+    assert_eq!(node.request_sync_with_random_peers().len(), 4);
+}
+
+#[test]
+fn distance_weighted_sync_prefers_close_peers() {
+    let mut node = make_node().with_config(Config {
+        sync_peers: 1,
+        sync_peers_max: 1,
+        sync_peer_weights: SyncPeerWeights::Distance,
+        ..Config::default()
+    });
+    node.node_ids = ["n1", "n2", "n3", "n4"].map(NodeId::from).to_vec();
+    // a line, n2 is 1 hop away, n3 2 and n4 3
+    node.topology = [("n1", "n2"), ("n2", "n3"), ("n3", "n4")]
+        .into_iter()
+        .map(|(from, to)| (from.into(), vec![to.into()]))
+        .collect();
+
+    let rounds = 6000;
+    let mut counts: HashMap<NodeId, usize> = HashMap::new();
+    for _ in 0..rounds {
+        for sync in node.request_sync_with_random_peers() {
+            *counts.entry(sync.dest).or_default() += 1;
+        }
+    }
+    assert!(!counts.contains_key("n1"), "never ourselves");
+    // weights 1, 1/2 and 1/3
+    let total = 1.0 + 1.0 / 2.0 + 1.0 / 3.0;
+    for (id, weight) in [("n2", 1.0), ("n3", 1.0 / 2.0), ("n4", 1.0 / 3.0)] {
+        let seen = counts[id] as f64 / rounds as f64;
+        let expected = weight / total;
+        assert!(
+            (seen - expected).abs() < 0.03,
+            "{id} picked {seen:.3} of rounds, expected {expected:.3}"
+        );
+    }
+
+    assert_eq!(
+        Config::default().sync_peer_weights,
+        SyncPeerWeights::Uniform
+    );
+    let config = Config::from_vars(vars(&[("DIST_SYNC_PEER_WEIGHTS", "distance")])).unwrap();
+    assert_eq!(config.sync_peer_weights, SyncPeerWeights::Distance);
+    assert!(Config::from_vars(vars(&[("DIST_SYNC_PEER_WEIGHTS", "near")])).is_err());
+}
+
+#[test]
+fn newness_weighted_sync_prefers_peers_that_fed_us_new_values() {
+    let clock = MockClock::new();
+    let mut node = make_node()
+        .with_config(Config {
+            sync_peers: 1,
+            sync_peers_max: 1,
+            sync_peer_weights: SyncPeerWeights::Newness,
+            ..Config::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+    node.node_ids = ["n1", "n2", "n3", "n4"].map(NodeId::from).to_vec();
+    let (tx, _rx) = channel();
+    let gossip = MessageBody::gossip {
+        msg_id: 1,
+        messages: (0..30).collect(),
+        seq: None,
+        background: false,
+        signature: None,
+    };
+    node.next(msg("n2", "n1", gossip), tx.clone()).unwrap();
+    // values we already hold are no sign of being ahead
+    let stale = MessageBody::gossip {
+        msg_id: 1,
+        messages: (0..30).collect(),
+        seq: None,
+        background: false,
+        signature: None,
+    };
+    node.next(msg("n3", "n1", stale), tx).unwrap();
+
+    let share_of_n2 = |node: &mut Node<u32>| {
+        let rounds = 3000;
+        let picked = (0..rounds)
+            .flat_map(|_| node.request_sync_with_random_peers())
+            .filter(|sync| sync.dest == "n2")
+            .count();
+        picked as f64 / rounds as f64
+    };
+    // weights 31, 1 and 1
+    let share = share_of_n2(&mut node);
+    assert!(share > 0.85, "n2 picked {share:.3} of rounds");
+
+    // ten half-lives later the score is down to about 0.03
+    clock.advance(node.config.newness_half_life * 10);
+    let share = share_of_n2(&mut node);
+    assert!(share < 0.4, "n2 still picked {share:.3} of rounds");
+
+    let config = Config::from_vars(vars(&[
+        ("DIST_SYNC_PEER_WEIGHTS", "newness"),
+        ("DIST_NEWNESS_HALF_LIFE_MS", "750"),
+    ]))
+    .unwrap();
+    assert_eq!(config.sync_peer_weights, SyncPeerWeights::Newness);
+    assert_eq!(config.newness_half_life, Duration::from_millis(750));
+}
+
+#[test]
+fn sync_payload_follows_strategy() {
+    let payload = |strategy| {
+        let mut node = make_node().with_config(Config {
+            sync_payload: strategy,
+            ..Config::default()
+        });
+        for value in [5, 1, 9, 3] {
+            node.insert_if_absent(value, &node_id("c1"));
+        }
+        sync_values(&node.request_sync_with_random_peers().remove(0).body)
+    };
+
+    assert_eq!(payload(SyncPayloadStrategy::Full), vec![1, 3, 5, 9]);
+    assert_eq!(payload(SyncPayloadStrategy::Recent(2)), vec![3, 9]);
+    let sample = payload(SyncPayloadStrategy::Sample(3));
+    assert_eq!(sample.len(), 3);
+    assert!(sample.iter().all(|v| [1, 3, 5, 9].contains(v)));
+}
+
+#[test]
+fn sync_payload_parses_from_env() {
+    let parse = |value: &str| Config::from_vars(vars(&[("DIST_SYNC_PAYLOAD", value)]));
+    assert_eq!(
+        parse("full").unwrap().sync_payload,
+        SyncPayloadStrategy::Full
+    );
+    assert_eq!(
+        parse("recent:50").unwrap().sync_payload,
+        SyncPayloadStrategy::Recent(50)
+    );
+    assert_eq!(
+        parse("sample:8").unwrap().sync_payload,
+        SyncPayloadStrategy::Sample(8)
+    );
+    assert!(parse("recent").is_err());
+    assert!(parse("sample:x").is_err());
+}
+
+#[test]
+fn single_node_cluster_stores_acks_and_ticks_with_zero_peers() {
+    let clock = MockClock::new();
+    let mut node = Node::<u32>::new().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    node.next(init_msg("n1", &["n1"]), tx.clone()).unwrap();
+    drain(&rx);
+
+    let broadcast = MessageBody::broadcast {
+        message: 5,
+        msg_id: 2,
+    };
+    node.next(msg("c1", "n1", broadcast), tx.clone()).unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(
+        sent[0].body,
+        MessageBody::broadcast_ok {
+            in_reply_to: Some(2),
+            ..
         }
+    ));
+
+    // fanout, retry, heartbeat and sync are all due, none has anyone to talk to
+    clock.advance(Duration::from_millis(1100));
+    node.tick(tx.clone()).unwrap();
+    assert!(drain(&rx).is_empty());
+    assert!(node.request_sync_with_random_peers().is_empty());
+    assert!(node.outbox_depths().is_empty());
+
+    node.next(plain_read("c1", 3), tx).unwrap();
+    match &drain(&rx)[..] {
+        [reply] => assert!(matches!(
+            &reply.body,
+            MessageBody::read_ok { messages, in_reply_to: 3, .. } if *messages == vec![5]
+        )),
+        other => panic!("expected one read_ok, got {:?}", other),
+    }
+}
+
+// ── Rate limiting ────────────────────────────────────────────────────────────
+
+#[test]
+fn rate_limiter_refills_over_time() {
+    let mut limiter = RateLimiter::new(RateLimit {
+        rate: 10.0,
+        burst: 2,
+    });
+    let start = Instant::now();
+
+    assert!(limiter.try_acquire(&node_id("n2"), start));
+    assert!(limiter.try_acquire(&node_id("n2"), start));
+    assert!(
+        !limiter.try_acquire(&node_id("n2"), start),
+        "burst exhausted"
+    );
+    // Buckets are per destination
+    assert!(limiter.try_acquire(&node_id("n3"), start));
+
+    // 10 tokens/sec refills one token every 100ms
+    assert!(limiter.try_acquire(&node_id("n2"), start + Duration::from_millis(100)));
+    assert!(!limiter.try_acquire(&node_id("n2"), start + Duration::from_millis(100)));
+}
+
+#[test]
+fn fanout_over_limit_is_deferred_to_outbox() {
+    let mut node = make_node();
+    node.rate_limiter = RateLimiter::new(RateLimit {
+        rate: 0.0,
+        burst: 1,
+    });
+    let (tx, rx) = channel();
+
+    node.add_to_outbox(OutboxKind::FanoutMsg, &node_id("n2"), 1)
+        .unwrap();
+    node.fanout_messages(tx.clone()).unwrap();
+    assert_eq!(drain(&rx).len(), 1);
+
+    node.add_to_outbox(OutboxKind::FanoutMsg, &node_id("n2"), 2)
+        .unwrap();
+    node.fanout_messages(tx).unwrap();
+
+    assert!(drain(&rx).is_empty(), "second batch exceeds the bucket");
+    assert!(
+        node.msg_outbox
+            .get("n2")
+            .is_some_and(|messages| messages.contains(&2)),
+        "over-limit batch must stay queued, not be dropped"
+    );
+}
+
+#[test]
+fn retry_over_limit_keeps_retry_outbox() {
+    let mut node = make_node();
+    node.rate_limiter = RateLimiter::new(RateLimit {
+        rate: 0.0,
+        burst: 0,
+    });
+    let (tx, rx) = channel();
+
+    node.retry_outbox.entry("n2".into()).or_default().insert(1);
+    node.retry_messages(tx).unwrap();
+
+    assert!(drain(&rx).is_empty());
+    assert!(node.retry_outbox["n2"].contains(&1));
+    assert!(node.in_flight_gossip.is_empty());
+}
+
+// ── Outbound priority ────────────────────────────────────────────────────────
+
+#[test]
+fn ack_sent_after_gossip_batch_is_written_first() {
+    let mut node = make_node();
+    node.topology.insert("n1".into(), vec!["n2".into()]);
+    let (tx, rx) = channel();
+
+    for value in 0..100u32 {
+        let peer = NodeId::new(format!("n{}", value + 10));
+        node.node_ids.push(peer.clone());
+        node.retry_outbox.entry(peer).or_default().insert(value);
     }
+    node.retry_messages(tx.clone()).unwrap();
+    node.handle_broadcast_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 1000,
+                msg_id: 3,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    let mut queue = OutboundQueue::new();
+    queue.fill_from(&rx);
+    assert_eq!(queue.len(), 101);
+
+    let first = queue.pop().unwrap();
+    assert!(
+        matches!(
+            first.body,
+            MessageBody::broadcast_ok {
+                in_reply_to: Some(3),
+                ..
+            }
+        ),
+        "ack should jump the retry batch, got {:?}",
+        first.body
+    );
+    while let Some(message) = queue.pop() {
+        assert!(matches!(message.body, MessageBody::gossip { .. }));
+    }
+}
+
+#[test]
+fn fresh_gossip_is_written_before_retries() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.retry_outbox.entry("n3".into()).or_default().insert(1);
+    node.retry_messages(tx.clone()).unwrap();
+    node.msg_outbox.entry("n2".into()).or_default().insert(2);
+    node.fanout_messages(tx).unwrap();
+
+    let mut queue = OutboundQueue::new();
+    queue.fill_from(&rx);
+    let order: Vec<NodeId> = std::iter::from_fn(|| queue.pop()).map(|m| m.dest).collect();
+    assert_eq!(order, vec![NodeId::from("n2"), NodeId::from("n3")]);
+}
+
+#[test]
+fn outbound_queue_is_fifo_within_a_priority() {
+    let mut queue = OutboundQueue::new();
+    for in_reply_to in 0..3 {
+        let body = MessageBody::gossip_ok {
+            in_reply_to: Some(in_reply_to),
+        };
+        queue.push(msg("n1", "n2", body));
+    }
+    let order: Vec<Option<u32>> = std::iter::from_fn(|| queue.pop())
+        .map(|m| match m.body {
+            MessageBody::gossip_ok { in_reply_to } => in_reply_to,
+            other => panic!("unexpected body {:?}", other),
+        })
+        .collect();
+    assert_eq!(order, vec![Some(0), Some(1), Some(2)]);
+}
+
+// ── Clock / tick ─────────────────────────────────────────────────────────────
+
+#[test]
+fn tick_retries_only_after_retry_interval_elapses() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    node.retry_outbox.entry("n2".into()).or_default().insert(1);
+
+    clock.advance(Duration::from_millis(299));
+    node.tick(tx.clone()).unwrap();
+    assert!(
+        !drain(&rx)
+            .iter()
+            .any(|m| matches!(m.body, MessageBody::gossip { .. })),
+        "retry interval has not elapsed yet"
+    );
+
+    clock.advance(Duration::from_millis(1));
+    node.tick(tx).unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "n2");
+    assert!(matches!(sent[0].body, MessageBody::gossip { .. }));
+}
+
+#[test]
+fn tick_requests_sync_on_sync_interval() {
+    let clock = MockClock::new();
+    let mut node = make_node()
+        .with_config(Config {
+            sync_interval: Some(Duration::from_secs(1)),
+            ..Config::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+
+    clock.advance(Duration::from_secs(1));
+    node.tick(tx).unwrap();
+
+    let sent = drain(&rx);
+    assert!(!sent.is_empty());
+    assert!(sent
+        .iter()
+        .all(|m| matches!(m.body, MessageBody::sync { .. })));
+}
+
+#[test]
+fn no_periodic_sync_without_a_sync_interval() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    for _ in 0..10 {
+        clock.advance(Duration::from_secs(1));
+        node.tick(tx.clone()).unwrap();
+    }
+    assert!(!drain(&rx)
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::sync { .. })));
+}
+
+#[test]
+fn startup_grace_holds_back_syncs_and_retries_after_init() {
+    let clock = MockClock::new();
+    let mut node = Node::<u32>::new()
+        .with_config(Config {
+            startup_grace: Duration::from_secs(3),
+            sync_interval: Some(Duration::from_secs(1)),
+            ..Config::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    node.next(init_msg("n1", &["n1", "n2", "n3"]), tx.clone())
+        .unwrap();
+    node.retry_outbox.entry("n2".into()).or_default().insert(1);
+    drain(&rx);
+
+    let held_back = |m: &Message| {
+        matches!(
+            m.body,
+            MessageBody::sync { .. } | MessageBody::gossip { .. }
+        )
+    };
+    for _ in 0..29 {
+        clock.advance(Duration::from_millis(100));
+        node.tick(tx.clone()).unwrap();
+        assert!(!drain(&rx).iter().any(held_back), "still settling");
+    }
+
+    clock.advance(Duration::from_millis(100));
+    node.tick(tx).unwrap();
+    let sent = drain(&rx);
+    assert!(sent
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::sync { .. })));
+    assert!(sent
+        .iter()
+        .any(|m| m.dest == "n2" && matches!(m.body, MessageBody::gossip { .. })));
+}
+
+#[test]
+fn disabled_anti_entropy_neither_syncs_nor_answers_syncs() {
+    let clock = MockClock::new();
+    let mut node = make_node()
+        .with_config(Config {
+            anti_entropy: false,
+            sync_interval: Some(Duration::from_secs(1)),
+            ..Config::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+
+    clock.advance(Duration::from_secs(1));
+    node.tick(tx.clone()).unwrap();
+    assert!(!drain(&rx)
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::sync { .. })));
+
+    let sync = node.request_sync_with_random_peers().remove(0);
+    let peer_sync = Message {
+        src: "n2".into(),
+        dest: "n1".into(),
+        body: sync.body,
+    };
+    assert!(node.dispatch(peer_sync, tx).is_err());
+    assert!(matches!(
+        drain(&rx)[0].body,
+        MessageBody::error { code: 10, .. }
+    ));
+}
+
+// ── Config ───────────────────────────────────────────────────────────────────
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn config_defaults_when_no_vars_set() {
+    let config = Config::from_vars(vars(&[("PATH", "/usr/bin")])).unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.fanout, 2);
+    assert_eq!(config.retry_interval, Duration::from_millis(300));
+}
+
+#[test]
+fn config_reads_dist_vars() {
+    let config = Config::from_vars(vars(&[
+        ("DIST_FANOUT", "3"),
+        ("DIST_SYNC_ROUNDS", "2"),
+        ("DIST_SYNC_INTERVAL_MS", "250"),
+        ("DIST_STARTUP_GRACE_MS", "1500"),
+        ("DIST_CONSISTENCY", "read-repair"),
+        ("DIST_RATE_LIMIT", "100.5"),
+        ("DIST_RATE_BURST", "10"),
+        ("DIST_SLOW_HANDLER_MS", "40"),
+        ("DIST_TRANSPORT", "tcp:127.0.0.1:7000"),
+        ("DIST_UDP_PEERS", "n2=127.0.0.1:7002, n3=127.0.0.1:7003"),
+        ("DIST_PEER_CODEC", "json"),
+        ("DIST_SOMETHING_NEW", "ignored"),
+    ]))
+    .unwrap();
+
+    assert_eq!(config.fanout, 3);
+    assert_eq!(config.sync_rounds, 2);
+    assert_eq!(config.sync_interval, Some(Duration::from_millis(250)));
+    assert_eq!(config.startup_grace, Duration::from_millis(1500));
+    assert_eq!(config.consistency, Consistency::ReadRepair);
+    assert_eq!(
+        config.rate_limit,
+        Some(RateLimit {
+            rate: 100.5,
+            burst: 10
+        })
+    );
+    assert_eq!(
+        config.slow_handler_threshold,
+        Some(Duration::from_millis(40))
+    );
+    assert_eq!(
+        config.transport,
+        Transport::Tcp("127.0.0.1:7000".parse().unwrap())
+    );
+    assert_eq!(
+        "udp:127.0.0.1:7001".parse::<Transport>().unwrap(),
+        Transport::Udp("127.0.0.1:7001".parse().unwrap())
+    );
+    assert_eq!(config.udp_peers.len(), 2);
+    assert_eq!(config.udp_peers["n3"], "127.0.0.1:7003".parse().unwrap());
+    assert_eq!(config.peer_codec, PeerCodec::Json);
+
+    let node = Node::<u32>::new().with_config(config);
+    assert_eq!(
+        node.schedule.sync_interval,
+        Some(Duration::from_millis(250))
+    );
+}
+
+#[test]
+fn config_rejects_bad_values() {
+    assert!(Config::from_vars(vars(&[("DIST_FANOUT", "two")])).is_err());
+    assert!(Config::from_vars(vars(&[("DIST_CONSISTENCY", "strong")])).is_err());
+    assert!(Config::from_vars(vars(&[("DIST_TRANSPORT", "tcp:7000")])).is_err());
+    assert!(Config::from_vars(vars(&[("DIST_UDP_PEERS", "n2:127.0.0.1:7002")])).is_err());
+    assert!(Config::from_vars(vars(&[("DIST_PEER_CODEC", "cbor")])).is_err());
+    assert!(
+        Config::from_vars(vars(&[("DIST_RATE_LIMIT", "5")])).is_err(),
+        "rate without burst is ambiguous"
+    );
+    for rate in ["0", "-1", "inf", "NaN"] {
+        assert!(
+            Config::from_vars(vars(&[("DIST_RATE_LIMIT", rate), ("DIST_RATE_BURST", "5")]))
+                .is_err(),
+            "rate {rate}"
+        );
+    }
+    assert!(
+        Config::from_vars(vars(&[("DIST_RATE_LIMIT", "5"), ("DIST_RATE_BURST", "0")])).is_err()
+    );
+}
+
+#[test]
+fn broadcast_fanout_follows_config() {
+    let mut node = make_node().with_config(Config {
+        fanout: 1,
+        ..Config::default()
+    });
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    let (tx, _rx) = channel();
+
+    node.handle_broadcast_message(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 5,
+                msg_id: 1,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    assert_eq!(node.msg_outbox.len(), 1);
+}
+
+#[test]
+fn random_fanout_peers_never_repeat_a_neighbour() {
+    for seed in 0..20 {
+        let mut node = make_node().with_config(Config {
+            fanout_random_peers: 1,
+            seed: Some(seed),
+            ..Config::default()
+        });
+        node.node_ids = ["n1", "n2", "n3", "n4", "n5"].map(NodeId::from).to_vec();
+        node.topology
+            .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+        let (tx, rx) = channel();
+
+        let broadcast = MessageBody::broadcast {
+            message: 5,
+            msg_id: 1,
+        };
+        node.handle_broadcast_message(msg("c1", "n1", broadcast), tx.clone())
+            .unwrap();
+        node.fanout_messages(tx).unwrap();
+
+        let mut dests: Vec<NodeId> = drain(&rx)
+            .into_iter()
+            .filter(|m| matches!(m.body, MessageBody::gossip { .. }))
+            .map(|m| m.dest)
+            .collect();
+        dests.sort();
+        assert_eq!(dests.len(), 3, "seed {seed}: {dests:?}");
+        assert_eq!(dests[..2], ["n2", "n3"], "both neighbours, once each");
+        assert!(dests[2] == "n4" || dests[2] == "n5");
+    }
+}
+
+// ── Panic boundary ───────────────────────────────────────────────────────────
+
+// Blows up on conversion of 13, standing in for a buggy handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Fragile(u32);
+
+impl From<u32> for Fragile {
+    fn from(value: u32) -> Self {
+        assert_ne!(value, 13, "unlucky value");
+        Fragile(value)
+    }
+}
+
+impl From<Fragile> for u32 {
+    fn from(value: Fragile) -> Self {
+        value.0
+    }
+}
+
+#[test]
+fn dispatch_survives_a_panicking_handler() {
+    let mut node = Node::<Fragile>::new();
+    node.id = "n1".into();
+    let (tx, rx) = channel();
+    let broadcast = |message| {
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message,
+                msg_id: message,
+            },
+        )
+    };
+
+    let err = node.dispatch(broadcast(13), tx.clone()).unwrap_err();
+    assert!(err.to_string().contains("unlucky value"), "{err}");
+
+    node.dispatch(broadcast(14), tx).unwrap();
+    assert!(node.store.contains(&Fragile(14)));
+    assert!(drain(&rx).iter().any(|m| matches!(
+        m.body,
+        MessageBody::broadcast_ok {
+            in_reply_to: Some(14),
+            ..
+        }
+    )));
+}
+
+#[test]
+fn unexpected_reply_is_counted_not_a_panic() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let result = node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::echo_ok {
+                msg_id: 1,
+                in_reply_to: 1,
+                echo: "hi".to_string(),
+            },
+        ),
+        tx,
+    );
+    assert!(result.is_ok());
+    assert!(drain(&rx).is_empty(), "acks are never answered");
+    assert_eq!(node.acks_received().get("echo_ok"), Some(&1));
+}
+
+#[test]
+fn full_counters_saturate_instead_of_panicking() {
+    let mut node = make_node();
+    node.stats.broadcasts = u64::MAX;
+    let (tx, _rx) = channel();
+    let broadcast = msg(
+        "c1",
+        "n1",
+        MessageBody::broadcast {
+            message: 7,
+            msg_id: 1,
+        },
+    );
+    node.dispatch(broadcast, tx).unwrap();
+    assert_eq!(node.stats().broadcasts, u64::MAX);
+}
+
+// ── Multi-node host ──────────────────────────────────────────────────────────
+
+fn init_msg(node_id: &str, node_ids: &[&str]) -> Message {
+    msg(
+        "c0",
+        node_id,
+        MessageBody::init {
+            msg_id: 1,
+            node_id: node_id.into(),
+            node_ids: node_ids.iter().map(|id| NodeId::from(*id)).collect(),
+        },
+    )
+}
+
+#[test]
+fn host_routes_messages_by_dest() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, rx) = channel();
+    let ids = ["n1", "n2"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    assert!(host.contains("n1") && host.contains("n2"));
+    drain(&rx);
+
+    host.route(
+        msg(
+            "c1",
+            "n2",
+            MessageBody::broadcast {
+                message: 8,
+                msg_id: 1,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    assert!(host.node("n2").unwrap().store.contains(&8));
+    assert!(host.node("n1").unwrap().store.is_empty());
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].src, "n2");
+}
+
+#[test]
+fn host_rejects_unknown_dest() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, _rx) = channel();
+    let result = host.route(
+        msg(
+            "c1",
+            "n9",
+            MessageBody::read {
+                msg_id: 1,
+                quorum: false,
+                offset: None,
+                limit: None,
+                from: None,
+            },
+        ),
+        tx,
+    );
+    assert!(result.is_err());
+    assert!(!host.contains("n9"), "only init creates nodes");
+}
+
+#[test]
+fn host_delivers_between_local_nodes_in_process() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, rx) = channel();
+    let ids = ["n1", "n2"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    host.node_mut("n1")
+        .unwrap()
+        .topology
+        .insert("n1".into(), vec!["n2".into()]);
+    drain(&rx);
+
+    host.route(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 4,
+                msg_id: 1,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    host.flush(tx).unwrap();
+
+    assert!(host.node("n2").unwrap().store.contains(&4));
+    assert!(
+        host.node("n1").unwrap().in_flight_gossip.is_empty(),
+        "gossip_ok from n2 came back in-process"
+    );
+    let external = drain(&rx);
+    assert_eq!(external.len(), 1, "only the client ack leaves the host");
+    assert_eq!(external[0].dest, "c1");
+}
+
+#[test]
+fn host_shutdown_resends_and_counts_unacked_gossip() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, rx) = channel();
+    host.route(init_msg("n1", &["n1", "n2"]), tx.clone())
+        .unwrap();
+    host.node_mut("n1")
+        .unwrap()
+        .topology
+        .insert("n1".into(), vec!["n2".into()]);
+    host.route(
+        msg(
+            "c1",
+            "n1",
+            MessageBody::broadcast {
+                message: 4,
+                msg_id: 1,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+    drain(&rx);
+
+    // n2 lives in another process and never acks
+    assert_eq!(host.shutdown(tx).unwrap(), 1);
+    let sent = drain(&rx);
+    assert!(sent
+        .iter()
+        .any(|m| m.dest == "n2" && matches!(m.body, MessageBody::gossip { .. })));
+}
+
+#[test]
+fn host_partition_drops_cross_group_traffic_until_healed() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, _rx) = channel();
+    let ids = ["n1", "n2"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    host.node_mut("n1").unwrap().store.insert(1);
+    host.node_mut("n2").unwrap().store.insert(2);
+
+    host.partition(&[&["n1"], &["n2"]]);
+    host.sync_round(tx.clone()).unwrap();
+    assert!(!host.converged());
+    assert!(host.dropped() > 0);
+
+    host.heal();
+    while !host.converged() {
+        host.sync_round(tx.clone()).unwrap();
+    }
+    assert_eq!(host.node("n1").unwrap().store.len(), 2);
+}
+
+#[test]
+fn three_partitioned_groups_converge_to_the_union_after_heal() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, _rx) = channel();
+    let ids = ["n1", "n2", "n3", "n4", "n5", "n6"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    let groups: [&[&str]; 3] = [&["n1", "n2"], &["n3", "n4"], &["n5", "n6"]];
+    host.partition(&groups);
+
+    for (group, members) in groups.iter().enumerate() {
+        for i in 0..3 {
+            let value = group as u32 * 10 + i;
+            let body = MessageBody::broadcast {
+                message: value,
+                msg_id: value,
+            };
+            host.route(msg("c1", members[0], body), tx.clone()).unwrap();
+        }
+    }
+    for _ in 0..10 {
+        host.flush(tx.clone()).unwrap();
+        host.sync_round(tx.clone()).unwrap();
+    }
+    for (group, members) in groups.iter().enumerate() {
+        let own: Vec<u32> = (0..3).map(|i| group as u32 * 10 + i).collect();
+        for id in members.iter() {
+            let mut values = host.node(*id).unwrap().read();
+            values.sort();
+            assert_eq!(values, own, "{id} only holds its own group's values");
+        }
+    }
+
+    host.heal();
+    let mut rounds = 0;
+    while !host.converged() {
+        rounds += 1;
+        assert!(rounds <= 50, "no convergence after {rounds} sync rounds");
+        host.sync_round(tx.clone()).unwrap();
+    }
+    let union = vec![0, 1, 2, 10, 11, 12, 20, 21, 22];
+    for id in ids {
+        let mut values = host.node(id).unwrap().read();
+        values.sort();
+        assert_eq!(values, union, "{id}");
+    }
+}
+
+#[test]
+fn delayed_acks_trigger_retries_without_duplicating_values() {
+    let clock = MockClock::new();
+    let config = Config::default();
+    let mut host = Host::<u32>::new(config.clone()).with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    let ids = ["n1", "n2"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    host.node_mut("n1")
+        .unwrap()
+        .topology
+        .insert("n1".into(), vec!["n2".into()]);
+    host.set_latency("n2", "n1", config.retry_interval * 2);
+
+    let broadcast = MessageBody::broadcast {
+        message: 4,
+        msg_id: 1,
+    };
+    host.route(msg("c1", "n1", broadcast), tx.clone()).unwrap();
+    let step = config.fanout_interval;
+    // the retry pass at 2x retry_interval resends, the first ack is due a
+    // fanout interval later
+    let steps = (config.retry_interval * 2).as_millis() / step.as_millis();
+    for _ in 0..steps {
+        clock.advance(step);
+        host.tick(tx.clone()).unwrap();
+    }
+    assert_eq!(
+        host.in_transit(),
+        2,
+        "acks for the gossip and its retry are still on the way"
+    );
+    assert_eq!(host.node("n1").unwrap().unacked(), 1);
+
+    // the late acks name expired batches, the next retry is acked in time
+    host.set_latency("n2", "n1", Duration::ZERO);
+    for _ in 0..20 {
+        clock.advance(step);
+        host.tick(tx.clone()).unwrap();
+    }
+    assert_eq!(host.in_transit(), 0);
+    assert_eq!(host.node("n1").unwrap().unacked(), 0);
+    let n2 = host.node("n2").unwrap();
+    assert_eq!(n2.read(), vec![4], "three deliveries, one stored value");
+    assert!(host.delivered() >= 6, "gossip three times and its acks");
+    drain(&rx);
+}
+
+#[test]
+fn process_batch_collects_outputs_and_errors() {
+    let mut host = Host::<u32>::new(Config::default());
+    let ids = ["n1", "n2"];
+    let mut batch: Vec<Message> = ids.iter().map(|id| init_msg(id, &ids)).collect();
+    batch.push(msg(
+        "c1",
+        "n1",
+        MessageBody::broadcast {
+            message: 5,
+            msg_id: 1,
+        },
+    ));
+    batch.push(msg("c1", "n9", MessageBody::generate { msg_id: 2 }));
+    batch.push(msg(
+        "c1",
+        "n2",
+        MessageBody::read {
+            msg_id: 3,
+            quorum: false,
+            offset: None,
+            limit: None,
+            from: None,
+        },
+    ));
+
+    let result = host.process_batch(batch);
+
+    // two init_ok, broadcast_ok and read_ok, all addressed to clients
+    assert_eq!(result.sent.len(), 4);
+    assert!(result.sent.iter().all(|m| m.dest.is_client()));
+    assert_eq!(result.errors.len(), 1);
+    assert_eq!(result.errors[0].0, 3);
+}
+
+// stands in for a handler stalled on I/O, time only moves when it runs
+static STALL_CLOCK: std::sync::LazyLock<MockClock> = std::sync::LazyLock::new(MockClock::new);
+
+fn handle_stall(_node: &mut Node<u32>, _msg: Message, _tx: Sender<Message>) -> anyhow::Result<()> {
+    STALL_CLOCK.advance(Duration::from_millis(50));
+    Ok(())
+}
+
+#[test]
+fn host_counts_handlers_over_the_slow_threshold() {
+    let config = Config {
+        slow_handler_threshold: Some(Duration::from_millis(20)),
+        ..Config::default()
+    };
+    let mut host = Host::<u32>::new(config).with_clock(Arc::new(STALL_CLOCK.clone()));
+    host.register_custom("stall", handle_stall);
+    let (tx, _rx) = channel();
+    host.route(init_msg("n1", &["n1"]), tx.clone()).unwrap();
+    let echo = MessageBody::echo {
+        echo: "hi".into(),
+        msg_id: 2,
+    };
+    host.route(msg("c1", "n1", echo), tx.clone()).unwrap();
+    assert_eq!(host.slow_handlers(), 0);
+
+    let stall = MessageBody::custom {
+        type_name: "stall".into(),
+        payload: serde_json::json!({}),
+    };
+    host.route(msg("c1", "n1", stall), tx).unwrap();
+    assert_eq!(
+        host.slow_handlers(),
+        1,
+        "the handler still ran to completion"
+    );
+}
+
+// ── Serialization ────────────────────────────────────────────────────────────
+
+#[test]
+fn topology_deserializes_regardless_of_field_order() {
+    let orders = [
+        r#"{"type":"topology","topology":{"n1":["n2"]},"msg_id":4}"#,
+        r#"{"type":"topology","msg_id":4,"topology":{"n1":["n2"]}}"#,
+        r#"{"msg_id":4,"topology":{"n1":["n2"]},"type":"topology"}"#,
+        r#"{"topology":{"n1":["n2"]},"type":"topology","msg_id":4}"#,
+    ];
+    let expected = MessageBody::topology {
+        topology: [("n1".into(), vec!["n2".into()])].into_iter().collect(),
+        msg_id: 4,
+    };
+    for json in orders {
+        let body: MessageBody = serde_json::from_str(json).unwrap();
+        assert_eq!(body, expected, "failed for {json}");
+    }
+
+    let ok: MessageBody =
+        serde_json::from_str(r#"{"in_reply_to":4,"type":"topology_ok","msg_id":5}"#).unwrap();
+    assert_eq!(
+        ok,
+        MessageBody::topology_ok {
+            msg_id: 5,
+            in_reply_to: 4
+        }
+    );
+}
+
+// ── Tombstones ───────────────────────────────────────────────────────────────
+
+#[test]
+fn tombstone_is_collected_once_every_node_has_seen_it() {
+    let ids: Vec<NodeId> = ["n1", "n2", "n3"]
+        .iter()
+        .map(|s| NodeId::from(*s))
+        .collect();
+    let mut tombstones = Tombstones::new();
+    tombstones.record(5, &node_id("n1"));
+    tombstones.record(6, &node_id("n1"));
+    assert!(tombstones.collect_garbage(&ids).is_empty());
+    assert!(
+        tombstones.collect_garbage(&[]).is_empty(),
+        "no membership, nobody has acked"
+    );
+
+    let from_n2: AckMap = [(5, ["n2".into()].into_iter().collect())]
+        .into_iter()
+        .collect();
+    assert!(tombstones
+        .merge(&node_id("n2"), &node_id("n1"), &from_n2)
+        .is_empty());
+    assert!(
+        tombstones.collect_garbage(&ids).is_empty(),
+        "n3 hasn't seen it"
+    );
+
+    tombstones.merge(&node_id("n3"), &node_id("n1"), &AckMap::new());
+    let from_n3: AckMap = [(5, ["n3".into()].into_iter().collect())]
+        .into_iter()
+        .collect();
+    tombstones.merge(&node_id("n3"), &node_id("n1"), &from_n3);
+    assert_eq!(tombstones.collect_garbage(&ids), vec![5]);
+    assert!(!tombstones.is_empty(), "6 is still waiting on n2 and n3");
+
+    let both: AckMap = [(6, ["n2".into(), "n3".into()].into_iter().collect())]
+        .into_iter()
+        .collect();
+    tombstones.merge(&node_id("n2"), &node_id("n1"), &both);
+    assert_eq!(tombstones.collect_garbage(&ids), vec![6]);
+    assert!(tombstones.is_empty());
+}
+
+#[test]
+fn removal_spreads_through_sync_and_tombstones_are_pruned() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, _rx) = channel();
+    let ids = ["n1", "n2", "n3"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+        host.node_mut(id).unwrap().store.extend([5u32, 6]);
+    }
+
+    host.node_mut("n1").unwrap().remove(5);
+
+    for _ in 0..50 {
+        host.sync_round(tx.clone()).unwrap();
+    }
+    for id in ids {
+        let node = host.node(id).unwrap();
+        assert!(!node.store.contains(&5), "{id} still has the removed value");
+        assert!(node.store.contains(&6));
+    }
+    assert!(
+        ids.iter()
+            .any(|id| host.node(*id).unwrap().tombstones.is_empty()),
+        "at least one node must have collected the tombstone"
+    );
+}
+
+#[test]
+fn tombstoned_value_is_not_resurrected_by_sync() {
+    let mut node = make_node();
+    let (tx, _rx) = channel();
+    node.store.insert(5u32);
+    node.remove(5);
+
+    node.handle_sync_ok_message(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::sync_ok {
+                msg_id: 1,
+                in_reply_to: Some(0),
+                messages: vec![5],
+                tombstones: Default::default(),
+                seq: None,
+                more: false,
+                bitmap: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+    assert!(!node.store.contains(&5));
+}
+
+// ── Quorum read ──────────────────────────────────────────────────────────────
+
+fn quorum_read(msg_id: u32) -> Message {
+    msg(
+        "c1",
+        "n1",
+        MessageBody::read {
+            msg_id,
+            quorum: true,
+            offset: None,
+            limit: None,
+            from: None,
+        },
+    )
+}
+
+#[test]
+fn quorum_read_waits_for_majority_and_unions_stores() {
+    let mut node = make_node();
+    node.store.insert(1u32);
+    let (tx, rx) = channel();
+
+    node.handle_read_message(quorum_read(4), tx.clone())
+        .unwrap();
+    let peer_reads = drain(&rx);
+    assert_eq!(peer_reads.len(), 2, "asks every peer");
+    let rpc_id = match peer_reads[0].body {
+        MessageBody::read {
+            msg_id,
+            quorum: false,
+            offset: None,
+            limit: None,
+            from: None,
+        } => msg_id,
+        ref other => panic!("expected peer read, got {:?}", other),
+    };
+
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![2],
+                in_reply_to: rpc_id,
+                msg_id: 77,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx.clone(),
+    )
+    .unwrap();
+
+    // 2 of 3 nodes answered, that's a majority
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "c1");
+    match &sent[0].body {
+        MessageBody::read_ok {
+            messages,
+            in_reply_to,
+            ..
+        } => {
+            let mut got = messages.clone();
+            got.sort();
+            assert_eq!(got, vec![1, 2]);
+            assert_eq!(*in_reply_to, 4);
+        }
+        other => panic!("expected read_ok, got {:?}", other),
+    }
+    assert!(node.pending.is_empty());
+    assert!(node.store.contains(&2), "quorum result is kept locally");
+
+    // The slower peer's reply is ignored
+    node.dispatch(
+        msg(
+            "n3",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![3],
+                in_reply_to: rpc_id,
+                msg_id: 78,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+    assert!(drain(&rx).is_empty());
+}
+
+#[test]
+fn quorum_read_times_out_with_error() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+
+    node.handle_read_message(quorum_read(9), tx.clone())
+        .unwrap();
+    drain(&rx);
+
+    clock.advance(node.config.rpc_timeout);
+    node.tick(tx).unwrap();
+
+    let errors: Vec<_> = drain(&rx)
+        .into_iter()
+        .filter(|m| matches!(m.body, MessageBody::error { .. }))
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].dest, "c1");
+    assert!(matches!(
+        errors[0].body,
+        MessageBody::error {
+            in_reply_to: 9,
+            code: 0,
+            ..
+        }
+    ));
+    assert!(node.pending.is_empty());
+}
+
+// Peers that never answer a repair read mustn't leave it parked forever
+#[test]
+fn unanswered_repair_read_is_swept_at_the_configured_deadline() {
+    let clock = MockClock::new();
+    let mut node = make_node()
+        .with_config(Config {
+            consistency: Consistency::ReadRepair,
+            rpc_timeout: Duration::from_millis(50),
+            ..Config::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+
+    node.handle_read_message(plain_read("c1", 4), tx.clone())
+        .unwrap();
+    let rpc_id = drain(&rx)
+        .iter()
+        .find_map(|m| match m.body {
+            MessageBody::read { msg_id, .. } => Some(msg_id),
+            _ => None,
+        })
+        .unwrap();
+
+    clock.advance(Duration::from_millis(49));
+    node.tick(tx.clone()).unwrap();
+    assert_eq!(node.pending.len(), 1, "not due yet");
+
+    clock.advance(Duration::from_millis(1));
+    node.tick(tx.clone()).unwrap();
+    assert!(node.pending.is_empty());
+    assert!(
+        !drain(&rx)
+            .iter()
+            .any(|m| matches!(m.body, MessageBody::error { .. })),
+        "the client was answered already"
+    );
+
+    // a reply that turns up after the sweep is dropped
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![3],
+                in_reply_to: rpc_id,
+                msg_id: 1,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+    assert!(drain(&rx).is_empty());
+}
+
+#[test]
+fn quorum_read_across_hosted_cluster() {
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, rx) = channel();
+    let ids = ["n1", "n2", "n3"];
+    for (value, id) in ids.iter().enumerate() {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+        host.node_mut(*id).unwrap().store.insert(value as u32);
+    }
+    drain(&rx);
+
+    host.route(quorum_read(1), tx).unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    match &sent[0].body {
+        MessageBody::read_ok { messages, .. } => {
+            assert!(messages.contains(&0), "own value always included");
+            assert!(messages.len() >= 2, "at least one peer merged in");
+        }
+        other => panic!("expected read_ok, got {:?}", other),
+    }
+}
+
+#[test]
+fn read_repair_pushes_missing_values_to_lagging_peer() {
+    let mut node = make_node().with_config(Config {
+        read_repair: true,
+        ..Config::default()
+    });
+    node.store.extend([1u32, 2]);
+    let (tx, rx) = channel();
+
+    node.handle_read_message(quorum_read(4), tx.clone())
+        .unwrap();
+    let rpc_id = match drain(&rx)[0].body {
+        MessageBody::read { msg_id, .. } => msg_id,
+        ref other => panic!("expected peer read, got {:?}", other),
+    };
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![2, 3],
+                in_reply_to: rpc_id,
+                msg_id: 1,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    let sent = drain(&rx);
+    let repair = sent
+        .iter()
+        .find(|m| m.dest == "n2")
+        .expect("repair sent to n2");
+    match &repair.body {
+        MessageBody::gossip { messages, .. } => assert_eq!(messages, &vec![1]),
+        other => panic!("expected gossip repair, got {:?}", other),
+    }
+    assert!(sent.iter().any(|m| m.dest == "c1"));
+}
+
+#[test]
+fn read_repair_off_by_default() {
+    let mut node = make_node();
+    node.store.insert(1u32);
+    let (tx, rx) = channel();
+
+    node.handle_read_message(quorum_read(4), tx.clone())
+        .unwrap();
+    let rpc_id = match drain(&rx)[0].body {
+        MessageBody::read { msg_id, .. } => msg_id,
+        ref other => panic!("expected peer read, got {:?}", other),
+    };
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![],
+                in_reply_to: rpc_id,
+                msg_id: 1,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+
+    assert!(drain(&rx).iter().all(|m| m.dest == "c1"));
+}
+
+fn plain_read(src: &str, msg_id: u32) -> Message {
+    msg(
+        src,
+        "n1",
+        MessageBody::read {
+            msg_id,
+            quorum: false,
+            offset: None,
+            limit: None,
+            from: None,
+        },
+    )
+}
+
+#[test]
+fn quorum_consistency_turns_plain_client_reads_into_quorum_reads() {
+    let mut node = make_node().with_config(Config {
+        consistency: Consistency::Quorum,
+        ..Config::default()
+    });
+    let (tx, rx) = channel();
+
+    node.handle_read_message(plain_read("c1", 4), tx.clone())
+        .unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(|m| m.dest.is_node()));
+
+    // a peer's read is served locally, or quorum reads would cascade
+    node.handle_read_message(plain_read("n2", 5), tx).unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(
+        sent[0].body,
+        MessageBody::read_ok { in_reply_to: 5, .. }
+    ));
+}
+
+#[test]
+fn read_repair_consistency_answers_locally_then_repairs_peers() {
+    let mut node = make_node().with_config(Config {
+        consistency: Consistency::ReadRepair,
+        ..Config::default()
+    });
+    node.store.extend([1u32, 2]);
+    let (tx, rx) = channel();
+
+    node.handle_read_message(plain_read("c1", 4), tx.clone())
+        .unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 3, "client reply plus a read per peer");
+    assert!(matches!(
+        sent.iter().find(|m| m.dest == "c1").unwrap().body,
+        MessageBody::read_ok { in_reply_to: 4, .. }
+    ));
+    let rpc_id = sent
+        .iter()
+        .find_map(|m| match m.body {
+            MessageBody::read { msg_id, .. } => Some(msg_id),
+            _ => None,
+        })
+        .unwrap();
+
+    node.dispatch(
+        msg(
+            "n2",
+            "n1",
+            MessageBody::read_ok {
+                messages: vec![2, 3],
+                in_reply_to: rpc_id,
+                msg_id: 1,
+                next_offset: None,
+                signature: None,
+            },
+        ),
+        tx,
+    )
+    .unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "n2");
+    assert!(matches!(
+        sent[0].body,
+        MessageBody::gossip { ref messages, .. } if messages == &vec![1]
+    ));
+    assert!(node.store.contains(&3), "peer values are merged locally");
+}
+
+#[test]
+fn bounded_lines_skip_oversized_lines_without_buffering_them() {
+    let long = "x".repeat(1000);
+    let input = format!("short\n{long}\nafter\r\n{long}");
+    // a tiny BufReader capacity makes the skip go through many refills
+    let reader = std::io::BufReader::with_capacity(8, input.as_bytes());
+    let mut lines = BoundedLines::new(reader, 16);
+
+    assert_eq!(lines.next().unwrap().unwrap(), "short");
+    let err = lines.next().unwrap().unwrap_err();
+    let oversized = err.downcast_ref::<OversizedMessage>().unwrap();
+    assert_eq!((oversized.len, oversized.max), (1000, 16));
+    assert_eq!(lines.next().unwrap().unwrap(), "after");
+    // an unterminated last line is still measured in full
+    let err = lines.next().unwrap().unwrap_err();
+    assert_eq!(err.downcast_ref::<OversizedMessage>().unwrap().len, 1000);
+    assert!(lines.next().is_none());
+}
+
+#[test]
+fn decode_rejects_oversized_lines_before_parsing() {
+    let big: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let line = format!(
+        r#"{{"src":"n2","dest":"n1","body":{{"type":"sync","msg_id":1,"messages":[{}]}}}}"#,
+        big.join(",")
+    );
+
+    let err = decode_line(&line, 256).unwrap_err();
+    let oversized = err.downcast_ref::<OversizedMessage>().unwrap();
+    assert_eq!(oversized.len, line.len());
+    assert_eq!(oversized.max, 256);
+
+    let message = decode_line(&line, line.len()).unwrap();
+    assert!(
+        matches!(message.body, MessageBody::sync { ref messages, .. } if messages.len() == 1000)
+    );
+}
+
+#[test]
+fn read_from_a_peer_relays_its_store_without_merging() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    node.store.insert(1u32);
+    let (tx, rx) = channel();
+    let read_from = |msg_id, from: &str| {
+        let body = MessageBody::read {
+            msg_id,
+            quorum: false,
+            offset: None,
+            limit: Some(10),
+            from: Some(from.into()),
+        };
+        msg("c1", "n1", body)
+    };
+
+    node.dispatch(read_from(5, "n2"), tx.clone()).unwrap();
+    let forwarded = drain(&rx);
+    let rpc_id = match forwarded[..] {
+        [Message {
+            ref dest,
+            body:
+                MessageBody::read {
+                    msg_id,
+                    limit: Some(10),
+                    from: None,
+                    ..
+                },
+            ..
+        }] if dest == "n2" => msg_id,
+        ref other => panic!("expected one read to n2, got {:?}", other),
+    };
+
+    // only n2 can answer it
+    let reply = |src: &str| {
+        let body = MessageBody::read_ok {
+            messages: vec![2, 3],
+            in_reply_to: rpc_id,
+            msg_id: 40,
+            next_offset: None,
+            signature: None,
+        };
+        msg(src, "n1", body)
+    };
+    node.dispatch(reply("n3"), tx.clone()).unwrap();
+    assert!(drain(&rx).is_empty());
+    node.dispatch(reply("n2"), tx.clone()).unwrap();
+    let sent = drain(&rx);
+    assert!(matches!(
+        sent[..],
+        [Message { ref dest, body: MessageBody::read_ok { ref messages, in_reply_to: 5, .. }, .. }]
+            if dest == "c1" && *messages == vec![2, 3]
+    ));
+    assert_eq!(node.read(), vec![1], "their store isn't merged into ours");
+
+    // reading from ourselves is a local read, from a stranger an error
+    node.dispatch(read_from(6, "n1"), tx.clone()).unwrap();
+    assert!(matches!(
+        drain(&rx)[..],
+        [Message {
+            body: MessageBody::read_ok { in_reply_to: 6, .. },
+            ..
+        }]
+    ));
+    assert!(node.dispatch(read_from(7, "n9"), tx.clone()).is_err());
+    assert!(matches!(
+        drain(&rx)[..],
+        [Message {
+            body: MessageBody::error {
+                in_reply_to: 7,
+                code: 1,
+                ..
+            },
+            ..
+        }]
+    ));
+
+    // a peer that never answers times the client out
+    node.dispatch(read_from(8, "n3"), tx.clone()).unwrap();
+    drain(&rx);
+    clock.advance(Duration::from_secs(2));
+    node.tick(tx).unwrap();
+    assert!(drain(&rx).iter().any(|m| matches!(
+        m.body,
+        MessageBody::error {
+            in_reply_to: 8,
+            code: 0,
+            ..
+        }
+    )));
+}
+
+// ── Streaming sync_ok ────────────────────────────────────────────────────────
+
+#[test]
+fn stream_sync_ok_hands_values_to_callback_in_order() {
+    let line = r#"{"body":{"messages":[4,5,6],"type":"sync_ok","in_reply_to":2,"msg_id":9,"tombstones":{"5":["n3"]}},"src":"n2","dest":"n1"}"#;
+    let mut seen = Vec::new();
+
+    let streamed = stream_sync_ok(line, |m| seen.push(m)).unwrap();
+
+    assert_eq!(seen, vec![4, 5, 6]);
+    assert_eq!(streamed.values, 3);
+    assert_eq!(
+        (streamed.src.as_str(), streamed.dest.as_str()),
+        ("n2", "n1")
+    );
+    assert_eq!((streamed.msg_id, streamed.in_reply_to), (9, 2));
+    assert!(streamed.tombstones[&5].contains("n3"));
+    assert!(stream_sync_ok(
+        r#"{"src":"n2","dest":"n1","body":{"type":"sync","messages":[]}}"#,
+        |_| {}
+    )
+    .is_err());
+}
+
+#[test]
+fn host_streams_large_sync_ok_into_store() {
+    let config = Config {
+        stream_threshold_bytes: 200,
+        ..Config::default()
+    };
+    let mut host = Host::<u32>::new(config);
+    let (tx, rx) = channel();
+    host.route(init_msg("n1", &["n1", "n2"]), tx.clone())
+        .unwrap();
+    drain(&rx);
+
+    let values: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+    let line = format!(
+        r#"{{"src":"n2","dest":"n1","body":{{"type":"sync_ok","msg_id":1,"in_reply_to":1,"messages":[{}]}}}}"#,
+        values.join(",")
+    );
+    assert!(host.try_stream(&line, tx.clone()).unwrap());
+    assert_eq!(host.node("n1").unwrap().store.len(), 100);
+    assert_eq!(host.node("n1").unwrap().acks_received()["sync_ok"], 1);
+    assert!(drain(&rx).is_empty());
+
+    // a capped reply is followed up with another sync
+    let capped = line.replace(r#""messages":"#, r#""more":true,"messages":"#);
+    assert!(host.try_stream(&capped, tx.clone()).unwrap());
+    assert!(matches!(
+        &drain(&rx)[..],
+        [sync] if sync.dest == "n2" && matches!(sync.body, MessageBody::sync { .. })
+    ));
+
+    // small lines and other types take the normal path
+    let small = r#"{"src":"n2","dest":"n1","body":{"type":"sync_ok","msg_id":1,"in_reply_to":1,"messages":[]}}"#;
+    assert!(!host.try_stream(small, tx.clone()).unwrap());
+    let other = line.replace("sync_ok", "sync");
+    assert!(!host.try_stream(&other, tx).unwrap());
+}
+
+#[test]
+fn host_streams_large_read_ok_for_a_quorum_read() {
+    let config = Config {
+        stream_threshold_bytes: 200,
+        consistency: Consistency::Quorum,
+        ..Config::default()
+    };
+    let mut host = Host::<u32>::new(config);
+    let (tx, rx) = channel();
+    let read_ok = |src: &str, msg_id: u32| {
+        let values: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        format!(
+            r#"{{"src":"{src}","dest":"n1","body":{{"type":"read_ok","msg_id":1,"in_reply_to":{msg_id},"messages":[{}]}}}}"#,
+            values.join(",")
+        )
+    };
+    // nothing to stream into before init, next() answers that
+    assert!(!host.try_stream(&read_ok("n2", 1), tx.clone()).unwrap());
+    host.route(init_msg("n1", &["n1", "n2", "n3"]), tx.clone())
+        .unwrap();
+    drain(&rx);
+
+    host.route(plain_read("c1", 4), tx.clone()).unwrap();
+    let rpc_id = drain(&rx)
+        .iter()
+        .find_map(|m| match m.body {
+            MessageBody::read { msg_id, .. } => Some(msg_id),
+            _ => None,
+        })
+        .unwrap();
+    // a reply nobody is waiting on takes the normal path
+    assert!(!host
+        .try_stream(&read_ok("n2", rpc_id + 1), tx.clone())
+        .unwrap());
+
+    // our own store and n2 make the quorum, n3's answer comes in late
+    assert!(host.try_stream(&read_ok("n2", rpc_id), tx.clone()).unwrap());
+    assert!(!host.try_stream(&read_ok("n3", rpc_id), tx.clone()).unwrap());
+    let replies: Vec<usize> = drain(&rx)
+        .into_iter()
+        .filter_map(|m| match m.body {
+            MessageBody::read_ok { messages, .. } if m.dest == "c1" => Some(messages.len()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(replies, vec![100]);
+    let node = host.node("n1").unwrap();
+    assert_eq!(node.store.len(), 100);
+    assert_eq!(node.acks_received()["read_ok"], 1);
+}
+
+// ── Custom messages ──────────────────────────────────────────────────────────
+
+fn handle_hello(node: &mut Node<u32>, msg: Message, tx: Sender<Message>) -> anyhow::Result<()> {
+    if let MessageBody::custom { ref payload, .. } = msg.body {
+        let body = MessageBody::custom {
+            type_name: "hello_ok".to_string(),
+            payload: serde_json::json!({ "seen": payload["n"], "by": node.id }),
+        };
+        msg.into_reply(body).send(tx)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn unknown_type_deserializes_as_custom() {
+    let line = r#"{"src":"c1","dest":"n1","body":{"type":"hello","n":7,"msg_id":3}}"#;
+    let message: Message = serde_json::from_str(line).unwrap();
+    let MessageBody::custom {
+        ref type_name,
+        ref payload,
+    } = message.body
+    else {
+        panic!("expected custom, got {:?}", message.body);
+    };
+    assert_eq!(type_name, "hello");
+    assert_eq!(payload["n"], 7);
+
+    let round_trip: serde_json::Value = serde_json::to_value(&message.body).unwrap();
+    assert_eq!(
+        round_trip,
+        serde_json::json!({"type": "hello", "n": 7, "msg_id": 3})
+    );
+}
+
+#[test]
+fn custom_message_goes_to_registered_handler() {
+    let mut node = make_node();
+    let ping = msg(
+        "c1",
+        "n1",
+        MessageBody::custom {
+            type_name: "hello".to_string(),
+            payload: serde_json::json!({ "n": 4 }),
+        },
+    );
+    let (tx, rx) = channel();
+    assert!(node.next(ping.clone(), tx.clone()).is_err());
+
+    node.register_custom("hello", handle_hello);
+    node.next(ping, tx).unwrap();
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert!(matches!(
+        sent[0].body,
+        MessageBody::custom { ref type_name, ref payload }
+            if type_name == "hello_ok" && payload["seen"] == 4 && payload["by"] == "n1"
+    ));
+}
+
+// ── Signing ──────────────────────────────────────────────────────────────────
+
+#[cfg(feature = "signing")]
+fn signed_node(key: &str) -> Node<u32> {
+    let mut node = make_node().with_config(Config {
+        signing_key: Some(key.to_string()),
+        ..Config::default()
+    });
+    node.topology.insert("n1".into(), vec![]);
+    node
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn signed_gossip_is_verified_on_receipt() {
+    let mut sender = signed_node("secret");
+    sender.id = "n2".into();
+    sender
+        .add_to_outbox(OutboxKind::FanoutMsg, &node_id("n1"), 5)
+        .unwrap();
+    let (tx, rx) = channel();
+    sender.fanout_messages(tx.clone()).unwrap();
+    let gossip = drain(&rx).remove(0);
+    assert!(matches!(
+        gossip.body,
+        MessageBody::gossip {
+            signature: Some(_),
+            ..
+        }
+    ));
+
+    let mut forged = gossip.clone();
+    if let MessageBody::gossip {
+        ref mut messages, ..
+    } = forged.body
+    {
+        messages.push(6);
+    }
+    let mut receiver = signed_node("secret");
+    assert!(receiver.next(forged, tx.clone()).is_err());
+    assert!(drain(&rx).is_empty(), "forged gossip must not be acked");
+
+    let mut other_cluster = signed_node("other");
+    assert!(other_cluster.next(gossip.clone(), tx.clone()).is_err());
+
+    receiver.next(gossip, tx).unwrap();
+    assert!(receiver.store.contains(&5) && !receiver.store.contains(&6));
+    assert!(matches!(drain(&rx)[0].body, MessageBody::gossip_ok { .. }));
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn unsigned_gossip_is_dropped_when_a_key_is_set() {
+    let body = MessageBody::gossip {
+        msg_id: 1,
+        messages: vec![3],
+        signature: None,
+        seq: None,
+        background: false,
+    };
+    let (tx, _rx) = channel();
+    let mut open = make_node();
+    open.next(msg("n2", "n1", body.clone()), tx.clone())
+        .unwrap();
+    assert!(open.store.contains(&3));
+
+    let mut strict = signed_node("secret");
+    assert!(strict.next(msg("n2", "n1", body), tx).is_err());
+    assert!(strict.store.is_empty());
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn sync_exchange_is_signed_and_forgeries_are_dropped() {
+    let mut a = signed_node("secret");
+    a.node_ids = vec!["n1".into(), "n2".into()];
+    a.store.extend([1, 2]);
+    let mut b = signed_node("secret");
+    b.id = "n2".into();
+    b.node_ids = a.node_ids.clone();
+    b.store.insert(3);
+    let (tx, rx) = channel();
+
+    let sync = a
+        .request_sync_with_random_peers()
+        .into_iter()
+        .find(|m| m.dest == "n2")
+        .unwrap();
+    assert!(matches!(
+        sync.body,
+        MessageBody::sync {
+            signature: Some(_),
+            ..
+        }
+    ));
+    let mut forged = sync.clone();
+    if let MessageBody::sync {
+        ref mut messages, ..
+    } = forged.body
+    {
+        messages.push(9);
+    }
+    assert!(b.next(forged, tx.clone()).is_err());
+    assert!(drain(&rx).is_empty() && !b.store.contains(&9));
+
+    b.next(sync, tx.clone()).unwrap();
+    let reply = drain(&rx).remove(0);
+    let mut unsigned = reply.clone();
+    if let MessageBody::sync_ok {
+        ref mut signature, ..
+    } = unsigned.body
+    {
+        *signature = None;
+    }
+    assert!(a.next(unsigned, tx.clone()).is_err());
+    assert!(!a.store.contains(&3));
+    a.next(reply, tx).unwrap();
+    assert!(a.store.contains(&3) && b.store.contains(&1));
+}
+
+#[cfg(feature = "signing")]
+#[test]
+fn unsigned_repair_reply_and_peer_read_ok_are_dropped() {
+    let mut node = signed_node("secret");
+    let (tx, _rx) = channel();
+    let repair = MessageBody::repair_reply {
+        in_reply_to: 1,
+        values: vec![4],
+        unknown: vec![],
+        signature: None,
+    };
+    assert!(node.next(msg("n2", "n1", repair), tx.clone()).is_err());
+    let read_ok = MessageBody::read_ok {
+        messages: vec![5],
+        in_reply_to: 1,
+        msg_id: 2,
+        next_offset: None,
+        signature: None,
+    };
+    assert!(node.next(msg("n2", "n1", read_ok), tx).is_err());
+    assert!(node.store.is_empty());
+}
+
+// ── Self-test ────────────────────────────────────────────────────────────────
+
+#[test]
+fn selftest_passes_every_implemented_workload() {
+    for (workload, outcome) in crate::selftest::run_all() {
+        if let crate::selftest::Outcome::Fail(e) = outcome {
+            panic!("{workload} failed: {e:#}");
+        }
+    }
+}
+
+// ── Unsupported types ────────────────────────────────────────────────────────
+
+#[test]
+fn unsupported_type_gets_not_supported_error() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let line = r#"{"src":"c1","dest":"n1","body":{"type":"txn","msg_id":42,"txn":[]}}"#;
+    let request: Message = serde_json::from_str(line).unwrap();
+
+    assert!(node.next(request, tx.clone()).is_err());
+
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "c1");
+    match &sent[0].body {
+        MessageBody::error {
+            in_reply_to,
+            code,
+            text,
+        } => {
+            assert_eq!(*in_reply_to, 42);
+            assert_eq!(*code, 10);
+            assert!(text.contains("txn"));
+        }
+        other => panic!("expected error, got {:?}", other),
+    }
+
+    // errors themselves are never answered
+    let error = MessageBody::error {
+        in_reply_to: 1,
+        code: 10,
+        text: String::new(),
+    };
+    assert!(node.next(msg("n2", "n1", error), tx).is_err());
+    assert!(drain(&rx).is_empty());
+}
+
+#[test]
+fn rpc_error_survives_context_and_becomes_an_error_body() {
+    use anyhow::Context;
+
+    let failing = || -> anyhow::Result<()> {
+        Err(RpcError::malformed_request("no key")).context("handling cas")
+    };
+    let err = failing().unwrap_err();
+    let rpc = RpcError::find(&err).expect("context keeps the RpcError reachable");
+    assert_eq!(rpc.code, ErrorCode::MalformedRequest);
+    assert_eq!(rpc.to_string(), "no key (error 12)");
+    assert_eq!(
+        MessageBody::rpc_error(7, rpc),
+        MessageBody::error {
+            in_reply_to: 7,
+            code: 12,
+            text: "no key".into(),
+        }
+    );
+
+    assert!(RpcError::find(&anyhow::anyhow!("disk on fire")).is_none());
+}
+
+fn handle_reject(_node: &mut Node<u32>, msg: Message, _tx: Sender<Message>) -> anyhow::Result<()> {
+    match msg.body {
+        MessageBody::custom { ref payload, .. } if payload["rpc"] == true => {
+            Err(RpcError::new(ErrorCode::Abort, "rejected").into())
+        }
+        _ => anyhow::bail!("rejected without a code"),
+    }
+}
+
+#[test]
+fn handler_rpc_error_is_answered_other_errors_only_logged() {
+    let mut node = make_node();
+    node.register_custom("reject", handle_reject);
+    let (tx, rx) = channel();
+    let request = |rpc: bool| {
+        msg(
+            "c1",
+            "n1",
+            MessageBody::custom {
+                type_name: "reject".into(),
+                payload: serde_json::json!({ "msg_id": 9, "rpc": rpc }),
+            },
+        )
+    };
+
+    let err = node.dispatch(request(true), tx.clone()).unwrap_err();
+    assert!(
+        RpcError::find(&err).is_some(),
+        "still returned to be logged"
+    );
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "c1");
+    assert_eq!(
+        sent[0].body,
+        MessageBody::error {
+            in_reply_to: 9,
+            code: ErrorCode::Abort.code(),
+            text: "rejected".into(),
+        }
+    );
+
+    assert!(node.dispatch(request(false), tx).is_err());
+    assert!(drain(&rx).is_empty());
+}
+
+#[cfg(not(feature = "bitmap-sync"))]
+#[test]
+fn bitmap_sync_without_the_feature_is_answered_not_supported() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let sync = MessageBody::sync {
+        msg_id: 4,
+        messages: vec![],
+        tombstones: Default::default(),
+        checksum: None,
+        seq: None,
+        mode: Default::default(),
+        bitmap: Some("AAAA".into()),
+        signature: None,
+    };
+    assert!(node.dispatch(msg("n2", "n1", sync), tx).is_err());
+    let sent = drain(&rx);
+    assert!(matches!(
+        sent[..],
+        [Message {
+            body: MessageBody::error {
+                in_reply_to: 4,
+                code: 10,
+                ..
+            },
+            ..
+        }]
+    ));
+}
+
+// ── Heartbeats ───────────────────────────────────────────────────────────────
+
+#[test]
+fn ping_is_answered_with_pong() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.next(msg("n2", "n1", MessageBody::ping { msg_id: 4 }), tx)
+        .unwrap();
+    let sent = drain(&rx);
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].dest, "n2");
+    assert!(matches!(sent[0].body, MessageBody::pong { in_reply_to: 4 }));
+}
+
+#[test]
+fn heartbeats_are_only_sent_with_an_interval() {
+    let clock = MockClock::new();
+    let ping_count = |config: Config| {
+        let (tx, rx) = channel();
+        let mut node = make_node()
+            .with_config(config)
+            .with_clock(Arc::new(clock.clone()));
+        clock.advance(Duration::from_secs(1));
+        node.tick(tx).unwrap();
+        drain(&rx)
+            .iter()
+            .filter(|m| matches!(m.body, MessageBody::ping { .. }))
+            .count()
+    };
+    assert_eq!(ping_count(Config::default()), 0);
+    let config = Config {
+        heartbeat_interval: Some(Duration::from_secs(1)),
+        ..Config::default()
+    };
+    assert_eq!(ping_count(config), 2, "one per peer");
+}
+
+#[test]
+fn silent_peer_is_excluded_until_it_answers() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    let (tx, rx) = channel();
+
+    assert!(node.peer_is_up(&node_id("n2")), "never heard from yet");
+    node.next(
+        msg("n2", "n1", MessageBody::pong { in_reply_to: 1 }),
+        tx.clone(),
+    )
+    .unwrap();
+    clock.advance(node.config.peer_timeout);
+    node.next(
+        msg("n3", "n1", MessageBody::pong { in_reply_to: 1 }),
+        tx.clone(),
+    )
+    .unwrap();
+    assert!(!node.peer_is_up(&node_id("n2")));
+    assert!(node.peer_is_up(&node_id("n3")) && node.peer_is_up(&node_id("n1")));
+
+    let broadcast = MessageBody::broadcast {
+        message: 7,
+        msg_id: 1,
+    };
+    node.next(msg("c1", "n1", broadcast), tx.clone()).unwrap();
+    assert!(!node.msg_outbox.contains_key("n2"));
+    assert!(node.msg_outbox["n3"].contains(&7));
+    assert!(node
+        .request_sync_with_random_peers()
+        .iter()
+        .all(|m| m.dest != "n2"));
+
+    node.next(msg("n2", "n1", MessageBody::pong { in_reply_to: 2 }), tx)
+        .unwrap();
+    assert!(node.peer_is_up(&node_id("n2")));
+    drain(&rx);
+}
+
+#[test]
+fn partitioned_peer_goes_down_in_hosted_cluster() {
+    let clock = MockClock::new();
+    let interval = Duration::from_millis(500);
+    let config = Config {
+        heartbeat_interval: Some(interval),
+        ..Config::default()
+    };
+    let mut host = Host::<u32>::new(config).with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    let ids = ["n1", "n2", "n3"];
+    for id in ids {
+        host.route(init_msg(id, &ids), tx.clone()).unwrap();
+    }
+    // one round while connected, so n1 has heard from n3 at least once
+    clock.advance(interval);
+    host.tick(tx.clone()).unwrap();
+    host.partition(&[&["n1", "n2"], &["n3"]]);
+
+    for _ in 0..5 {
+        clock.advance(interval);
+        host.tick(tx.clone()).unwrap();
+    }
+
+    let n1 = host.node("n1").unwrap();
+    assert!(n1.peer_is_up(&node_id("n2")));
+    assert!(!n1.peer_is_up(&node_id("n3")));
+    drain(&rx);
+}
+
+#[test]
+fn phi_detector_suspects_a_regular_peer_sooner_than_a_jittery_one() {
+    let clock = MockClock::new();
+    let config = Config {
+        phi_threshold: Some(8.0),
+        ..Config::default()
+    };
+    let mut node = make_node()
+        .with_config(config)
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    // a pong answering the ping we just sent
+    let pong = |node: &mut Node<u32>, src: &str| {
+        node.liveness.pinged(&node_id(src), 1);
+        let pong = msg(src, "n1", MessageBody::pong { in_reply_to: 1 });
+        node.next(pong, tx.clone()).unwrap();
+    };
+
+    // n2 answers every 100ms, n3 alternates between 150 and 50
+    for t in (10..=2000).step_by(10) {
+        clock.advance(Duration::from_millis(10));
+        if t % 100 == 0 {
+            pong(&mut node, "n2");
+        }
+        if t % 200 == 0 || t % 200 == 50 {
+            pong(&mut node, "n3");
+        }
+        if t == 200 {
+            assert!(node.liveness.phi(&node_id("n2"), clock.now()).is_none());
+        }
+    }
+    let now = clock.now();
+    clock.advance(Duration::from_millis(300));
+    let later = clock.now();
+    assert!(node.liveness.phi(&node_id("n2"), now) < node.liveness.phi(&node_id("n2"), later));
+    assert!(node.liveness.phi(&node_id("n3"), later).unwrap() < 8.0);
+    assert!(
+        !node.peer_is_up(&node_id("n2")),
+        "well past n2's usual 100ms"
+    );
+    assert!(node.peer_is_up(&node_id("n3")), "within n3's spread");
+
+    node.next(
+        msg("c1", "n1", MessageBody::liveness { msg_id: 3 }),
+        tx.clone(),
+    )
+    .unwrap();
+    match &drain(&rx).last().unwrap().body {
+        MessageBody::liveness_ok {
+            in_reply_to: 3,
+            phi,
+            down,
+        } => {
+            assert_eq!(phi.len(), 2);
+            assert!(phi["n2"] >= 8.0);
+            assert_eq!(*down, vec![NodeId::from("n2")]);
+        }
+        other => panic!("expected liveness_ok, got {:?}", other),
+    }
+
+    // answering again brings it straight back
+    pong(&mut node, "n2");
+    assert!(node.peer_is_up(&node_id("n2")));
+}
+
+#[test]
+fn phi_only_samples_pongs_to_our_own_pings() {
+    let clock = MockClock::new();
+    let config = Config {
+        heartbeat_interval: Some(Duration::from_millis(100)),
+        ..Config::default()
+    };
+    let mut node = make_node()
+        .with_config(config)
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+
+    // n2's own pings and pongs nobody asked for are signs of life only
+    for msg_id in 1..=5 {
+        clock.advance(Duration::from_millis(100));
+        node.next(msg("n2", "n1", MessageBody::ping { msg_id }), tx.clone())
+            .unwrap();
+        let stray = MessageBody::pong {
+            in_reply_to: msg_id,
+        };
+        node.next(msg("n2", "n1", stray), tx.clone()).unwrap();
+    }
+    assert!(node.liveness.phi(&node_id("n2"), clock.now()).is_none());
+    drain(&rx);
+
+    for _ in 0..5 {
+        clock.advance(Duration::from_millis(100));
+        node.tick(tx.clone()).unwrap();
+        for ping in drain(&rx) {
+            if let MessageBody::ping { msg_id } = ping.body {
+                let reply = MessageBody::pong {
+                    in_reply_to: msg_id,
+                };
+                node.next(msg(ping.dest.as_str(), "n1", reply.clone()), tx.clone())
+                    .unwrap();
+                // a duplicate isn't a second sample
+                node.next(msg(ping.dest.as_str(), "n1", reply), tx.clone())
+                    .unwrap();
+            }
+        }
+    }
+    // the first answer starts the clock, each later one is a 100ms sample.
+    // Sampled duplicates would halve the mean and make the usual gap suspect.
+    assert_eq!(node.liveness.phis(clock.now()).len(), 2);
+    clock.advance(Duration::from_millis(100));
+    assert!(node.liveness.phi(&node_id("n2"), clock.now()).unwrap() < 0.5);
+}
+
+// ── Gossip sequence numbers ──────────────────────────────────────────────────
+
+#[test]
+fn sequences_report_skipped_numbers_only() {
+    let mut seqs = Sequences::new();
+    assert_eq!(seqs.next_for(&node_id("n2")), 1);
+    assert_eq!(seqs.next_for(&node_id("n2")), 2);
+    assert_eq!(seqs.next_for(&node_id("n3")), 1);
+
+    assert_eq!(seqs.observe(&node_id("n2"), 1), None);
+    assert_eq!(seqs.observe(&node_id("n2"), 4), Some(2..4));
+    assert_eq!(
+        seqs.observe(&node_id("n2"), 3),
+        None,
+        "late arrivals aren't a gap"
+    );
+    assert_eq!(seqs.observe(&node_id("n3"), 2), Some(1..2));
+
+    assert_eq!(
+        seqs.next_sync_for(&node_id("n2")),
+        1,
+        "sync rounds count separately"
+    );
+    assert!(seqs.accept_sync_ok(&node_id("n2"), 2));
+    assert!(!seqs.accept_sync_ok(&node_id("n2"), 1));
+    assert!(!seqs.accept_sync_ok(&node_id("n2"), 2));
+}
+
+#[test]
+fn dropped_gossip_is_recovered_through_a_repair_request() {
+    let config = Config {
+        sequence_gossip: true,
+        ..Config::default()
+    };
+    let mut sender = make_node().with_config(config.clone());
+    let mut receiver = make_node().with_config(config);
+    receiver.id = "n2".into();
+    let (tx, rx) = channel();
+
+    let mut batches = Vec::new();
+    for value in [1, 2, 3] {
+        sender.store.insert(value);
+        sender
+            .add_to_outbox(OutboxKind::FanoutMsg, &node_id("n2"), value)
+            .unwrap();
+        sender.fanout_messages(tx.clone()).unwrap();
+        batches.extend(drain(&rx));
+    }
+    assert!(matches!(
+        batches[2].body,
+        MessageBody::gossip { seq: Some(3), .. }
+    ));
+
+    // the middle batch is lost
+    receiver.next(batches[0].clone(), tx.clone()).unwrap();
+    receiver.next(batches[2].clone(), tx.clone()).unwrap();
+    assert!(!receiver.store.contains(&2));
+
+    let sent = drain(&rx);
+    assert!(!sent
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::sync { .. })));
+    let request = sent
+        .into_iter()
+        .find(|m| matches!(m.body, MessageBody::repair_request { .. }))
+        .expect("gap should trigger a repair request");
+    assert_eq!(request.dest, "n1");
+    assert!(matches!(
+        &request.body,
+        MessageBody::repair_request { missing, .. } if *missing == vec![2]
+    ));
+    sender.next(request, tx.clone()).unwrap();
+    let reply = drain(&rx).remove(0);
+    assert!(matches!(
+        &reply.body,
+        MessageBody::repair_reply { values, unknown, .. } if *values == vec![2] && unknown.is_empty()
+    ));
+    receiver.next(reply, tx.clone()).unwrap();
+
+    assert!(receiver.store.contains(&2));
+    assert_eq!(receiver.store, sender.store);
+    // nothing was unknown, so no sync follows
+    assert!(!drain(&rx)
+        .iter()
+        .any(|m| matches!(m.body, MessageBody::sync { .. })));
+}
+
+#[test]
+fn repair_of_batches_no_longer_logged_falls_back_to_sync() {
+    let config = Config {
+        sequence_gossip: true,
+        ..Config::default()
+    };
+    let mut sender = make_node().with_config(config);
+    sender.store.insert(7);
+    let (tx, rx) = channel();
+
+    // a restarted sender has no log of what it sent before
+    let request = msg(
+        "n2",
+        "n1",
+        MessageBody::repair_request {
+            msg_id: 4,
+            missing: vec![5, 6],
+        },
+    );
+    sender.next(request, tx.clone()).unwrap();
+    let reply = drain(&rx).remove(0);
+    assert!(matches!(
+        &reply.body,
+        MessageBody::repair_reply { in_reply_to: 4, values, unknown, .. } if values.is_empty() && *unknown == vec![5, 6]
+    ));
+
+    let mut receiver = make_node();
+    receiver.id = "n2".into();
+    let deadline = receiver.clock.now() + receiver.config.rpc_timeout;
+    let rpc = PendingRpc::Repair { peer: "n1".into() };
+    receiver.pending.insert(4, rpc, deadline);
+    receiver.next(reply, tx.clone()).unwrap();
+    let sync = drain(&rx)
+        .into_iter()
+        .find(|m| matches!(m.body, MessageBody::sync { .. }))
+        .expect("unknown batches should trigger a sync");
+    sender.next(sync, tx.clone()).unwrap();
+    receiver.next(drain(&rx).remove(0), tx).unwrap();
+    assert!(receiver.store.contains(&7));
+}
+
+#[test]
+fn unanswered_repair_falls_back_to_sync_and_strays_are_dropped() {
+    let clock = MockClock::new();
+    let config = Config {
+        sequence_gossip: true,
+        ..Config::default()
+    };
+    let mut receiver = make_node()
+        .with_config(config)
+        .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    let gossip = |seq| {
+        msg(
+            "n2",
+            "n1",
+            MessageBody::gossip {
+                msg_id: seq as u32,
+                messages: vec![seq as u32],
+                seq: Some(seq),
+                background: false,
+                signature: None,
+            },
+        )
+    };
+    receiver.next(gossip(1), tx.clone()).unwrap();
+    receiver.next(gossip(3), tx.clone()).unwrap();
+    let request_id = drain(&rx)
+        .iter()
+        .find_map(|m| match m.body {
+            MessageBody::repair_request { msg_id, .. } => Some(msg_id),
+            _ => None,
+        })
+        .unwrap();
+
+    // a reply to some other request, or from another node, isn't merged
+    let reply = |src: &str, in_reply_to| {
+        msg(
+            src,
+            "n1",
+            MessageBody::repair_reply {
+                in_reply_to,
+                values: vec![2],
+                unknown: vec![],
+                signature: None,
+            },
+        )
+    };
+    assert!(receiver
+        .next(reply("n2", request_id + 1), tx.clone())
+        .is_err());
+    assert!(receiver.next(reply("n3", request_id), tx.clone()).is_err());
+    assert!(!receiver.store.contains(&2));
+
+    clock.advance(receiver.config.rpc_timeout);
+    receiver.tick(tx.clone()).unwrap();
+    assert!(drain(&rx)
+        .iter()
+        .any(|m| m.dest == "n2" && matches!(m.body, MessageBody::sync { .. })));
+    assert!(
+        receiver.next(reply("n2", request_id), tx).is_err(),
+        "expired along with its request"
+    );
+}
+
+#[test]
+fn stale_sync_ok_is_not_merged() {
+    let mut node = make_node();
+    let (tx, _rx) = channel();
+    let sync_ok = |seq, value| {
+        msg(
+            "n2",
+            "n1",
+            MessageBody::sync_ok {
+                msg_id: 1,
+                in_reply_to: Some(0),
+                messages: vec![value],
+                tombstones: Default::default(),
+                seq: Some(seq),
+                more: false,
+                bitmap: None,
+                signature: None,
+            },
+        )
+    };
+
+    node.handle_sync_ok_message(sync_ok(2, 7), tx.clone())
+        .unwrap();
+    for stale in [sync_ok(1, 5), sync_ok(2, 6)] {
+        node.handle_sync_ok_message(stale, tx.clone()).unwrap();
+    }
+    assert_eq!(node.read(), vec![7]);
+    node.handle_sync_ok_message(sync_ok(3, 5), tx).unwrap();
+    let mut values = node.read();
+    values.sort_unstable();
+    assert_eq!(values, vec![5, 7]);
+}
+
+// ── Wire codecs ──────────────────────────────────────────────────────────────
+
+fn big_sync() -> Message {
+    msg(
+        "n2",
+        "n1",
+        MessageBody::sync {
+            msg_id: 3,
+            messages: (0..500).collect(),
+            tombstones: Default::default(),
+            checksum: Some(42),
+            seq: None,
+            mode: Default::default(),
+            bitmap: None,
+            signature: None,
+        },
+    )
+}
+
+#[test]
+fn json_codec_round_trips() {
+    let message = big_sync();
+    let bytes = JsonCodec.encode(&message).unwrap();
+    assert_eq!(JsonCodec.decode(&bytes).unwrap(), message);
+    assert!(JsonCodec.decode(b"{").is_err());
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_codec_round_trips_and_is_smaller() {
+    use crate::MsgPackCodec;
+
+    let message = big_sync();
+    let packed = MsgPackCodec.encode(&message).unwrap();
+    assert_eq!(MsgPackCodec.decode(&packed).unwrap(), message);
+    assert!(packed.len() < JsonCodec.encode(&message).unwrap().len());
+
+    let ack = msg(
+        "n1",
+        "n2",
+        MessageBody::gossip_ok {
+            in_reply_to: Some(1),
+        },
+    );
+    let packed = MsgPackCodec.encode(&ack).unwrap();
+    assert_eq!(MsgPackCodec.decode(&packed).unwrap(), ack);
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn peer_codec_is_configurable_and_detected() {
+    let config = Config::from_vars(vars(&[("DIST_PEER_CODEC", "msgpack")])).unwrap();
+    assert_eq!(config.peer_codec, PeerCodec::MsgPack);
+
+    let message = big_sync();
+    for codec in [PeerCodec::Json, PeerCodec::MsgPack] {
+        let bytes = codec.encode(&message).unwrap();
+        assert_eq!(PeerCodec::detect(&bytes), Some(codec));
+        assert_eq!(codec.decode(&bytes).unwrap(), message);
+    }
+    assert_eq!(PeerCodec::detect(b" \n{}"), Some(PeerCodec::Json));
+    assert_eq!(PeerCodec::detect(b"[1]"), None);
+    assert_eq!(PeerCodec::detect(b""), None);
+}
+
+// ── Bitmap sync payloads ─────────────────────────────────────────────────────
+
+#[cfg(feature = "bitmap-sync")]
+#[test]
+fn bitmap_round_trips_sparse_and_dense_sets() {
+    use crate::bitmap;
+
+    let sparse = vec![0, 7, 65_536, 4_000_000_000, u32::MAX];
+    assert_eq!(
+        bitmap::decode(&bitmap::encode(sparse.clone())).unwrap(),
+        sparse
+    );
+    assert!(bitmap::decode(&bitmap::encode([])).unwrap().is_empty());
+
+    // one container past the array limit, one below it
+    let dense: Vec<u32> = (0..10_000).chain(70_000..70_010).collect();
+    let encoded = bitmap::encode(dense.iter().rev().copied());
+    assert_eq!(bitmap::decode(&encoded).unwrap(), dense);
+    let json = serde_json::to_string(&dense).unwrap();
+    assert!(
+        encoded.len() * 4 < json.len(),
+        "{} vs {}",
+        encoded.len(),
+        json.len()
+    );
+
+    assert!(bitmap::decode("!!").is_err());
+    assert!(bitmap::decode(&encoded[..20]).is_err());
+}
+
+#[cfg(feature = "bitmap-sync")]
+#[test]
+fn bitmap_set_ops_match_a_hash_set_across_container_kinds() {
+    use crate::bitmap::Bitmap;
+    use std::collections::HashSet;
+
+    // container 0 dense in both, 1 dense against sparse, 2 sparse in both,
+    // 3 only in one of them
+    let a: Vec<u32> = (0..6_000)
+        .chain((65_536..75_536).step_by(2))
+        .chain([131_072, 131_080, 131_100])
+        .chain([196_608])
+        .collect();
+    let b: Vec<u32> = (3_000..9_000)
+        .chain([65_536, 65_538, 65_539])
+        .chain([131_080])
+        .collect();
+    let (bitmap_a, bitmap_b): (Bitmap, Bitmap) =
+        (a.iter().copied().collect(), b.iter().copied().collect());
+    let (set_a, set_b): (HashSet<u32>, HashSet<u32>) =
+        (a.iter().copied().collect(), b.iter().copied().collect());
+    let sorted = |set: &HashSet<u32>| {
+        let mut values: Vec<u32> = set.iter().copied().collect();
+        values.sort_unstable();
+        values
+    };
+
+    let only_a = bitmap_a.difference(&bitmap_b);
+    assert_eq!(
+        only_a.iter().collect::<Vec<_>>(),
+        sorted(&(&set_a - &set_b))
+    );
+    assert_eq!(only_a.len(), (&set_a - &set_b).len());
+    let only_b = bitmap_b.difference(&bitmap_a);
+    assert_eq!(
+        only_b.iter().collect::<Vec<_>>(),
+        sorted(&(&set_b - &set_a))
+    );
+
+    let mut union = bitmap_a.clone();
+    union.union_with(&bitmap_b);
+    assert_eq!(union.iter().collect::<Vec<_>>(), sorted(&(&set_a | &set_b)));
+    assert_eq!(union.len(), (&set_a | &set_b).len());
+    assert_eq!(Bitmap::decode(&union.encode()).unwrap(), union);
+
+    // shrinking a dense container below the array limit turns it back into
+    // an array, so equal sets compare equal however they were built
+    let mut shrunk = bitmap_a.clone();
+    for value in 100..6_000 {
+        shrunk.remove(&value);
+    }
+    let rebuilt: Bitmap = shrunk.iter().collect();
+    assert_eq!(shrunk, rebuilt);
+    assert!(!shrunk.contains(&100) && shrunk.contains(&99));
+}
+
+#[cfg(feature = "bitmap-sync")]
+#[test]
+fn bitmap_decode_rejects_malformed_containers() {
+    use crate::bitmap::Bitmap;
+
+    // two values make a 9 byte container, 12 base64 characters with no
+    // padding, so two encodings glued together are one payload
+    let pair: Bitmap = [5u32, 9].into_iter().collect();
+    let encoded = pair.encode();
+    assert_eq!(Bitmap::decode(&encoded).unwrap(), pair);
+    let twice = format!("{encoded}{encoded}");
+    assert!(
+        Bitmap::<u32>::decode(&twice).is_err(),
+        "same container twice"
+    );
+    // array container 0 holding 9 then 5
+    assert!(
+        Bitmap::<u32>::decode("AAAAAQAJAAUA").is_err(),
+        "unsorted array"
+    );
+}
+
+#[cfg(feature = "bitmap-sync")]
+#[test]
+fn sync_carries_values_as_a_bitmap() {
+    let mut n1 = seeded("n1", &[5, 1, 3]);
+    let mut n2 = seeded("n2", &[8]);
+
+    let (sync, replies) = sync_round(&mut n1, &mut n2);
+    let MessageBody::sync {
+        ref messages,
+        ref bitmap,
+        ..
+    } = sync.body
+    else {
+        panic!("expected sync, got {:?}", sync.body);
+    };
+    assert!(messages.is_empty());
+    assert!(bitmap.is_some());
+    for reply in replies {
+        assert!(matches!(
+            reply.body,
+            MessageBody::sync_ok {
+                ref messages,
+                bitmap: Some(_),
+                ..
+            } if messages.is_empty()
+        ));
+    }
+    assert_eq!(sorted(&n2), vec![1, 3, 5, 8]);
+    assert_eq!(sorted(&n1), vec![1, 3, 5, 8]);
+}
+
+#[cfg(feature = "bitmap-sync")]
+#[test]
+fn streamed_sync_ok_decodes_a_bitmap() {
+    let line = format!(
+        r#"{{"src":"n2","dest":"n1","body":{{"type":"sync_ok","msg_id":1,"messages":[],"bitmap":"{}"}}}}"#,
+        crate::bitmap::encode([9, 70_000, 2])
+    );
+    let mut seen = Vec::new();
+    let streamed = stream_sync_ok(&line, |m| seen.push(m)).unwrap();
+    assert_eq!(seen, vec![2, 9, 70_000]);
+    assert_eq!(streamed.values, 3);
+}
+
+// ── State snapshots ──────────────────────────────────────────────────────────
+
+#[test]
+fn state_round_trips_through_serialize_and_restore() {
+    let mut node = make_node();
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    let (tx, rx) = channel();
+    for value in [30, 10, 20] {
+        let body = MessageBody::broadcast {
+            message: value,
+            msg_id: value,
+        };
+        node.handle_broadcast_message(msg("c1", "n1", body), tx.clone())
+            .unwrap();
+    }
+    node.remove(10);
+    node.sequences.next_for(&node_id("n2"));
+    node.retry_cursor = 1;
+    drain(&rx);
+
+    let checkpoint = node.serialize_state().unwrap();
+    let mut restored = Node::<u32>::new();
+    restored.restore_state(&checkpoint).unwrap();
+
+    assert_eq!(restored.state(), node.state());
+    assert_eq!(restored.read(), vec![30, 20], "insertion order survives");
+    assert!(restored.restore_state("{}").is_err());
+}
+
+#[test]
+fn restored_node_resends_what_was_unacked() {
+    let clock = MockClock::new();
+    let mut node = make_node().with_clock(Arc::new(clock.clone()));
+    node.topology
+        .insert("n1".into(), vec!["n2".into(), "n3".into()]);
+    let (tx, rx) = channel();
+    let body = MessageBody::broadcast {
+        message: 7,
+        msg_id: 1,
+    };
+    node.next(msg("c1", "n1", body), tx.clone()).unwrap();
+    node.fanout_messages(tx.clone()).unwrap();
+    drain(&rx);
+    let checkpoint = node.serialize_state().unwrap();
+
+    let mut restored = make_node().with_clock(Arc::new(clock.clone()));
+    restored.restore_state(&checkpoint).unwrap();
+    clock.advance(restored.config.retry_interval);
+    restored.tick(tx).unwrap();
+    let mut resent: Vec<NodeId> = drain(&rx)
+        .into_iter()
+        .filter(|m| matches!(&m.body, MessageBody::gossip { messages, .. } if messages == &[7]))
+        .map(|m| m.dest)
+        .collect();
+    resent.sort();
+    assert_eq!(resent, vec![NodeId::from("n2"), NodeId::from("n3")]);
+}
+
+// ── Protocol conformance ─────────────────────────────────────────────────────
+
+#[test]
+fn standard_bodies_match_maelstrom_schemas() {
+    use crate::conformance::check_message;
+
+    let mut host = Host::<u32>::new(Config::default());
+    let (tx, rx) = channel();
+    let mut requests = vec![init_msg("n1", &["n1", "n2"])];
+    let bodies = [
+        MessageBody::topology {
+            topology: [("n1".into(), vec!["n2".into()])].into_iter().collect(),
+            msg_id: 2,
+        },
+        MessageBody::echo {
+            msg_id: 3,
+            echo: "hi".to_string(),
+        },
+        MessageBody::broadcast {
+            message: 7,
+            msg_id: 4,
+        },
+        MessageBody::read {
+            msg_id: 5,
+            quorum: false,
+            offset: None,
+            limit: None,
+            from: None,
+        },
+        MessageBody::generate { msg_id: 6 },
+    ];
+    requests.extend(bodies.into_iter().map(|body| msg("c1", "n1", body)));
+    for request in requests {
+        check_message(&serde_json::to_value(&request).unwrap()).unwrap();
+        host.route(request, tx.clone()).unwrap();
+    }
+
+    let replies = drain(&rx);
+    let mut types: Vec<String> = replies.iter().map(|m| m.body.type_name()).collect();
+    types.sort();
+    assert_eq!(
+        types,
+        [
+            "broadcast_ok",
+            "echo_ok",
+            "generate_ok",
+            "init_ok",
+            "read_ok",
+            "topology_ok"
+        ]
+    );
+    for reply in &replies {
+        let value = serde_json::to_value(reply).unwrap();
+        check_message(&value).unwrap_or_else(|e| panic!("{value}: {e:#}"));
+    }
+}
+
+#[test]
+fn conformance_catches_misspelled_and_mistyped_fields() {
+    use crate::conformance::{check_body, check_message};
+
+    let misspelled = serde_json::json!({"type": "echo_ok", "in_reply_too": 1, "echo": "x"});
+    let error = check_body(&misspelled).unwrap_err().to_string();
+    assert_eq!(error, "echo_ok is missing in_reply_to");
+    let mistyped = serde_json::json!({"type": "read_ok", "in_reply_to": 1, "messages": "1,2"});
+    assert!(check_body(&mistyped).is_err());
+    let topology = serde_json::json!({"type": "topology", "msg_id": 1, "topology": {"n1": [2]}});
+    assert!(check_body(&topology).is_err());
+    let no_dest = serde_json::json!({"src": "n1", "body": {"type": "init_ok", "in_reply_to": 1}});
+    assert!(check_message(&no_dest).is_err());
+
+    // an ack without in_reply_to decodes fine here but Maelstrom rejects it
+    let ack = MessageBody::broadcast_ok {
+        in_reply_to: None,
+        msg_id: 1,
+    };
+    assert!(check_body(&serde_json::to_value(ack).unwrap()).is_err());
+    // our own bodies have no schema
+    let gossip = serde_json::json!({"type": "gossip", "messages": [1]});
+    assert!(check_body(&gossip).is_ok());
+}
+
+// ── Metrics ──────────────────────────────────────────────────────────────────
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_endpoint_serves_node_counters_in_prometheus_format() {
+    use std::io::{Read, Write};
+
+    let clock = MockClock::new();
+    let mut host = Host::<u32>::new(Config::default()).with_clock(Arc::new(clock.clone()));
+    let addr = host.serve_metrics("127.0.0.1:0".parse().unwrap()).unwrap();
+    let (tx, _rx) = channel();
+    host.route(init_msg("n1", &["n1", "n2"]), tx.clone())
+        .unwrap();
+    let broadcast = MessageBody::broadcast {
+        message: 5,
+        msg_id: 2,
+    };
+    host.route(msg("c1", "n1", broadcast), tx.clone()).unwrap();
+    clock.advance(Duration::from_millis(60));
+    host.tick(tx.clone()).unwrap();
+
+    // a connection that never sends its request doesn't hold up others
+    let _idle = std::net::TcpStream::connect(addr).unwrap();
+    let scrape = |request: &'static [u8]| {
+        std::thread::spawn(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+    };
+    // the page is rendered by the node loop once a scrape asks for it
+    let scraping = scrape(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    while !scraping.is_finished() {
+        host.tick(tx.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let response = scraping.join().unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    for line in [
+        "# TYPE dist_broadcasts_total counter",
+        "dist_broadcasts_total{node=\"n1\"} 1",
+        "dist_gossip_sent_total{node=\"n1\"} 1",
+        "dist_store_values{node=\"n1\"} 1",
+        "dist_outbox_depth{node=\"n1\",peer=\"n2\"} 1",
+        "dist_slow_handlers_total 0",
+    ] {
+        assert!(response.lines().any(|l| l == line), "{line} in {response}");
+    }
+
+    let response = scrape(b"GET / HTTP/1.1\r\n\r\n").join().unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+}
+
+#[test]
+fn syncs_sent_counts_follow_ups_and_repair_fallbacks() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let capped = MessageBody::sync_ok {
+        msg_id: 1,
+        in_reply_to: Some(0),
+        messages: vec![1],
+        tombstones: Default::default(),
+        seq: None,
+        more: true,
+        bitmap: None,
+        signature: None,
+    };
+    node.next(msg("n2", "n1", capped), tx.clone()).unwrap();
+    assert_eq!(node.stats().syncs_sent, 1);
+
+    let deadline = node.clock.now() + node.config.rpc_timeout;
+    let rpc = PendingRpc::Repair { peer: "n2".into() };
+    node.pending.insert(4, rpc, deadline);
+    let unknown = MessageBody::repair_reply {
+        in_reply_to: 4,
+        values: vec![],
+        unknown: vec![3],
+        signature: None,
+    };
+    node.next(msg("n2", "n1", unknown), tx).unwrap();
+    assert_eq!(node.stats().syncs_sent, 2);
+    let syncs = drain(&rx)
+        .iter()
+        .filter(|m| matches!(m.body, MessageBody::sync { .. }))
+        .count();
+    assert_eq!(syncs, 2);
 }
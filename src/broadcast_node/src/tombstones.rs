@@ -0,0 +1,82 @@
+use std::collections::{HashMap, HashSet};
+
+use node_common::NodeId;
+use serde::{Deserialize, Serialize};
+
+// Removal markers for delete-capable workloads (OR-Set style). Each tombstone
+// carries the set of nodes known to have seen it. The sets are exchanged and
+// unioned during sync, and once every member of `node_ids` is in the set the
+// tombstone is dropped, so memory stays bounded under heavy deletes.
+//
+// Once collected, a late duplicate of the removed value (an old retry for
+// example) is treated as a fresh add again.
+pub type AckMap = HashMap<u32, HashSet<NodeId>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tombstones {
+    acks: AckMap,
+}
+
+impl Tombstones {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, value: u32, self_id: &NodeId) {
+        self.acks.entry(value).or_default().insert(self_id.clone());
+    }
+
+    pub fn contains(&self, value: u32) -> bool {
+        self.acks.contains_key(&value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.acks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.acks.len()
+    }
+
+    // What goes into an outgoing sync/sync_ok
+    pub fn snapshot(&self) -> AckMap {
+        self.acks.clone()
+    }
+
+    // Folds in a peer's view. Both we and the peer have now seen every
+    // tombstone in it. Returns the values that were new to us so the
+    // caller can drop them from the store.
+    pub fn merge(&mut self, peer: &NodeId, self_id: &NodeId, incoming: &AckMap) -> Vec<u32> {
+        let mut newly_removed = Vec::new();
+        for (value, seen_by) in incoming {
+            let local = self.acks.entry(*value).or_insert_with(|| {
+                newly_removed.push(*value);
+                HashSet::new()
+            });
+            local.extend(seen_by.iter().cloned());
+            local.insert(peer.clone());
+            local.insert(self_id.clone());
+        }
+        newly_removed
+    }
+
+    // Drops every tombstone all of `node_ids` have acknowledged, returning the
+    // values sorted. Without a membership nobody has acknowledged anything,
+    // so nothing is collected.
+    pub fn collect_garbage(&mut self, node_ids: &[NodeId]) -> Vec<u32> {
+        if node_ids.is_empty() {
+            return Vec::new();
+        }
+        let mut collected: Vec<u32> = self
+            .acks
+            .iter()
+            .filter(|(_, seen_by)| node_ids.iter().all(|id| seen_by.contains(id)))
+            .map(|(value, _)| *value)
+            .collect();
+        collected.sort_unstable();
+        for value in &collected {
+            self.acks.remove(value);
+        }
+        collected
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{BroadcastNodeTrait, Message, MessageBody, Node};
+use crate::{BroadcastNodeTrait, Message, MessageBody, Node, NodeId};
 use anyhow::Result;
 use std::hash::Hash;
 use std::sync::mpsc::Sender;
@@ -8,8 +8,10 @@ fn new_ulid() -> Ulid {
     Ulid::new()
 }
 
-pub(crate) fn generate_unique_id() -> String {
-    new_ulid().to_string()
+// Prefixed with the node id so ids from different nodes can't collide even
+// if two ULIDs do (e.g. after a clock rollback)
+pub(crate) fn generate_unique_id(node_id: &NodeId) -> String {
+    format!("{}-{}", node_id, new_ulid())
 }
 
 pub(crate) fn generate_message_id() -> u32 {
@@ -27,7 +29,7 @@ where
     Node<Data>: BroadcastNodeTrait,
 {
     if let MessageBody::generate { msg_id } = msg.body {
-        let unique_id = generate_unique_id();
+        let unique_id = generate_unique_id(&node.id);
         let payload = MessageBody::generate_ok {
             msg_id: node.get_and_increment_msg_id(),
             in_reply_to: msg_id,
@@ -0,0 +1,96 @@
+use anyhow::Result;
+use node_common::ErrorCode;
+use serde_json::Value;
+use std::sync::mpsc::Sender;
+
+use crate::{GCounterNode, Message, MessageBody};
+
+// A grow-only counter only takes whole, non-negative deltas that fit an i64
+pub(crate) fn parse_delta(delta: &Value) -> Option<i64> {
+    delta.as_i64().filter(|delta| *delta >= 0)
+}
+
+pub fn handle_add_message(
+    node: &mut GCounterNode,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()> {
+    if let MessageBody::add { msg_id, ref delta } = msg.body {
+        let body = match parse_delta(delta) {
+            None => error(
+                msg_id,
+                format!("delta must be a non-negative integer, got {delta}"),
+            ),
+            Some(delta) => match node.value.checked_add(delta) {
+                Some(value) => {
+                    node.value = value;
+                    MessageBody::add_ok {
+                        in_reply_to: msg_id,
+                    }
+                }
+                None => error(
+                    msg_id,
+                    format!("adding {delta} to {} overflows the counter", node.value),
+                ),
+            },
+        };
+        msg.into_reply(body).send(tx)?;
+    }
+    Ok(())
+}
+
+pub fn handle_read_message(
+    node: &mut GCounterNode,
+    msg: Message,
+    tx: Sender<Message>,
+) -> Result<()> {
+    if let MessageBody::read { msg_id } = msg.body {
+        msg.into_reply(MessageBody::read_ok {
+            in_reply_to: msg_id,
+            value: node.total(),
+        })
+        .send(tx)?;
+    }
+    Ok(())
+}
+
+// Merges a peer's view of the counts, keeping the larger count per node. That
+// includes our own entry, so a replica that lost its state gets its count
+// back from its peers.
+pub fn handle_counts_message(node: &mut GCounterNode, msg: Message) -> Result<()> {
+    if let MessageBody::counts { counts } = msg.body {
+        for (id, count) in counts {
+            if id == node.id {
+                node.value = node.value.max(count);
+            } else {
+                let known = node.counts.entry(id).or_default();
+                *known = (*known).max(count);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn gossip_counts(node: &GCounterNode, tx: Sender<Message>) -> Result<()> {
+    let mut counts = node.counts.clone();
+    counts.insert(node.id.clone(), node.value);
+    for peer in node.node_ids.iter().filter(|id| **id != node.id) {
+        Message {
+            src: node.id.clone(),
+            dest: peer.clone(),
+            body: MessageBody::counts {
+                counts: counts.clone(),
+            },
+        }
+        .send(tx.clone())?;
+    }
+    Ok(())
+}
+
+fn error(in_reply_to: u32, text: String) -> MessageBody {
+    MessageBody::error {
+        in_reply_to,
+        code: ErrorCode::MalformedRequest.code(),
+        text,
+    }
+}
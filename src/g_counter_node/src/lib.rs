@@ -1,11 +1,110 @@
-use serde::{Deserialize, Serialize};
+mod counter;
+mod message_body;
+#[cfg(test)]
+mod tests;
 
+use anyhow::Result;
+use node_common::{canonical_members, Clock, NodeId, SystemClock};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::message_body::MessageBody;
 pub type Message = node_common::Message<MessageBody>;
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-pub enum MessageBody {
-    read,
-    read_ok {},
+// A single counter replica, a state based G-Counter. Each node only ever
+// raises its own count and gossips every count it knows on a timer; peers
+// keep the highest count they've seen per node, so lost, duplicated or
+// reordered gossip is harmless and a read is the sum over all nodes.
+#[derive(Debug, Clone)]
+pub struct GCounterNode {
+    pub id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    // the adds this node accepted
+    pub value: i64,
+    // the other nodes' counts, as last gossiped to us
+    pub counts: HashMap<NodeId, i64>,
+    pub clock: Arc<dyn Clock>,
+    // how often our counts go out to every peer
+    pub gossip_interval: Duration,
+    last_gossip: Option<Instant>,
+}
+
+impl Default for GCounterNode {
+    fn default() -> Self {
+        Self {
+            id: NodeId::default(),
+            node_ids: Vec::new(),
+            value: 0,
+            counts: HashMap::new(),
+            clock: Arc::new(SystemClock),
+            gossip_interval: Duration::from_millis(200),
+            last_gossip: None,
+        }
+    }
+}
+
+impl GCounterNode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn next(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        match msg.body {
+            MessageBody::init { .. } => self.handle_init_message(msg, tx),
+            MessageBody::add { .. } => counter::handle_add_message(self, msg, tx),
+            MessageBody::read { .. } => counter::handle_read_message(self, msg, tx),
+            MessageBody::counts { .. } => counter::handle_counts_message(self, msg),
+            MessageBody::init_ok { .. }
+            | MessageBody::add_ok { .. }
+            | MessageBody::read_ok { .. }
+            | MessageBody::error { .. } => {
+                anyhow::bail!("no handler for {:?} from {}", msg.body, msg.src)
+            }
+        }
+    }
+
+    // Gossips our counts once every gossip_interval
+    pub fn tick(&mut self, tx: Sender<Message>) -> Result<()> {
+        let now = self.clock.now();
+        if self
+            .last_gossip
+            .is_some_and(|at| now.saturating_duration_since(at) < self.gossip_interval)
+        {
+            return Ok(());
+        }
+        self.last_gossip = Some(now);
+        counter::gossip_counts(self, tx)
+    }
+
+    // The sum over every node's count. Each count fits an i64 but their sum
+    // may not, so it saturates rather than wrapping.
+    pub fn total(&self) -> i64 {
+        self.counts
+            .iter()
+            .filter(|(id, _)| **id != self.id)
+            .fold(self.value, |total, (_, count)| total.saturating_add(*count))
+    }
+
+    fn handle_init_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        if let MessageBody::init {
+            msg_id,
+            ref node_id,
+            ref node_ids,
+        } = msg.body
+        {
+            (self.id, self.node_ids) = (node_id.clone(), canonical_members(node_ids.clone()));
+            msg.into_reply(MessageBody::init_ok {
+                in_reply_to: msg_id,
+            })
+            .send(tx)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,45 @@
+use node_common::NodeId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Maelstrom's g-counter protocol. `delta` is kept as raw JSON so a bad one
+// gets a MalformedRequest reply instead of failing to deserialize. `counts`
+// is our own gossip between replicas.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(non_camel_case_types)]
+pub enum MessageBody {
+    init {
+        msg_id: u32,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+    },
+    init_ok {
+        in_reply_to: u32,
+    },
+    add {
+        msg_id: u32,
+        delta: Value,
+    },
+    add_ok {
+        in_reply_to: u32,
+    },
+    read {
+        msg_id: u32,
+    },
+    read_ok {
+        in_reply_to: u32,
+        value: i64,
+    },
+    // every replica's count as the sender last heard it, merged by max
+    counts {
+        counts: HashMap<NodeId, i64>,
+    },
+    error {
+        in_reply_to: u32,
+        code: u32,
+        text: String,
+    },
+}
@@ -0,0 +1,196 @@
+use node_common::MockClock;
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+use crate::{GCounterNode, Message, MessageBody};
+
+// ── Helpers ──────────────────────────────────────────────────────────────────
+
+fn make_node() -> GCounterNode {
+    GCounterNode {
+        id: "n1".into(),
+        node_ids: vec!["n1".into(), "n2".into()],
+        ..GCounterNode::new()
+    }
+}
+
+fn channel() -> (Sender<Message>, Receiver<Message>) {
+    mpsc::channel()
+}
+
+fn read(msg_id: u32) -> Message {
+    Message {
+        src: "c1".into(),
+        dest: "n1".into(),
+        body: MessageBody::read { msg_id },
+    }
+}
+
+fn counts(counts: &[(&str, i64)]) -> Message {
+    Message {
+        src: "n2".into(),
+        dest: "n1".into(),
+        body: MessageBody::counts {
+            counts: counts.iter().map(|(id, c)| ((*id).into(), *c)).collect(),
+        },
+    }
+}
+
+fn add(msg_id: u32, delta: Value) -> Message {
+    Message {
+        src: "c1".into(),
+        dest: "n1".into(),
+        body: MessageBody::add { msg_id, delta },
+    }
+}
+
+// ── Add ──────────────────────────────────────────────────────────────────────
+
+#[test]
+fn add_accumulates_and_read_returns_total() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.next(add(1, json!(3)), tx.clone()).unwrap();
+    node.next(add(2, json!(4)), tx.clone()).unwrap();
+    node.next(read(3), tx).unwrap();
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::add_ok { in_reply_to: 1 }
+    ));
+    assert_eq!(
+        replies[2].body,
+        MessageBody::read_ok {
+            in_reply_to: 3,
+            value: 7
+        }
+    );
+}
+
+#[test]
+fn add_rejects_malformed_deltas() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    for (msg_id, delta) in [json!(-1), json!(1.5), json!("2"), json!(u64::MAX)]
+        .into_iter()
+        .enumerate()
+    {
+        node.next(add(msg_id as u32, delta), tx.clone()).unwrap();
+    }
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert_eq!(replies.len(), 4);
+    assert!(replies
+        .iter()
+        .all(|m| matches!(m.body, MessageBody::error { code: 12, .. })));
+    assert_eq!(node.value, 0);
+}
+
+#[test]
+fn add_that_would_overflow_is_rejected_without_applying() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.next(add(1, json!(i64::MAX - 1)), tx.clone()).unwrap();
+    node.next(add(2, json!(i64::MAX)), tx.clone()).unwrap();
+    node.next(add(3, json!(1)), tx).unwrap();
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert!(matches!(
+        replies[1].body,
+        MessageBody::error {
+            in_reply_to: 2,
+            code: 12,
+            ..
+        }
+    ));
+    assert!(matches!(
+        replies[2].body,
+        MessageBody::add_ok { in_reply_to: 3 }
+    ));
+    assert_eq!(node.value, i64::MAX);
+}
+
+// ── Replication ──────────────────────────────────────────────────────────────
+
+#[test]
+fn gossip_makes_every_replica_read_the_total() {
+    let clock = MockClock::new();
+    let mut n1 = make_node().with_clock(Arc::new(clock.clone()));
+    let mut n2 = GCounterNode {
+        id: "n2".into(),
+        ..make_node()
+    }
+    .with_clock(Arc::new(clock.clone()));
+    let (tx, rx) = channel();
+    n1.next(add(1, json!(3)), tx.clone()).unwrap();
+    n2.next(add(1, json!(4)), tx.clone()).unwrap();
+    rx.try_iter().count();
+
+    n1.tick(tx.clone()).unwrap();
+    n2.tick(tx.clone()).unwrap();
+    let gossip: Vec<Message> = rx.try_iter().collect();
+    assert_eq!(gossip.len(), 2);
+    for msg in gossip {
+        // delivered twice, as a retransmit would be
+        let node = if msg.dest == "n1" { &mut n1 } else { &mut n2 };
+        node.next(msg.clone(), tx.clone()).unwrap();
+        node.next(msg, tx.clone()).unwrap();
+    }
+    assert_eq!((n1.total(), n2.total()), (7, 7));
+
+    n1.next(read(2), tx.clone()).unwrap();
+    assert_eq!(
+        rx.try_recv().unwrap().body,
+        MessageBody::read_ok {
+            in_reply_to: 2,
+            value: 7
+        }
+    );
+
+    // nothing more goes out until the interval has passed
+    n1.tick(tx.clone()).unwrap();
+    assert!(rx.try_recv().is_err());
+    clock.advance(n1.gossip_interval);
+    n1.tick(tx).unwrap();
+    assert_eq!(rx.try_iter().count(), 1);
+}
+
+#[test]
+fn gossip_never_lowers_a_count() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.next(add(1, json!(5)), tx.clone()).unwrap();
+    node.next(counts(&[("n1", 2), ("n2", 6)]), tx.clone())
+        .unwrap();
+    node.next(counts(&[("n2", 4)]), tx.clone()).unwrap();
+    node.next(counts(&[("n1", 9)]), tx).unwrap();
+
+    assert!(rx
+        .try_iter()
+        .all(|m| matches!(m.body, MessageBody::add_ok { .. })));
+    assert_eq!(node.value, 9);
+    assert_eq!(node.total(), 15);
+}
+
+// Rounds carry no number, merging by max is what makes a replay harmless. A
+// peer that restarted and gossips from scratch is merged like any other.
+#[test]
+fn replayed_or_reordered_rounds_leave_the_max() {
+    let mut node = make_node();
+    let (tx, _rx) = channel();
+    for round in [
+        counts(&[("n2", 5)]),
+        counts(&[("n2", 5)]),
+        counts(&[("n2", 7)]),
+        counts(&[("n2", 6)]),
+        counts(&[("n2", 7)]),
+    ] {
+        node.next(round, tx.clone()).unwrap();
+    }
+    assert_eq!(node.total(), 7);
+    node.next(counts(&[("n2", 8)]), tx).unwrap();
+    assert_eq!(node.total(), 8);
+}
@@ -0,0 +1,131 @@
+mod message_body;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use broadcast_node::{BroadcastNodeTrait, Clock, Node};
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+pub use crate::message_body::MessageBody;
+pub use broadcast_node::{BoundedLines, Config, OversizedMessage};
+pub type Message = node_common::Message<MessageBody>;
+
+// A line read from or written to Maelstrom: either the g-set client protocol
+// or anything broadcast_node speaks (init, errors, gossip and syncs)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Envelope {
+    Client(Message),
+    Node(broadcast_node::Message),
+}
+
+// Requests from clients are tried as g-set bodies first, everything else
+// (including a node's peer read) goes through broadcast_node's decode_line
+pub fn decode_line(line: &str, max_bytes: usize) -> Result<Envelope> {
+    if line.len() <= max_bytes {
+        if let Ok(msg) = serde_json::from_str::<Message>(line) {
+            if msg.src.is_client() {
+                return Ok(Envelope::Client(msg));
+            }
+        }
+    }
+    broadcast_node::decode_line(line, max_bytes).map(Envelope::Node)
+}
+
+// A g-set replica is a broadcast node with a different client protocol: an
+// add is stored, gossiped and synced exactly like a broadcast value, and a
+// read returns the same store.
+pub struct GSetNode {
+    node: Node<u32>,
+}
+
+impl GSetNode {
+    pub fn new(config: Config) -> Self {
+        Self {
+            node: Node::default().with_config(config),
+        }
+    }
+
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            node: self.node.with_clock(clock),
+        }
+    }
+
+    pub fn node(&self) -> &Node<u32> {
+        &self.node
+    }
+
+    pub fn next(&mut self, msg: Envelope, tx: Sender<Envelope>) -> Result<()> {
+        let msg = match msg {
+            Envelope::Client(msg) => into_broadcast(msg)?,
+            Envelope::Node(msg) => msg,
+        };
+        let (local_tx, local_rx) = mpsc::channel();
+        let result = self.node.dispatch(msg, local_tx);
+        forward(local_rx, &tx)?;
+        result
+    }
+
+    pub fn tick(&mut self, tx: Sender<Envelope>) -> Result<()> {
+        let (local_tx, local_rx) = mpsc::channel();
+        self.node.tick(local_tx)?;
+        forward(local_rx, &tx)
+    }
+}
+
+fn into_broadcast(msg: Message) -> Result<broadcast_node::Message> {
+    let body = match msg.body {
+        MessageBody::add { msg_id, element } => broadcast_node::MessageBody::broadcast {
+            message: element,
+            msg_id,
+        },
+        MessageBody::read { msg_id } => broadcast_node::MessageBody::read {
+            msg_id,
+            quorum: false,
+            offset: None,
+            limit: None,
+            from: None,
+        },
+        MessageBody::add_ok { .. } | MessageBody::read_ok { .. } => {
+            anyhow::bail!("no handler for {:?} from {}", msg.body, msg.src)
+        }
+    };
+    Ok(broadcast_node::Message {
+        src: msg.src,
+        dest: msg.dest,
+        body,
+    })
+}
+
+// Replies to clients are put back into g-set terms, the rest goes out as is
+fn forward(rx: Receiver<broadcast_node::Message>, tx: &Sender<Envelope>) -> Result<()> {
+    for out in rx.try_iter() {
+        let body = match out.body {
+            broadcast_node::MessageBody::broadcast_ok {
+                in_reply_to: Some(in_reply_to),
+                ..
+            } if out.dest.is_client() => MessageBody::add_ok { in_reply_to },
+            broadcast_node::MessageBody::read_ok {
+                ref messages,
+                in_reply_to,
+                ..
+            } if out.dest.is_client() => MessageBody::read_ok {
+                in_reply_to,
+                value: messages.clone(),
+            },
+            _ => {
+                tx.send(Envelope::Node(out))?;
+                continue;
+            }
+        };
+        tx.send(Envelope::Client(Message {
+            src: out.src,
+            dest: out.dest,
+            body,
+        }))?;
+    }
+    Ok(())
+}
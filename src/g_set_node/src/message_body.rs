@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+// Maelstrom's g-set client protocol. Only clients speak it, init and
+// everything between nodes is broadcast_node's MessageBody.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[allow(non_camel_case_types)]
+pub enum MessageBody {
+    add { msg_id: u32, element: u32 },
+    add_ok { in_reply_to: u32 },
+    read { msg_id: u32 },
+    read_ok { in_reply_to: u32, value: Vec<u32> },
+}
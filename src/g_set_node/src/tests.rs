@@ -0,0 +1,118 @@
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+use crate::{decode_line, Envelope, GSetNode, Message, MessageBody};
+use broadcast_node::{Config, MockClock};
+
+// ── Helpers ──────────────────────────────────────────────────────────────────
+
+fn make_node(id: &str) -> GSetNode {
+    let mut node = GSetNode::new(Config::default());
+    let init = format!(
+        r#"{{"src":"c0","dest":"{id}","body":{{"type":"init","msg_id":1,"node_id":"{id}","node_ids":["n1","n2"]}}}}"#
+    );
+    let (tx, _rx) = channel();
+    node.next(decode_line(&init, usize::MAX).unwrap(), tx)
+        .unwrap();
+    node
+}
+
+fn channel() -> (Sender<Envelope>, Receiver<Envelope>) {
+    mpsc::channel()
+}
+
+fn client(body: MessageBody) -> Envelope {
+    Envelope::Client(Message {
+        src: "c1".into(),
+        dest: "n1".into(),
+        body,
+    })
+}
+
+// ── Decoding ─────────────────────────────────────────────────────────────────
+
+#[test]
+fn client_requests_are_g_set_and_the_rest_is_broadcast() {
+    let add = r#"{"src":"c1","dest":"n1","body":{"type":"add","msg_id":1,"element":4}}"#;
+    assert_eq!(
+        decode_line(add, usize::MAX).unwrap(),
+        client(MessageBody::add {
+            msg_id: 1,
+            element: 4
+        })
+    );
+
+    // a peer's read is broadcast_node's, not a client read
+    let read = r#"{"src":"n2","dest":"n1","body":{"type":"read","msg_id":1}}"#;
+    assert!(matches!(
+        decode_line(read, usize::MAX).unwrap(),
+        Envelope::Node(_)
+    ));
+    assert!(decode_line(add, 10).is_err(), "size limit still applies");
+}
+
+// ── Add / read ───────────────────────────────────────────────────────────────
+
+#[test]
+fn add_then_read_returns_the_set() {
+    let mut node = make_node("n1");
+    let (tx, rx) = channel();
+    for (msg_id, element) in [(1, 4), (2, 4), (3, 7)] {
+        node.next(client(MessageBody::add { msg_id, element }), tx.clone())
+            .unwrap();
+    }
+    node.next(client(MessageBody::read { msg_id: 4 }), tx)
+        .unwrap();
+
+    let replies: Vec<Envelope> = rx.try_iter().collect();
+    assert_eq!(
+        replies[0],
+        Envelope::Client(Message {
+            src: "n1".into(),
+            dest: "c1".into(),
+            body: MessageBody::add_ok { in_reply_to: 1 },
+        })
+    );
+    match &replies[3] {
+        Envelope::Client(Message {
+            body: MessageBody::read_ok { in_reply_to, value },
+            ..
+        }) => {
+            assert_eq!(*in_reply_to, 4);
+            let mut value = value.clone();
+            value.sort();
+            assert_eq!(value, vec![4, 7]);
+        }
+        other => panic!("expected read_ok, got {:?}", other),
+    }
+    let line = serde_json::to_string(&replies[3]).unwrap();
+    assert!(line.contains(r#""type":"read_ok""#) && line.contains(r#""value":["#));
+}
+
+// ── Replication ──────────────────────────────────────────────────────────────
+
+#[test]
+fn adds_reach_the_other_replica_through_gossip() {
+    let clock = MockClock::new();
+    let mut n1 = make_node("n1").with_clock(Arc::new(clock.clone()));
+    let mut n2 = make_node("n2");
+    let (tx, rx) = channel();
+    let add = MessageBody::add {
+        msg_id: 1,
+        element: 9,
+    };
+    n1.next(client(add), tx.clone()).unwrap();
+
+    clock.advance(Config::default().fanout_interval);
+    n1.tick(tx.clone()).unwrap();
+    let gossip: Vec<Envelope> = rx
+        .try_iter()
+        .filter(|out| matches!(out, Envelope::Node(msg) if msg.dest == "n2"))
+        .collect();
+    assert!(!gossip.is_empty());
+    for msg in gossip {
+        n2.next(msg, tx.clone()).unwrap();
+    }
+    assert!(n2.node().store().contains(&9));
+}
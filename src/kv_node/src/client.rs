@@ -0,0 +1,225 @@
+use anyhow::Result;
+use node_common::{Clock, ErrorCode, NodeId, PendingRpcs, SystemClock};
+use serde_json::{json, Value};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Message, MessageBody};
+
+pub type Update = Arc<dyn Fn(u32) -> u32 + Send + Sync>;
+
+// How a cas_update ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CasOutcome {
+    // the key now holds this value, written by us
+    Updated(u32),
+    // every attempt lost its cas to another writer
+    Contended { attempts: usize },
+    // the service answered with an error other than PreconditionFailed, or
+    // with a value that isn't a u32
+    Failed { code: u32, text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finished {
+    // the id cas_update returned
+    pub op: u32,
+    pub key: Value,
+    pub outcome: CasOutcome,
+}
+
+// One cas_update in progress, parked under the msg_id of its current request
+#[derive(Clone)]
+pub struct CasUpdate {
+    op: u32,
+    key: Value,
+    update: Update,
+    // cas attempts sent so far
+    attempts: usize,
+    // the value our cas writes while it's in flight, None while reading
+    writing: Option<u32>,
+}
+
+// Talks to a Maelstrom kv service (lin-kv, seq-kv) on a node's behalf.
+// Requests are asynchronous, replies come back through the node's input
+// and have to be handed to handle_reply, finished updates are returned
+// from there (or from tick when the service stops answering).
+pub struct KvClient {
+    // our node id, requests are sent from it
+    pub id: NodeId,
+    pub service: NodeId,
+    pub pending: PendingRpcs<CasUpdate>,
+    pub clock: Arc<dyn Clock>,
+    pub rpc_timeout: Duration,
+    // cas attempts per update before giving up as Contended
+    pub max_attempts: usize,
+    next_msg_id: u32,
+}
+
+impl KvClient {
+    pub fn new(id: impl Into<NodeId>, service: impl Into<NodeId>) -> Self {
+        Self {
+            id: id.into(),
+            service: service.into(),
+            pending: PendingRpcs::new(),
+            clock: Arc::new(SystemClock),
+            rpc_timeout: Duration::from_millis(1000),
+            max_attempts: 10,
+            next_msg_id: 0,
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    // Read-modify-write of an integer key: reads it, cas-es it from what was
+    // read to f(read), and starts over from the read whenever the cas fails
+    // with PreconditionFailed. A missing key reads as 0 and is created by the
+    // cas. Returns the id the Finished for this update will carry.
+    pub fn cas_update(
+        &mut self,
+        key: Value,
+        f: impl Fn(u32) -> u32 + Send + Sync + 'static,
+        tx: Sender<Message>,
+    ) -> Result<u32> {
+        let op = self.next_msg_id();
+        let update = CasUpdate {
+            op,
+            key,
+            update: Arc::new(f),
+            attempts: 0,
+            writing: None,
+        };
+        self.send_read(update, tx)?;
+        Ok(op)
+    }
+
+    // Feeds a reply from the service. Replies matching no update (late ones
+    // after a timeout) are ignored.
+    pub fn handle_reply(&mut self, msg: Message, tx: Sender<Message>) -> Result<Option<Finished>> {
+        let Some(in_reply_to) = msg.body.in_reply_to() else {
+            return Ok(None);
+        };
+        let Some(mut update) = self.pending.remove(in_reply_to) else {
+            return Ok(None);
+        };
+        match (msg.body, update.writing) {
+            (MessageBody::read_ok { value, .. }, None) => match as_u32(&value) {
+                Some(current) => self.send_cas(update, current, false, tx),
+                None => Ok(Some(failed(
+                    update,
+                    ErrorCode::MalformedRequest,
+                    format!("{value} is not a u32"),
+                ))),
+            },
+            (MessageBody::error { code, .. }, None)
+                if code == ErrorCode::KeyDoesNotExist.code() =>
+            {
+                self.send_cas(update, 0, true, tx)
+            }
+            (MessageBody::cas_ok { .. }, Some(value)) => {
+                Ok(Some(finished(update, CasOutcome::Updated(value))))
+            }
+            (MessageBody::error { code, .. }, Some(_))
+                if code == ErrorCode::PreconditionFailed.code() =>
+            {
+                if update.attempts >= self.max_attempts {
+                    let attempts = update.attempts;
+                    return Ok(Some(finished(update, CasOutcome::Contended { attempts })));
+                }
+                update.writing = None;
+                self.send_read(update, tx)?;
+                Ok(None)
+            }
+            (MessageBody::error { code, text, .. }, _) => {
+                Ok(Some(finished(update, CasOutcome::Failed { code, text })))
+            }
+            (body, _) => anyhow::bail!("unexpected {body:?} for cas_update of {}", update.key),
+        }
+    }
+
+    // Gives up on updates whose current request went unanswered
+    pub fn tick(&mut self) -> Vec<Finished> {
+        let now = self.clock.now();
+        self.pending
+            .take_expired(now)
+            .into_iter()
+            .map(|(_, update)| {
+                let text = format!("{} didn't answer", self.service);
+                failed(update, ErrorCode::Timeout, text)
+            })
+            .collect()
+    }
+
+    fn send_read(&mut self, update: CasUpdate, tx: Sender<Message>) -> Result<()> {
+        let msg_id = self.next_msg_id();
+        let body = MessageBody::read {
+            msg_id,
+            key: update.key.clone(),
+        };
+        self.park(msg_id, update);
+        self.send(body, tx)
+    }
+
+    fn send_cas(
+        &mut self,
+        mut update: CasUpdate,
+        from: u32,
+        create_if_not_exists: bool,
+        tx: Sender<Message>,
+    ) -> Result<Option<Finished>> {
+        let msg_id = self.next_msg_id();
+        let to = (update.update)(from);
+        let body = MessageBody::cas {
+            msg_id,
+            key: update.key.clone(),
+            from: json!(from),
+            to: json!(to),
+            create_if_not_exists,
+        };
+        update.attempts += 1;
+        update.writing = Some(to);
+        self.park(msg_id, update);
+        self.send(body, tx)?;
+        Ok(None)
+    }
+
+    fn park(&mut self, msg_id: u32, update: CasUpdate) {
+        let deadline = self.clock.now() + self.rpc_timeout;
+        self.pending.insert(msg_id, update, deadline);
+    }
+
+    fn send(&self, body: MessageBody, tx: Sender<Message>) -> Result<()> {
+        Message {
+            src: self.id.clone(),
+            dest: self.service.clone(),
+            body,
+        }
+        .send(tx)
+    }
+
+    fn next_msg_id(&mut self) -> u32 {
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        self.next_msg_id
+    }
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|n| u32::try_from(n).ok())
+}
+
+fn finished(update: CasUpdate, outcome: CasOutcome) -> Finished {
+    Finished {
+        op: update.op,
+        key: update.key,
+        outcome,
+    }
+}
+
+fn failed(update: CasUpdate, code: ErrorCode, text: String) -> Finished {
+    let code = code.code();
+    finished(update, CasOutcome::Failed { code, text })
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use node_common::{ErrorCode, NodeId};
+use std::sync::mpsc::Sender;
+
+use crate::{KvNode, Message, MessageBody};
+
+// A client request we handed to the key's owner
+#[derive(Debug, Clone)]
+pub struct Forwarded {
+    pub client: NodeId,
+    pub client_msg_id: u32,
+    pub owner: NodeId,
+}
+
+// Sends the request on to its owner under a fresh msg_id. The client's
+// msg_id is remembered so the relayed reply still answers it.
+pub fn forward_request(node: &mut KvNode, msg: Message, tx: Sender<Message>) -> Result<()> {
+    let (Some(owner), Some(client_msg_id)) = (node.owner_elsewhere(&msg), msg.body.msg_id()) else {
+        return Ok(());
+    };
+    let rpc_id = node.next_msg_id();
+    let deadline = node.clock.now() + node.rpc_timeout;
+    node.forwarded.insert(
+        rpc_id,
+        Forwarded {
+            client: msg.src.clone(),
+            client_msg_id,
+            owner: owner.clone(),
+        },
+        deadline,
+    );
+    let mut body = msg.body.clone();
+    body.set_msg_id(rpc_id);
+    msg.into_message(body, owner).send(tx)
+}
+
+// A reply from an owner, passed back to the client it was forwarded for
+pub fn relay_reply(node: &mut KvNode, msg: Message, tx: Sender<Message>) -> Result<()> {
+    let Some(in_reply_to) = msg.body.in_reply_to() else {
+        return Ok(());
+    };
+    let Some(forwarded) = node.forwarded.remove(in_reply_to) else {
+        anyhow::bail!(
+            "reply {} from {} matches no forwarded request",
+            in_reply_to,
+            msg.src
+        );
+    };
+    let mut body = msg.body.clone();
+    body.set_in_reply_to(forwarded.client_msg_id);
+    msg.into_message(body, forwarded.client).send(tx)
+}
+
+pub fn expire_forwarded(node: &mut KvNode, tx: Sender<Message>) -> Result<()> {
+    let now = node.clock.now();
+    for (_, forwarded) in node.forwarded.take_expired(now) {
+        Message {
+            src: node.id.clone(),
+            dest: forwarded.client,
+            body: MessageBody::error {
+                in_reply_to: forwarded.client_msg_id,
+                code: ErrorCode::Timeout.code(),
+                text: format!("owner {} didn't answer", forwarded.owner),
+            },
+        }
+        .send(tx.clone())?;
+    }
+    Ok(())
+}
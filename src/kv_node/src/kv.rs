@@ -0,0 +1,86 @@
+use anyhow::Result;
+use node_common::ErrorCode;
+use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::sync::mpsc::Sender;
+
+use crate::{KvNode, Message, MessageBody};
+
+// Keys are compared by their JSON text, so 1 and "1" are different keys
+pub(crate) fn key_of(key: &Value) -> String {
+    key.to_string()
+}
+
+pub fn handle_read_message(node: &mut KvNode, msg: Message, tx: Sender<Message>) -> Result<()> {
+    if let MessageBody::read { msg_id, ref key } = msg.body {
+        let body = match node.store.get(&key_of(key)) {
+            Some(value) => MessageBody::read_ok {
+                in_reply_to: msg_id,
+                value: value.clone(),
+            },
+            None => error(msg_id, ErrorCode::KeyDoesNotExist, format!("no key {key}")),
+        };
+        msg.into_reply(body).send(tx)?;
+    }
+    Ok(())
+}
+
+pub fn handle_write_message(node: &mut KvNode, msg: Message, tx: Sender<Message>) -> Result<()> {
+    if let MessageBody::write {
+        msg_id,
+        ref key,
+        ref value,
+    } = msg.body
+    {
+        node.store.insert(key_of(key), value.clone());
+        msg.into_reply(MessageBody::write_ok {
+            in_reply_to: msg_id,
+        })
+        .send(tx)?;
+    }
+    Ok(())
+}
+
+// Compare and swap in a single map lookup, so of two cas ops expecting the
+// same `from` exactly one wins and the other gets PreconditionFailed
+pub fn handle_cas_message(node: &mut KvNode, msg: Message, tx: Sender<Message>) -> Result<()> {
+    if let MessageBody::cas {
+        msg_id,
+        ref key,
+        ref from,
+        ref to,
+        create_if_not_exists,
+    } = msg.body
+    {
+        let body = match node.store.entry(key_of(key)) {
+            Entry::Occupied(mut current) if current.get() == from => {
+                current.insert(to.clone());
+                MessageBody::cas_ok {
+                    in_reply_to: msg_id,
+                }
+            }
+            Entry::Occupied(current) => error(
+                msg_id,
+                ErrorCode::PreconditionFailed,
+                format!("key {key} is {}, not {from}", current.get()),
+            ),
+            Entry::Vacant(slot) if create_if_not_exists => {
+                slot.insert(to.clone());
+                MessageBody::cas_ok {
+                    in_reply_to: msg_id,
+                }
+            }
+            Entry::Vacant(_) => error(msg_id, ErrorCode::KeyDoesNotExist, format!("no key {key}")),
+        };
+        msg.into_reply(body).send(tx)?;
+    }
+    Ok(())
+}
+
+fn error(in_reply_to: u32, code: ErrorCode, text: String) -> MessageBody {
+    MessageBody::error {
+        in_reply_to,
+        code: code.code(),
+        text,
+    }
+}
@@ -0,0 +1,121 @@
+mod client;
+mod forward;
+mod kv;
+mod message_body;
+mod routing;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use node_common::{canonical_members, Clock, NodeId, PendingRpcs, SystemClock};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::client::{CasOutcome, CasUpdate, Finished, KvClient};
+pub use crate::forward::Forwarded;
+pub use crate::message_body::MessageBody;
+pub type Message = node_common::Message<MessageBody>;
+
+// A single lin-kv replica. Every request is handled to completion before the
+// next one is read, which is what makes cas linearizable here. A client
+// request for a key another node owns (see home_node) is forwarded there and
+// the owner's reply relayed back, so each key is only ever written on one
+// node.
+#[derive(Debug, Clone)]
+pub struct KvNode {
+    pub id: NodeId,
+    pub node_ids: Vec<NodeId>,
+    pub store: HashMap<String, Value>,
+    // client requests forwarded to their owner, keyed by the forward's msg_id
+    pub forwarded: PendingRpcs<Forwarded>,
+    pub clock: Arc<dyn Clock>,
+    // how long an owner gets to answer before the client is sent a timeout
+    pub rpc_timeout: Duration,
+    next_msg_id: u32,
+}
+
+impl Default for KvNode {
+    fn default() -> Self {
+        Self {
+            id: NodeId::default(),
+            node_ids: Vec::new(),
+            store: HashMap::new(),
+            forwarded: PendingRpcs::new(),
+            clock: Arc::new(SystemClock),
+            rpc_timeout: Duration::from_millis(1000),
+            next_msg_id: 0,
+        }
+    }
+}
+
+impl KvNode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn next(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        match msg.body {
+            MessageBody::init { .. } => self.handle_init_message(msg, tx),
+            MessageBody::read { .. } | MessageBody::write { .. } | MessageBody::cas { .. }
+                if self.owner_elsewhere(&msg).is_some() =>
+            {
+                forward::forward_request(self, msg, tx)
+            }
+            MessageBody::read { .. } => kv::handle_read_message(self, msg, tx),
+            MessageBody::write { .. } => kv::handle_write_message(self, msg, tx),
+            MessageBody::cas { .. } => kv::handle_cas_message(self, msg, tx),
+            MessageBody::read_ok { .. }
+            | MessageBody::write_ok { .. }
+            | MessageBody::cas_ok { .. }
+            | MessageBody::error { .. } => forward::relay_reply(self, msg, tx),
+            MessageBody::init_ok { .. } => {
+                anyhow::bail!("no handler for {:?} from {}", msg.body, msg.src)
+            }
+        }
+    }
+
+    // Answers forwards whose owner didn't reply in time
+    pub fn tick(&mut self, tx: Sender<Message>) -> Result<()> {
+        forward::expire_forwarded(self, tx)
+    }
+
+    // The owner of a client request's key, unless that's us. Requests from
+    // other nodes are always served here, even if our views of the
+    // membership disagree, so a request is forwarded at most once.
+    pub(crate) fn owner_elsewhere(&self, msg: &Message) -> Option<NodeId> {
+        if self.node_ids.contains(&msg.src) {
+            return None;
+        }
+        let owner = self.home_node(&kv::key_of(msg.body.key()?));
+        (owner != self.id).then_some(owner)
+    }
+
+    pub(crate) fn next_msg_id(&mut self) -> u32 {
+        self.next_msg_id = self.next_msg_id.wrapping_add(1);
+        self.next_msg_id
+    }
+
+    fn handle_init_message(&mut self, msg: Message, tx: Sender<Message>) -> Result<()> {
+        if let MessageBody::init {
+            msg_id,
+            ref node_id,
+            ref node_ids,
+        } = msg.body
+        {
+            (self.id, self.node_ids) = (node_id.clone(), canonical_members(node_ids.clone()));
+            msg.into_reply(MessageBody::init_ok {
+                in_reply_to: msg_id,
+            })
+            .send(tx)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use node_common::NodeId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Maelstrom's lin-kv protocol. Keys and values are arbitrary JSON, in
+// practice the workload sends integers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[allow(non_camel_case_types)]
+pub enum MessageBody {
+    init {
+        msg_id: u32,
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+    },
+    init_ok {
+        in_reply_to: u32,
+    },
+    read {
+        msg_id: u32,
+        key: Value,
+    },
+    read_ok {
+        in_reply_to: u32,
+        value: Value,
+    },
+    write {
+        msg_id: u32,
+        key: Value,
+        value: Value,
+    },
+    write_ok {
+        in_reply_to: u32,
+    },
+    cas {
+        msg_id: u32,
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    cas_ok {
+        in_reply_to: u32,
+    },
+    error {
+        in_reply_to: u32,
+        code: u32,
+        text: String,
+    },
+}
+
+impl MessageBody {
+    // The key a client request is about
+    pub fn key(&self) -> Option<&Value> {
+        match self {
+            MessageBody::read { key, .. }
+            | MessageBody::write { key, .. }
+            | MessageBody::cas { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+
+    pub fn set_msg_id(&mut self, id: u32) {
+        match self {
+            MessageBody::init { msg_id, .. }
+            | MessageBody::read { msg_id, .. }
+            | MessageBody::write { msg_id, .. }
+            | MessageBody::cas { msg_id, .. } => *msg_id = id,
+            _ => {}
+        }
+    }
+
+    pub fn in_reply_to(&self) -> Option<u32> {
+        match self {
+            MessageBody::init_ok { in_reply_to }
+            | MessageBody::read_ok { in_reply_to, .. }
+            | MessageBody::write_ok { in_reply_to }
+            | MessageBody::cas_ok { in_reply_to }
+            | MessageBody::error { in_reply_to, .. } => Some(*in_reply_to),
+            _ => None,
+        }
+    }
+
+    pub fn set_in_reply_to(&mut self, id: u32) {
+        match self {
+            MessageBody::init_ok { in_reply_to }
+            | MessageBody::read_ok { in_reply_to, .. }
+            | MessageBody::write_ok { in_reply_to }
+            | MessageBody::cas_ok { in_reply_to }
+            | MessageBody::error { in_reply_to, .. } => *in_reply_to = id,
+            _ => {}
+        }
+    }
+
+    pub fn msg_id(&self) -> Option<u32> {
+        match self {
+            MessageBody::init { msg_id, .. }
+            | MessageBody::read { msg_id, .. }
+            | MessageBody::write { msg_id, .. }
+            | MessageBody::cas { msg_id, .. } => Some(*msg_id),
+            _ => None,
+        }
+    }
+}
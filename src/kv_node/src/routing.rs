@@ -0,0 +1,30 @@
+use node_common::NodeId;
+
+use crate::KvNode;
+
+// Points each node gets on the ring. More points spread keys more evenly,
+// 16 is plenty for the handful of nodes Maelstrom runs.
+const VNODES: u32 = 16;
+
+// FNV-1a, hand rolled because every node has to agree on the ring and std's
+// hasher may differ between builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl KvNode {
+    // The node owning `key` on a consistent hash ring over node_ids. Adding
+    // or removing a node only moves the keys that land on its points, every
+    // other key keeps its owner. Before init we own everything.
+    pub fn home_node(&self, key: &str) -> NodeId {
+        let point = fnv1a(key.as_bytes());
+        self.node_ids
+            .iter()
+            .flat_map(|id| (0..VNODES).map(move |v| (fnv1a(format!("{id}#{v}").as_bytes()), id)))
+            .min_by_key(|(vnode, id)| (vnode.wrapping_sub(point), *id))
+            .map(|(_, id)| id.clone())
+            .unwrap_or_else(|| self.id.clone())
+    }
+}
@@ -0,0 +1,428 @@
+use node_common::{MockClock, NodeId};
+use serde_json::json;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+
+use crate::{CasOutcome, Finished, KvClient, KvNode, Message, MessageBody};
+
+// ── Helpers ──────────────────────────────────────────────────────────────────
+
+// The only replica, so every key is local
+fn make_node() -> KvNode {
+    KvNode {
+        id: "n1".into(),
+        node_ids: vec!["n1".into()],
+        ..KvNode::new()
+    }
+}
+
+fn channel() -> (Sender<Message>, Receiver<Message>) {
+    mpsc::channel()
+}
+
+fn msg(src: &str, body: MessageBody) -> Message {
+    Message {
+        src: src.into(),
+        dest: "n1".into(),
+        body,
+    }
+}
+
+fn cas(msg_id: u32, from: u32, to: u32) -> MessageBody {
+    MessageBody::cas {
+        msg_id,
+        key: json!(0),
+        from: json!(from),
+        to: json!(to),
+        create_if_not_exists: false,
+    }
+}
+
+// ── Read / write ─────────────────────────────────────────────────────────────
+
+#[test]
+fn read_of_missing_key_is_key_does_not_exist() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let read = MessageBody::read {
+        msg_id: 1,
+        key: json!(3),
+    };
+    node.next(msg("c1", read), tx).unwrap();
+    assert!(matches!(
+        rx.try_recv().unwrap().body,
+        MessageBody::error {
+            in_reply_to: 1,
+            code: 20,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn write_then_read_returns_value() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    let write = MessageBody::write {
+        msg_id: 1,
+        key: json!(3),
+        value: json!(9),
+    };
+    node.next(msg("c1", write), tx.clone()).unwrap();
+    let read = MessageBody::read {
+        msg_id: 2,
+        key: json!(3),
+    };
+    node.next(msg("c1", read), tx).unwrap();
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::write_ok { in_reply_to: 1 }
+    ));
+    assert_eq!(
+        replies[1].body,
+        MessageBody::read_ok {
+            in_reply_to: 2,
+            value: json!(9)
+        }
+    );
+}
+
+// ── Cas ──────────────────────────────────────────────────────────────────────
+
+#[test]
+fn concurrent_cas_on_same_key_has_one_winner() {
+    let mut node = make_node();
+    node.store.insert("0".to_string(), json!(1));
+    let (tx, rx) = channel();
+
+    // both clients read 1 and try to move it on
+    node.next(msg("c1", cas(10, 1, 2)), tx.clone()).unwrap();
+    node.next(msg("c2", cas(20, 1, 3)), tx).unwrap();
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert_eq!(replies[0].dest, "c1");
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::cas_ok { in_reply_to: 10 }
+    ));
+    assert_eq!(replies[1].dest, "c2");
+    assert!(matches!(
+        replies[1].body,
+        MessageBody::error {
+            in_reply_to: 20,
+            code: 22,
+            ..
+        }
+    ));
+    assert_eq!(node.store["0"], json!(2));
+}
+
+#[test]
+fn cas_on_missing_key_creates_only_when_asked() {
+    let mut node = make_node();
+    let (tx, rx) = channel();
+    node.next(msg("c1", cas(1, 0, 5)), tx.clone()).unwrap();
+    let create = MessageBody::cas {
+        msg_id: 2,
+        key: json!(0),
+        from: json!(0),
+        to: json!(5),
+        create_if_not_exists: true,
+    };
+    node.next(msg("c1", create), tx).unwrap();
+
+    let replies: Vec<Message> = rx.try_iter().collect();
+    assert!(matches!(
+        replies[0].body,
+        MessageBody::error { code: 20, .. }
+    ));
+    assert!(matches!(
+        replies[1].body,
+        MessageBody::cas_ok { in_reply_to: 2 }
+    ));
+    assert_eq!(node.store["0"], json!(5));
+}
+
+// ── Routing ──────────────────────────────────────────────────────────────────
+
+fn owners(node_ids: &[&str]) -> Vec<NodeId> {
+    let node = KvNode {
+        id: "n1".into(),
+        node_ids: node_ids.iter().map(|id| NodeId::from(*id)).collect(),
+        ..KvNode::new()
+    };
+    (0..1000)
+        .map(|key| node.home_node(&key.to_string()))
+        .collect()
+}
+
+#[test]
+fn home_node_only_moves_keys_of_the_changed_node() {
+    let before = owners(&["n1", "n2", "n3", "n4"]);
+    assert_eq!(
+        before,
+        owners(&["n4", "n3", "n2", "n1"]),
+        "order doesn't matter"
+    );
+    for id in ["n1", "n2", "n3", "n4"] {
+        assert!(
+            before.iter().any(|owner| owner == id),
+            "{id} owns some keys"
+        );
+    }
+
+    let joined = owners(&["n1", "n2", "n3", "n4", "n5"]);
+    for (old, new) in before.iter().zip(&joined) {
+        assert!(new == old || new == "n5", "key moved from {old} to {new}");
+    }
+
+    let left = owners(&["n1", "n2", "n4"]);
+    for (old, new) in before.iter().zip(&left) {
+        assert!(new == old || old == "n3", "key moved from {old} to {new}");
+    }
+}
+
+#[test]
+fn init_canonicalizes_node_ids_so_every_node_builds_the_same_ring() {
+    let init = |node_id: &str, node_ids: [&str; 4]| {
+        let mut node = KvNode::new();
+        let (tx, _rx) = channel();
+        let init = MessageBody::init {
+            msg_id: 1,
+            node_id: node_id.into(),
+            node_ids: node_ids.into_iter().map(NodeId::from).collect(),
+        };
+        node.next(msg("c0", init), tx).unwrap();
+        node
+    };
+    let a = init("n1", ["n3", "n1", "n4", "n2"]);
+    let b = init("n2", ["n2", "n4", "n1", "n3"]);
+
+    assert_eq!(a.node_ids, b.node_ids);
+    assert_eq!(a.node_ids, ["n1", "n2", "n3", "n4"]);
+    for key in 0..1000 {
+        let key = key.to_string();
+        assert_eq!(a.home_node(&key), b.home_node(&key), "key {key}");
+    }
+}
+
+// ── Forwarding ───────────────────────────────────────────────────────────────
+
+fn cluster_node() -> KvNode {
+    KvNode {
+        id: "n1".into(),
+        node_ids: vec!["n1".into(), "n2".into()],
+        ..KvNode::new()
+    }
+}
+
+// Some key n2 owns
+fn remote_key(node: &KvNode) -> u32 {
+    (0..)
+        .find(|key: &u32| node.home_node(&json!(key).to_string()) == "n2")
+        .unwrap()
+}
+
+#[test]
+fn request_for_remote_key_is_forwarded_and_reply_relayed() {
+    let mut node = cluster_node();
+    let key = remote_key(&node);
+    let (tx, rx) = channel();
+    let read = MessageBody::read {
+        msg_id: 7,
+        key: json!(key),
+    };
+    node.next(msg("c1", read), tx.clone()).unwrap();
+
+    let forward = rx.try_recv().unwrap();
+    assert_eq!((forward.src.as_str(), forward.dest.as_str()), ("n1", "n2"));
+    let rpc_id = match forward.body {
+        MessageBody::read { msg_id, .. } => msg_id,
+        other => panic!("expected forwarded read, got {:?}", other),
+    };
+
+    let reply = MessageBody::read_ok {
+        in_reply_to: rpc_id,
+        value: json!(4),
+    };
+    node.next(msg("n2", reply), tx).unwrap();
+    let relayed = rx.try_recv().unwrap();
+    assert_eq!(relayed.dest, "c1");
+    assert_eq!(
+        relayed.body,
+        MessageBody::read_ok {
+            in_reply_to: 7,
+            value: json!(4)
+        }
+    );
+    assert!(node.forwarded.is_empty());
+}
+
+#[test]
+fn forwarded_request_from_a_node_is_served_locally() {
+    let mut node = cluster_node();
+    let key = remote_key(&node);
+    let (tx, rx) = channel();
+    let write = MessageBody::write {
+        msg_id: 1,
+        key: json!(key),
+        value: json!(2),
+    };
+    node.next(msg("n2", write), tx).unwrap();
+
+    assert!(matches!(
+        rx.try_recv().unwrap().body,
+        MessageBody::write_ok { in_reply_to: 1 }
+    ));
+}
+
+#[test]
+fn silent_owner_times_out_with_error_to_client() {
+    let clock = MockClock::new();
+    let mut node = cluster_node().with_clock(Arc::new(clock.clone()));
+    let key = remote_key(&node);
+    let (tx, rx) = channel();
+    let cas = MessageBody::cas {
+        msg_id: 5,
+        key: json!(key),
+        from: json!(1),
+        to: json!(2),
+        create_if_not_exists: false,
+    };
+    node.next(msg("c1", cas), tx.clone()).unwrap();
+    assert_eq!(rx.try_recv().unwrap().dest, "n2");
+
+    node.tick(tx.clone()).unwrap();
+    assert!(rx.try_recv().is_err(), "not due yet");
+    clock.advance(node.rpc_timeout);
+    node.tick(tx).unwrap();
+
+    let timeout = rx.try_recv().unwrap();
+    assert_eq!(timeout.dest, "c1");
+    assert!(matches!(
+        timeout.body,
+        MessageBody::error {
+            in_reply_to: 5,
+            code: 0,
+            ..
+        }
+    ));
+}
+
+// ── Cas update client ────────────────────────────────────────────────────────
+
+fn lin_kv() -> KvNode {
+    KvNode {
+        id: "lin-kv".into(),
+        node_ids: vec!["lin-kv".into()],
+        ..KvNode::new()
+    }
+}
+
+// Delivers in lockstep rounds: every request sent in a round reaches the
+// service before any client sees a reply, so clients that read together
+// also race their cas
+fn run(
+    service: &mut KvNode,
+    clients: &mut [KvClient],
+    tx: Sender<Message>,
+    rx: &Receiver<Message>,
+) -> Vec<Finished> {
+    let mut finished = Vec::new();
+    let mut round: Vec<Message> = rx.try_iter().collect();
+    while !round.is_empty() {
+        for msg in round {
+            if msg.dest == service.id {
+                service.next(msg, tx.clone()).unwrap();
+            } else {
+                let client = clients.iter_mut().find(|c| c.id == msg.dest).unwrap();
+                finished.extend(client.handle_reply(msg, tx.clone()).unwrap());
+            }
+        }
+        round = rx.try_iter().collect();
+    }
+    finished
+}
+
+#[test]
+fn contended_cas_updates_retry_until_every_increment_lands() {
+    let mut service = lin_kv();
+    let mut clients: Vec<KvClient> = ["n1", "n2", "n3"]
+        .into_iter()
+        .map(|id| KvClient::new(id, "lin-kv"))
+        .collect();
+    let (tx, rx) = channel();
+
+    let mut finished = Vec::new();
+    for _ in 0..2 {
+        for client in &mut clients {
+            client.cas_update(json!(0), |n| n + 1, tx.clone()).unwrap();
+        }
+        finished.extend(run(&mut service, &mut clients, tx.clone(), &rx));
+    }
+
+    let mut written: Vec<u32> = finished
+        .iter()
+        .map(|done| match done.outcome {
+            CasOutcome::Updated(value) => value,
+            ref other => panic!("expected an update, got {other:?}"),
+        })
+        .collect();
+    written.sort();
+    assert_eq!(written, vec![1, 2, 3, 4, 5, 6], "no increment was lost");
+    assert_eq!(service.store["0"], json!(6));
+    assert!(clients.iter().all(|c| c.pending.is_empty()));
+}
+
+#[test]
+fn cas_update_gives_up_after_max_attempts() {
+    let mut service = lin_kv();
+    service.store.insert("0".to_string(), json!(5));
+    let mut clients: Vec<KvClient> = ["n1", "n2"]
+        .into_iter()
+        .map(|id| {
+            let mut client = KvClient::new(id, "lin-kv");
+            client.max_attempts = 1;
+            client
+        })
+        .collect();
+    let (tx, rx) = channel();
+    for client in &mut clients {
+        client.cas_update(json!(0), |n| n * 2, tx.clone()).unwrap();
+    }
+
+    let mut outcomes: Vec<CasOutcome> = run(&mut service, &mut clients, tx, &rx)
+        .into_iter()
+        .map(|done| done.outcome)
+        .collect();
+    outcomes.sort_by_key(|outcome| format!("{outcome:?}"));
+    assert_eq!(
+        outcomes,
+        vec![
+            CasOutcome::Contended { attempts: 1 },
+            CasOutcome::Updated(10)
+        ]
+    );
+    assert_eq!(service.store["0"], json!(10));
+}
+
+#[test]
+fn unanswered_cas_update_times_out() {
+    let clock = MockClock::new();
+    let mut client = KvClient::new("n1", "lin-kv").with_clock(Arc::new(clock.clone()));
+    let (tx, _rx) = channel();
+    let op = client.cas_update(json!(0), |n| n + 1, tx).unwrap();
+
+    assert!(client.tick().is_empty(), "not due yet");
+    clock.advance(client.rpc_timeout);
+    let expired = client.tick();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].op, op);
+    assert!(matches!(
+        expired[0].outcome,
+        CasOutcome::Failed { code: 0, .. }
+    ));
+}
@@ -1,99 +1,842 @@
 #[cfg(all(feature = "broadcast", feature = "g-counter", not(debug_assertions)))]
 compile_error!("select only one workload feature");
+#[cfg(all(feature = "broadcast", feature = "lin-kv", not(debug_assertions)))]
+compile_error!("select only one workload feature");
+#[cfg(all(feature = "g-counter", feature = "lin-kv", not(debug_assertions)))]
+compile_error!("select only one workload feature");
+#[cfg(all(feature = "broadcast", feature = "g-set", not(debug_assertions)))]
+compile_error!("select only one workload feature");
+#[cfg(all(feature = "g-counter", feature = "g-set", not(debug_assertions)))]
+compile_error!("select only one workload feature");
+#[cfg(all(feature = "lin-kv", feature = "g-set", not(debug_assertions)))]
+compile_error!("select only one workload feature");
 
 #[cfg(feature = "broadcast")]
 use anyhow::Context;
 #[cfg(feature = "broadcast")]
-use broadcast_node::{BroadcastNodeTrait, Message, Node};
-#[cfg(feature = "broadcast")]
-use serde_path_to_error::deserialize;
+use broadcast_node::{
+    decode_line, selftest, BoundedLines, Config, Host, JsonCodec, Message, NodeId, OutboundQueue,
+    OversizedMessage, PeerCodec, Transport, WireCodec,
+};
 #[cfg(feature = "broadcast")]
 use std::{
-    io::{stdin, stdout, BufRead, Lines, Write},
-    sync::mpsc,
+    collections::HashMap,
+    io::{self, stdin, stdout, BufReader, BufWriter, Write},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
+// Largest UDP payload over IPv4
+#[cfg(feature = "broadcast")]
+const MAX_DATAGRAM_BYTES: usize = 65_507;
+
+// Most values in one sync_ok over UDP. A u32 takes at most 11 bytes with its
+// comma, which leaves 8KB of the datagram for the envelope and tombstones.
+#[cfg(feature = "broadcast")]
+const DATAGRAM_SYNC_LIMIT: usize = (MAX_DATAGRAM_BYTES - 8 * 1024) / 11;
+
 #[cfg(feature = "broadcast")]
 fn main() -> anyhow::Result<()> {
-    let mut node: Node<u32> = Node::default();
+    if std::env::args().any(|arg| arg == "--selftest") {
+        return selftest();
+    }
+    let mut config = Config::from_env().context("loading config from environment")?;
+    if let Transport::Udp(_) = config.transport {
+        fit_to_datagrams(&mut config);
+    }
+    eprintln!("Starting with {:?}", config);
+    let max_message_bytes = config.max_message_bytes;
+    let transport = config.transport;
+    let udp_peers = config.udp_peers.clone();
+    let peer_codec = config.peer_codec;
+    // nothing but input drives tick, so reads give up after this long
+    let idle_tick = config.fanout_interval.max(Duration::from_millis(1));
+    #[cfg(feature = "metrics")]
+    let metrics_addr = config.metrics_addr;
+    let mut host: Host<u32> = Host::new(config);
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = metrics_addr {
+        host.serve_metrics(addr)?;
+    }
     let (tx, rx) = mpsc::channel::<Message>();
+    let stop = watch_for_sigterm()?;
+
+    let print_thread_handle = match transport {
+        Transport::Stdio => {
+            let print_thread_handle = spawn_writer(|| stdout().lock(), rx);
+            let lines = spawn_reader(
+                move || BoundedLines::new(stdin().lock(), max_message_bytes),
+                idle_tick,
+            )
+            .map(|line| line.map(Input::Line));
+            main_loop(lines, &mut host, &tx, max_message_bytes, &stop)?;
+            print_thread_handle
+        }
+        Transport::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).with_context(|| format!("binding {addr}"))?;
+            eprintln!("Listening on {addr}");
+            let (stream, peer) = listener.accept().context("accepting a connection")?;
+            eprintln!("Serving {peer}");
+            let writer = stream.try_clone().context("cloning the connection")?;
+            let print_thread_handle = spawn_writer(move || BufWriter::new(writer), rx);
+            let lines = spawn_reader(
+                move || BoundedLines::new(BufReader::new(stream), max_message_bytes),
+                idle_tick,
+            )
+            .map(|line| line.map(Input::Line));
+            main_loop(lines, &mut host, &tx, max_message_bytes, &stop)?;
+            print_thread_handle
+        }
+        Transport::Udp(addr) => {
+            let socket = UdpSocket::bind(addr).with_context(|| format!("binding {addr}"))?;
+            eprintln!("Listening on udp {addr}");
+            socket
+                .set_read_timeout(Some(idle_tick))
+                .context("setting the socket read timeout")?;
+            let addresses = Arc::new(Mutex::new(udp_peers));
+            let sender = socket.try_clone().context("cloning the socket")?;
+            let print_thread_handle =
+                spawn_datagram_writer(sender, addresses.clone(), peer_codec, rx);
+            main_loop(
+                datagrams(socket, addresses, max_message_bytes),
+                &mut host,
+                &tx,
+                max_message_bytes,
+                &stop,
+            )?;
+            print_thread_handle
+        }
+    };
+
+    // input closed or SIGTERM: one final resend, then let the writer drain
+    // and exit
+    let unacked = host.shutdown(tx.clone())?;
+    eprintln!("Shutting down with {} unacked outbox entries", unacked);
+    drop(tx);
+    let _ = print_thread_handle.join();
+    Ok(())
+}
+
+// The writer drops a message that doesn't fit a datagram, so sync replies are
+// capped to one, the rest follows in later rounds as with any capped reply
+#[cfg(feature = "broadcast")]
+fn fit_to_datagrams(config: &mut Config) {
+    let cap = NonZeroUsize::new(DATAGRAM_SYNC_LIMIT).unwrap_or(NonZeroUsize::MIN);
+    let limit = config.sync_reply_limit.map_or(cap, |limit| limit.min(cap));
+    config.sync_reply_limit = Some(limit);
+}
+
+// Set once the process gets a SIGTERM, main_loop checks it between lines.
+// The signal is awaited on its own thread with a small tokio runtime, the
+// rest of the binary stays synchronous.
+#[cfg(all(feature = "broadcast", unix))]
+fn watch_for_sigterm() -> anyhow::Result<Arc<AtomicBool>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .context("starting the signal runtime")?;
+    let mut sigterm = runtime
+        .block_on(async { signal(SignalKind::terminate()) })
+        .context("listening for SIGTERM")?;
+    let flag = stop.clone();
+    thread::spawn(move || {
+        if runtime.block_on(sigterm.recv()).is_some() {
+            eprintln!("Received SIGTERM, shutting down");
+            flag.store(true, Ordering::SeqCst);
+        }
+    });
+    Ok(stop)
+}
+
+// No SIGTERM to watch for, the process only stops when its input closes
+#[cfg(all(feature = "broadcast", not(unix)))]
+fn watch_for_sigterm() -> anyhow::Result<Arc<AtomicBool>> {
+    Ok(Arc::new(AtomicBool::new(false)))
+}
+
+// Reads lines on their own thread so a blocked read can't keep main_loop
+// from ticking or from noticing a SIGTERM. Yields an empty line whenever
+// nothing arrived for `idle`, as datagrams does on a read timeout, and ends
+// when the input does. `open` runs on the reader thread, like spawn_writer's.
+#[cfg(feature = "broadcast")]
+fn spawn_reader<I, F>(open: F, idle: Duration) -> impl Iterator<Item = anyhow::Result<String>>
+where
+    I: Iterator<Item = anyhow::Result<String>>,
+    F: FnOnce() -> I + Send + 'static,
+{
+    let (lines_tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in open() {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    std::iter::from_fn(move || match lines.recv_timeout(idle) {
+        Ok(line) => Some(line),
+        Err(mpsc::RecvTimeoutError::Timeout) => Some(Ok(String::new())),
+        Err(mpsc::RecvTimeoutError::Disconnected) => None,
+    })
+}
 
-    let print_thread_handle = thread::spawn(move || {
-        let mut stdout = stdout().lock();
+// One item of input for main_loop. Lines are decoded there so big syncs can
+// be streamed, datagrams in a binary peer codec arrive already decoded.
+#[cfg(feature = "broadcast")]
+enum Input {
+    Line(String),
+    #[cfg(feature = "msgpack")]
+    Message(Message),
+}
+
+// Writes everything sent on `rx` as JSON lines, highest priority first and
+// flushed whenever the queue runs dry. `open` runs on the writer thread so
+// it can hand back a lock that isn't Send, like stdout's.
+#[cfg(feature = "broadcast")]
+fn spawn_writer<W, F>(open: F, rx: mpsc::Receiver<Message>) -> thread::JoinHandle<()>
+where
+    W: Write,
+    F: FnOnce() -> W + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut out = open();
+        let mut queue = OutboundQueue::new();
         while let Ok(message) = rx.recv() {
-            eprintln!("Sending: src={}, dest={}", message.src, message.dest);
-            let _ = serde_json::to_writer(&mut stdout, &message).context("serializing response");
-            let _ = stdout
-                .write_all(b"\n")
-                .context("write trailing newline")
-                .context("Couldn't write to stdout");
+            queue.push(message);
+            queue.fill_from(&rx);
+            while let Some(message) = queue.pop() {
+                eprintln!("Sending: src={}, dest={}", message.src, message.dest);
+                let bytes = match JsonCodec.encode(&message) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        eprintln!("Failed to serialize message for {}: {:#}", message.dest, e);
+                        continue;
+                    }
+                };
+                let _ = out
+                    .write_all(&bytes)
+                    .and_then(|_| out.write_all(b"\n"))
+                    .context("Couldn't write output");
+            }
+            let _ = out.flush();
+        }
+    })
+}
+
+// Incoming datagrams, each one a whole message. JSON ones are handed on as
+// lines, MessagePack ones (see PeerCodec::detect) are decoded here. The
+// sender's address is remembered under the message's `src` so replies find
+// their way back, but only for a node we have no address for yet or a
+// client: otherwise any datagram claiming to be from a peer would redirect
+// all of its traffic. Datagrams in neither codec, or that aren't UTF-8 or
+// have no `src`, are dropped here. Ones over max_bytes come back as an
+// OversizedMessage. A read that times out yields an empty line, main_loop
+// ticks on it and moves on.
+#[cfg(feature = "broadcast")]
+fn datagrams(
+    socket: UdpSocket,
+    addresses: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    max_bytes: usize,
+) -> impl Iterator<Item = anyhow::Result<Input>> {
+    #[derive(serde::Deserialize)]
+    struct Source {
+        src: NodeId,
+    }
+
+    let mut buf = vec![0; MAX_DATAGRAM_BYTES];
+    std::iter::from_fn(move || loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Some(Ok(Input::Line(String::new())))
+            }
+            Err(e) => return Some(Err(e.into())),
+        };
+        let remember = |src: &NodeId| {
+            if let Ok(mut addresses) = addresses.lock() {
+                if src.is_client() || !addresses.contains_key(src) {
+                    addresses.insert(src.clone(), from);
+                }
+            }
+        };
+        if len > max_bytes {
+            return Some(Err(OversizedMessage {
+                len,
+                max: max_bytes,
+            }
+            .into()));
+        }
+        match PeerCodec::detect(&buf[..len]) {
+            Some(PeerCodec::Json) => {}
+            None => {
+                eprintln!("Dropping datagram from {from}: neither JSON nor a peer codec");
+                continue;
+            }
+            #[cfg(feature = "msgpack")]
+            Some(codec) => match codec.decode(&buf[..len]) {
+                Ok(message) => {
+                    remember(&message.src);
+                    return Some(Ok(Input::Message(message)));
+                }
+                Err(e) => {
+                    eprintln!("Dropping datagram from {from}: {e:#}");
+                    continue;
+                }
+            },
+        }
+        let Ok(input) = std::str::from_utf8(&buf[..len]) else {
+            eprintln!("Dropping datagram from {from}: not UTF-8");
+            continue;
+        };
+        match serde_json::from_str::<Source>(input) {
+            Ok(source) => {
+                remember(&source.src);
+                return Some(Ok(Input::Line(input.to_owned())));
+            }
+            Err(e) => eprintln!("Dropping datagram from {from}: {e}"),
+        }
+    })
+}
+
+// Sends each message as one datagram to the last known address of its dest,
+// encoded with `codec` for other nodes and as JSON for clients. Messages that
+// don't fit a datagram, or are for a node we have no address
+// for, are dropped with a warning, retries and syncs deal with the loss.
+#[cfg(feature = "broadcast")]
+fn spawn_datagram_writer(
+    socket: UdpSocket,
+    addresses: Arc<Mutex<HashMap<NodeId, SocketAddr>>>,
+    codec: PeerCodec,
+    rx: mpsc::Receiver<Message>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut queue = OutboundQueue::new();
+        while let Ok(message) = rx.recv() {
+            queue.push(message);
+            queue.fill_from(&rx);
+            while let Some(message) = queue.pop() {
+                let encoded = if message.dest.is_client() {
+                    JsonCodec.encode(&message)
+                } else {
+                    codec.encode(&message)
+                };
+                let bytes = match encoded {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        eprintln!("Failed to serialize message for {}: {:#}", message.dest, e);
+                        continue;
+                    }
+                };
+                if bytes.len() > MAX_DATAGRAM_BYTES {
+                    eprintln!(
+                        "Dropping {} for {}: {} bytes exceeds the datagram limit of {}",
+                        message.body.type_name(),
+                        message.dest,
+                        bytes.len(),
+                        MAX_DATAGRAM_BYTES
+                    );
+                    continue;
+                }
+                let to = addresses
+                    .lock()
+                    .ok()
+                    .and_then(|addresses| addresses.get(&message.dest).copied());
+                let Some(to) = to else {
+                    eprintln!("Dropping message for {}: no known address", message.dest);
+                    continue;
+                };
+                eprintln!("Sending: src={}, dest={}", message.src, message.dest);
+                if let Err(e) = socket.send_to(&bytes, to) {
+                    eprintln!("Failed to send to {} at {}: {}", message.dest, to, e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(feature = "broadcast")]
+fn selftest() -> anyhow::Result<()> {
+    let mut failed = 0;
+    for (workload, outcome) in selftest::run_all() {
+        match outcome {
+            selftest::Outcome::Pass => println!("PASS {workload}"),
+            selftest::Outcome::Skipped(reason) => println!("SKIP {workload}: {reason}"),
+            selftest::Outcome::Fail(e) => {
+                failed += 1;
+                println!("FAIL {workload}: {e:#}");
+            }
+        }
+    }
+    anyhow::ensure!(failed == 0, "{failed} workload(s) failed");
+    Ok(())
+}
+
+// Build with `--no-default-features --features lin-kv`
+#[cfg(all(feature = "lin-kv", not(feature = "broadcast")))]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::{stdin, stdout, BufRead, Write};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut node = kv_node::KvNode::new();
+    let (tx, rx) = mpsc::channel();
+    // stdin is read on its own thread so a forward whose owner never answers
+    // still times out while no client is talking to us
+    let (lines_tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
         }
     });
-    let lines = stdin().lock().lines();
-    main_loop(lines, &mut node, &tx, true)?;
+    let tick = (node.rpc_timeout / 10).max(Duration::from_millis(1));
+    let mut stdout = stdout().lock();
+    loop {
+        node.tick(tx.clone())?;
+        match lines.recv_timeout(tick) {
+            Ok(line) => {
+                let input = line.context("reading line from STDIN")?;
+                match serde_json::from_str::<kv_node::Message>(&input) {
+                    Ok(msg) => {
+                        if let Err(e) = node.next(msg, tx.clone()) {
+                            eprintln!("Failed to handle message: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Dropping input: {}", e),
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        for reply in rx.try_iter() {
+            serde_json::to_writer(&mut stdout, &reply).context("serializing response")?;
+            stdout.write_all(b"\n").context("write trailing newline")?;
+        }
+    }
+    Ok(())
+}
 
-    //trigger one final resend
-    node.fanout_messages(tx.clone())?;
-    node.retry_messages(tx.clone())?;
+// Build with `--no-default-features --features g-counter`
+#[cfg(all(
+    feature = "g-counter",
+    not(any(feature = "broadcast", feature = "lin-kv"))
+))]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::{stdin, stdout, BufRead, Write};
+    use std::sync::mpsc;
+    use std::thread;
 
-    let lines = stdin().lock().lines();
-    main_loop(lines, &mut node, &tx, false)?;
-    let _ = print_thread_handle.join();
+    let mut node = g_counter_node::GCounterNode::new();
+    let (tx, rx) = mpsc::channel();
+    // stdin is read on its own thread so gossip still goes out while no
+    // client is talking to us
+    let (lines_tx, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut stdout = stdout().lock();
+    loop {
+        node.tick(tx.clone())?;
+        match lines.recv_timeout(node.gossip_interval) {
+            Ok(line) => {
+                let input = line.context("reading line from STDIN")?;
+                let msg: g_counter_node::Message =
+                    serde_json::from_str(&input).context("deserializing g-counter message")?;
+                if let Err(e) = node.next(msg, tx.clone()) {
+                    eprintln!("Failed to handle message: {}", e);
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        for reply in rx.try_iter() {
+            serde_json::to_writer(&mut stdout, &reply).context("serializing response")?;
+            stdout.write_all(b"\n").context("write trailing newline")?;
+        }
+    }
     Ok(())
 }
 
-#[cfg(not(feature = "broadcast"))]
+// Build with `--no-default-features --features g-set`
+#[cfg(all(
+    feature = "g-set",
+    not(any(feature = "broadcast", feature = "lin-kv", feature = "g-counter"))
+))]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use std::io::{stdin, stdout, Write};
+    use std::sync::mpsc;
+
+    let config = g_set_node::Config::from_env().context("loading config from environment")?;
+    let max_message_bytes = config.max_message_bytes;
+    let mut node = g_set_node::GSetNode::new(config);
+    let (tx, rx) = mpsc::channel();
+    let mut stdout = stdout().lock();
+    for line in g_set_node::BoundedLines::new(stdin().lock(), max_message_bytes) {
+        node.tick(tx.clone())?;
+        let input = match line {
+            Ok(input) => input,
+            Err(e) if e.is::<g_set_node::OversizedMessage>() => {
+                eprintln!("Dropping input: {}", e);
+                continue;
+            }
+            Err(e) => return Err(e.context("reading line from STDIN")),
+        };
+        match g_set_node::decode_line(&input, max_message_bytes) {
+            Ok(msg) => {
+                if let Err(e) = node.next(msg, tx.clone()) {
+                    eprintln!("Failed to handle message: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Dropping input: {:#}", e),
+        }
+        for reply in rx.try_iter() {
+            serde_json::to_writer(&mut stdout, &reply).context("serializing response")?;
+            stdout.write_all(b"\n").context("write trailing newline")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    feature = "broadcast",
+    feature = "lin-kv",
+    feature = "g-counter",
+    feature = "g-set"
+)))]
 fn main() -> anyhow::Result<()> {
     anyhow::bail!("no workload feature selected")
 }
 
 #[cfg(feature = "broadcast")]
 fn main_loop(
-    stdin: Lines<std::io::StdinLock<'_>>,
-    node: &mut Node<u32>,
+    lines: impl Iterator<Item = anyhow::Result<Input>>,
+    host: &mut Host<u32>,
     tx: &mpsc::Sender<Message>,
-    run_periodic: bool,
+    max_message_bytes: usize,
+    stop: &AtomicBool,
 ) -> anyhow::Result<()> {
-    let fanout_interval = Duration::from_millis(50);
-    let retry_interval = Duration::from_millis(300);
-    let mut last_fanout = Instant::now();
-    let mut last_retry = Instant::now();
-
-    for (_msg_num, line) in stdin.enumerate() {
-        if run_periodic {
-            if last_fanout.elapsed() >= fanout_interval {
-                node.fanout_messages(tx.clone())?;
-                last_fanout = Instant::now();
-            }
-
-            if last_retry.elapsed() >= retry_interval {
-                node.retry_messages(tx.clone())?;
-                last_retry = Instant::now();
-            }
+    for line in lines {
+        if stop.load(Ordering::SeqCst) {
+            break;
         }
+        host.tick(tx.clone())?;
 
         let input = match line {
-            Ok(l) => {
-                eprintln!("Received line: '{}'", l);
-                l
+            Ok(Input::Line(input)) => input,
+            #[cfg(feature = "msgpack")]
+            Ok(Input::Message(message)) => {
+                eprintln!("Received {} from {}", message.body.type_name(), message.src);
+                match host.route(message, tx.clone()) {
+                    Ok(_) => eprintln!("Message handled successfully"),
+                    Err(e) => eprintln!("Failed to handle message: {}", e),
+                }
+                continue;
+            }
+            Err(e) if e.is::<OversizedMessage>() => {
+                eprintln!("Dropping input: {}", e);
+                continue;
             }
-            Err(e) => panic!("{e}"),
+            Err(e) => return Err(e.context("reading input line")),
         };
-        let deser = &mut serde_json::Deserializer::from_str(&input);
-        let result = deserialize(deser);
-        let input = match result {
+        if input.is_empty() {
+            continue;
+        }
+        eprintln!("Received line: '{}'", log_prefix(&input));
+        match host.try_stream(&input, tx.clone()) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("Failed to stream message: {:#}", e);
+                continue;
+            }
+        }
+        let input = match decode_line(&input, max_message_bytes) {
             Ok(msg) => msg,
             Err(e) => {
-                eprintln!("Deserialization failed: {}", e);
-                return Err(e).context("Failed to deserialize STDIN input from Maelstrom");
+                eprintln!("Dropping input: {:#}", e);
+                continue;
             }
         };
-        match node.next(input, tx.clone()) {
+        match host.route(input, tx.clone()) {
             Ok(_) => eprintln!("Message handled successfully"),
             Err(e) => eprintln!("Failed to handle message: {}", e),
         }
     }
     Ok(())
 }
+
+// At most LOG_PREFIX_CHARS of a line, a whole sync can run to megabytes
+#[cfg(feature = "broadcast")]
+fn log_prefix(line: &str) -> std::borrow::Cow<'_, str> {
+    const LOG_PREFIX_CHARS: usize = 200;
+    match line.char_indices().nth(LOG_PREFIX_CHARS) {
+        Some((end, _)) => format!("{}... ({} bytes)", &line[..end], line.len()).into(),
+        None => line.into(),
+    }
+}
+
+#[cfg(all(test, feature = "broadcast"))]
+mod tests {
+    use super::*;
+    use broadcast_node::MessageBody;
+    use std::time::Instant;
+
+    fn send(socket: &UdpSocket, to: SocketAddr, line: &str) {
+        socket.send_to(line.as_bytes(), to).unwrap();
+    }
+
+    // The next message for `socket`, None once nothing arrives for a while
+    fn recv(socket: &UdpSocket) -> Option<Message> {
+        let mut buf = vec![0; MAX_DATAGRAM_BYTES];
+        let (len, _) = socket.recv_from(&mut buf).ok()?;
+        Some(serde_json::from_slice(&buf[..len]).unwrap())
+    }
+
+    fn socket() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        socket
+    }
+
+    // A node serving `socket` the way main does, until `stop` is set
+    fn serve(
+        socket: UdpSocket,
+        peers: HashMap<NodeId, SocketAddr>,
+        codec: PeerCodec,
+        stop: Arc<AtomicBool>,
+    ) -> thread::JoinHandle<()> {
+        socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        thread::spawn(move || {
+            let mut config = Config {
+                fanout_interval: Duration::from_millis(10),
+                ..Config::default()
+            };
+            fit_to_datagrams(&mut config);
+            let mut host: Host<u32> = Host::new(config);
+            let (tx, rx) = mpsc::channel();
+            let addresses = Arc::new(Mutex::new(peers));
+            let writer =
+                spawn_datagram_writer(socket.try_clone().unwrap(), addresses.clone(), codec, rx);
+            let lines = datagrams(socket, addresses, MAX_DATAGRAM_BYTES);
+            main_loop(lines, &mut host, &tx, MAX_DATAGRAM_BYTES, &stop).unwrap();
+            drop(tx);
+            writer.join().unwrap();
+        })
+    }
+
+    #[test]
+    fn two_nodes_gossip_over_loopback_udp() {
+        let (n1, n2) = (socket(), socket());
+        let (addr1, addr2) = (n1.local_addr().unwrap(), n2.local_addr().unwrap());
+        let stop = Arc::new(AtomicBool::new(false));
+        let nodes = [
+            serve(
+                n1,
+                HashMap::from([("n2".into(), addr2)]),
+                PeerCodec::Json,
+                stop.clone(),
+            ),
+            serve(
+                n2,
+                HashMap::from([("n1".into(), addr1)]),
+                PeerCodec::Json,
+                stop.clone(),
+            ),
+        ];
+        let client = socket();
+        for (id, addr) in [("n1", addr1), ("n2", addr2)] {
+            let init = format!(
+                r#"{{"src":"c1","dest":"{id}","body":{{"type":"init","msg_id":1,"node_id":"{id}","node_ids":["n1","n2"]}}}}"#
+            );
+            send(&client, addr, &init);
+            assert!(matches!(
+                recv(&client).unwrap().body,
+                MessageBody::init_ok { .. }
+            ));
+        }
+
+        // a datagram claiming to be n1 from elsewhere doesn't take over its
+        // address, the pong goes to the real n1
+        let rogue = socket();
+        send(
+            &rogue,
+            addr2,
+            r#"{"src":"n1","dest":"n2","body":{"type":"ping","msg_id":9}}"#,
+        );
+        assert!(recv(&rogue).is_none());
+
+        send(
+            &client,
+            addr1,
+            r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":2,"message":7}}"#,
+        );
+        assert!(matches!(
+            recv(&client).unwrap().body,
+            MessageBody::broadcast_ok { .. }
+        ));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = false;
+        while !seen && Instant::now() < deadline {
+            send(
+                &client,
+                addr2,
+                r#"{"src":"c1","dest":"n2","body":{"type":"read","msg_id":3}}"#,
+            );
+            seen = matches!(
+                recv(&client).map(|m| m.body),
+                Some(MessageBody::read_ok { messages, .. }) if messages == vec![7]
+            );
+        }
+        stop.store(true, Ordering::SeqCst);
+        for node in nodes {
+            node.join().unwrap();
+        }
+        assert!(seen, "n2 never heard of the broadcast");
+    }
+
+    // n1 gossips to a fake n2 in MessagePack and answers its MessagePack
+    // ping in kind, while the client still gets JSON
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn peers_exchange_msgpack_datagrams() {
+        use broadcast_node::MsgPackCodec;
+
+        let (n1, n2) = (socket(), socket());
+        let (addr1, addr2) = (n1.local_addr().unwrap(), n2.local_addr().unwrap());
+        let stop = Arc::new(AtomicBool::new(false));
+        let node = serve(
+            n1,
+            HashMap::from([("n2".into(), addr2)]),
+            PeerCodec::MsgPack,
+            stop.clone(),
+        );
+        let client = socket();
+        send(
+            &client,
+            addr1,
+            r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2"]}}"#,
+        );
+        assert!(matches!(
+            recv(&client).unwrap().body,
+            MessageBody::init_ok { .. }
+        ));
+        send(
+            &client,
+            addr1,
+            r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":2,"message":7}}"#,
+        );
+        assert!(matches!(
+            recv(&client).unwrap().body,
+            MessageBody::broadcast_ok { .. }
+        ));
+
+        let mut buf = vec![0; MAX_DATAGRAM_BYTES];
+        let (len, _) = n2.recv_from(&mut buf).unwrap();
+        assert_eq!(PeerCodec::detect(&buf[..len]), Some(PeerCodec::MsgPack));
+        MsgPackCodec.decode(&buf[..len]).unwrap();
+
+        let ping = Message {
+            src: "n2".into(),
+            dest: "n1".into(),
+            body: MessageBody::ping { msg_id: 9 },
+        };
+        n2.send_to(&MsgPackCodec.encode(&ping).unwrap(), addr1)
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut pong = false;
+        while !pong && Instant::now() < deadline {
+            let Ok((len, _)) = n2.recv_from(&mut buf) else {
+                break;
+            };
+            pong = matches!(
+                MsgPackCodec.decode(&buf[..len]).map(|m| m.body),
+                Ok(MessageBody::pong { .. })
+            );
+        }
+        stop.store(true, Ordering::SeqCst);
+        node.join().unwrap();
+        assert!(pong, "n1 never answered the MessagePack ping");
+    }
+
+    // An oversized line and one that isn't a message are both dropped, the
+    // node answers the read after them and main_loop only ends with the input
+    #[test]
+    fn bad_lines_are_dropped_and_the_node_keeps_serving() {
+        let mut host: Host<u32> = Host::new(Config::default());
+        let (tx, rx) = mpsc::channel();
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+        let oversized = format!(
+            r#"{{"src":"c1","dest":"n1","body":{{"type":"echo","msg_id":2,"echo":"{}"}}}}"#,
+            "x".repeat(512)
+        );
+        let read = r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#;
+        let lines = [init, &oversized, "not json", read]
+            .into_iter()
+            .map(|line| Ok(Input::Line(line.to_owned())));
+        main_loop(lines, &mut host, &tx, 256, &AtomicBool::new(false)).unwrap();
+
+        let replies: Vec<MessageBody> = rx.try_iter().map(|m| m.body).collect();
+        assert!(matches!(replies[0], MessageBody::init_ok { .. }));
+        assert!(matches!(
+            replies[1],
+            MessageBody::read_ok { in_reply_to: 3, .. }
+        ));
+        assert_eq!(replies.len(), 2);
+    }
+
+    #[test]
+    fn sync_replies_fit_a_datagram() {
+        let mut config = Config::default();
+        fit_to_datagrams(&mut config);
+        let mut host: Host<u32> = Host::new(config);
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2"]}}"#;
+        let mut messages = vec![decode_line(init, MAX_DATAGRAM_BYTES).unwrap()];
+        for i in 0..2 * DATAGRAM_SYNC_LIMIT as u32 {
+            let broadcast = format!(
+                r#"{{"src":"c1","dest":"n1","body":{{"type":"broadcast","msg_id":{i},"message":{}}}}}"#,
+                u32::MAX - i
+            );
+            messages.push(decode_line(&broadcast, MAX_DATAGRAM_BYTES).unwrap());
+        }
+        host.process_batch(messages);
+
+        let pull = r#"{"src":"n2","dest":"n1","body":{"type":"sync","msg_id":5,"messages":[]}}"#;
+        let batch = host.process_batch([decode_line(pull, MAX_DATAGRAM_BYTES).unwrap()]);
+        let reply = batch
+            .sent
+            .iter()
+            .find(|m| matches!(m.body, MessageBody::sync_ok { .. }))
+            .unwrap();
+        assert!(matches!(
+            reply.body,
+            MessageBody::sync_ok { more: true, .. }
+        ));
+        assert!(serde_json::to_vec(reply).unwrap().len() <= MAX_DATAGRAM_BYTES);
+    }
+}
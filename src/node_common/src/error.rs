@@ -0,0 +1,102 @@
+use std::fmt;
+
+// Maelstrom's standard error codes, see
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            _ => return None,
+        })
+    }
+}
+
+// A failure a handler wants the requester to see, with the code to report.
+// Return it through anyhow like any other error; whoever answers the request
+// finds it with RpcError::find and replies with its code and text, anything
+// else goes no further than the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+impl RpcError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+
+    pub fn not_supported(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotSupported, text)
+    }
+
+    pub fn temporarily_unavailable(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::TemporarilyUnavailable, text)
+    }
+
+    pub fn malformed_request(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::MalformedRequest, text)
+    }
+
+    pub fn timeout(text: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Timeout, text)
+    }
+
+    // The RpcError anywhere in the chain, so context added on the way up
+    // doesn't hide the code
+    pub fn find(error: &anyhow::Error) -> Option<&RpcError> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (error {})", self.text, self.code.code())
+    }
+}
+
+impl std::error::Error for RpcError {}
@@ -1,3 +1,15 @@
+mod clock;
+mod error;
+mod node_id;
+mod outbound;
+mod rpc;
+
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::error::{ErrorCode, RpcError};
+pub use crate::node_id::{canonical_members, NodeId};
+pub use crate::outbound::{OutboundQueue, Prioritized, Priority};
+pub use crate::rpc::PendingRpcs;
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::mpsc::Sender;
@@ -5,8 +17,8 @@ use std::sync::mpsc::Sender;
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Message<Body> {
-    pub src: String,
-    pub dest: String,
+    pub src: NodeId,
+    pub dest: NodeId,
     pub body: Body,
 }
 
@@ -27,10 +39,10 @@ impl<Body> Message<Body> {
         }
     }
 
-    pub fn into_message(self, payload: Body, new_dest: &str) -> Self {
+    pub fn into_message(self, payload: Body, new_dest: NodeId) -> Self {
         Self {
             src: self.dest,
-            dest: new_dest.to_owned(),
+            dest: new_dest,
             body: payload,
         }
     }
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+
+use crate::Message;
+
+// Ordering matters here, higher variants are written first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    // bulk anti-entropy traffic, sync and gossip retries
+    Low,
+    // fresh broadcasts and client requests
+    Normal,
+    // control replies and acks
+    High,
+}
+
+pub trait Prioritized {
+    fn priority(&self) -> Priority;
+}
+
+impl<Body: Prioritized> Prioritized for Message<Body> {
+    fn priority(&self) -> Priority {
+        self.body.priority()
+    }
+}
+
+// Buffers everything the writer has received so far and hands it back highest
+// priority first, FIFO within the same priority.
+#[derive(Debug)]
+pub struct OutboundQueue<M> {
+    high: VecDeque<M>,
+    normal: VecDeque<M>,
+    low: VecDeque<M>,
+}
+
+impl<M> Default for OutboundQueue<M> {
+    fn default() -> Self {
+        Self {
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+            low: VecDeque::new(),
+        }
+    }
+}
+
+impl<M: Prioritized> OutboundQueue<M> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, message: M) {
+        match message.priority() {
+            Priority::High => self.high.push_back(message),
+            Priority::Normal => self.normal.push_back(message),
+            Priority::Low => self.low.push_back(message),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<M> {
+        self.high
+            .pop_front()
+            .or_else(|| self.normal.pop_front())
+            .or_else(|| self.low.pop_front())
+    }
+
+    // Pulls whatever is already waiting on the channel without blocking, so a
+    // reply queued behind a large batch still gets to jump ahead of it.
+    pub fn fill_from(&mut self, rx: &Receiver<M>) {
+        for message in rx.try_iter() {
+            self.push(message);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.high.len() + self.normal.len() + self.low.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+// Requests we sent and are still waiting on, keyed by the msg_id the reply
+// will carry as in_reply_to. `T` holds whatever the caller needs to finish
+// the request once the reply (or the deadline) arrives.
+#[derive(Debug, Clone)]
+pub struct PendingRpcs<T> {
+    pending: HashMap<u32, (T, Instant)>,
+}
+
+impl<T> Default for PendingRpcs<T> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PendingRpcs<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, msg_id: u32, state: T, deadline: Instant) {
+        self.pending.insert(msg_id, (state, deadline));
+    }
+
+    pub fn get_mut(&mut self, in_reply_to: u32) -> Option<&mut T> {
+        self.pending.get_mut(&in_reply_to).map(|(state, _)| state)
+    }
+
+    pub fn remove(&mut self, in_reply_to: u32) -> Option<T> {
+        self.pending.remove(&in_reply_to).map(|(state, _)| state)
+    }
+
+    pub fn contains(&self, in_reply_to: u32) -> bool {
+        self.pending.contains_key(&in_reply_to)
+    }
+
+    // Removes and returns everything whose deadline is at or before `now`
+    pub fn take_expired(&mut self, now: Instant) -> Vec<(u32, T)> {
+        let expired: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|msg_id| self.remove(msg_id).map(|state| (msg_id, state)))
+            .collect()
+    }
+
+    // Removes and returns everything `ready` accepts, whatever its deadline
+    pub fn take_where(&mut self, mut ready: impl FnMut(&T) -> bool) -> Vec<(u32, T)> {
+        let taken: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, (state, _))| ready(state))
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        taken
+            .into_iter()
+            .filter_map(|msg_id| self.remove(msg_id).map(|state| (msg_id, state)))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}